@@ -0,0 +1,494 @@
+// Controller automation recording
+// Writes controller changes made during playback into patterns of the project
+//
+// Recorded changes go to dedicated "Automation" patterns, one track per
+// (module, controller) pair, so existing patterns are never touched. The
+// fraction of a line is kept with the 0x41..0x5F delay effect, and the result
+// plays back in standalone SunVox like hand-written automation.
+
+use crate::error::Result;
+use crate::module_graph::module_exists;
+use crate::patterns::{
+    new_pattern, pattern_exists, pattern_info, patterns, set_pattern_event, set_pattern_size,
+};
+use crate::project::{song_bpm, song_tpl};
+use crate::slot::SlotLock;
+use crate::sunvox_ffi::*;
+use std::collections::BTreeMap;
+
+/// Pattern effect 0x41..0x5F: delay the event by 1..31 32nds of a line
+pub const EFFECT_DELAY: u16 = 0x40;
+
+/// Maximum number of tracks in a pattern, and so of recorded lanes
+const MAX_LANES: usize = 32;
+
+/// Playback position with sub-line precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LinePosition {
+    pub line: i32,
+    /// 32nds of a line (0..31)
+    pub fraction: u8,
+}
+
+impl LinePosition {
+    /// Decode the 27.5 fixed point format of `sv_get_current_line2`
+    pub fn from_fixed(value: i32) -> Self {
+        Self {
+            line: value >> 5,
+            fraction: (value & 31) as u8,
+        }
+    }
+}
+
+/// Line being played, as heard at the current system time
+///
+/// SunVox evaluates this against `sv_get_ticks`, so it follows the blocks
+/// rendered during realtime playback; when rendering faster than realtime it
+/// lags behind.
+pub fn current_position(slot: i32) -> LinePosition {
+    LinePosition::from_fixed(unsafe { sv_get_current_line2(slot) })
+}
+
+pub fn is_playing(slot: i32) -> bool {
+    unsafe { sv_end_of_song(slot) == 0 }
+}
+
+/// Lines `current_position` may trail the start of a block by: a fixed
+/// margin plus a number of block lengths, as the engine reports the line in
+/// steps of its render chunks
+const TRAIL_LINES: f64 = 1.0;
+const TRAIL_BLOCKS: f64 = 4.0;
+
+/// Song position of the frames of a block, counted on the output frame clock
+///
+/// `current_position` trails the rendered audio by up to a few lines,
+/// depending on the block size, so the playhead counts the rendered frames
+/// instead: a frame offset maps to the line at the start of the block plus
+/// the offset converted through the song's BPM and TPL. The count starts
+/// from the engine's line when playback starts, and since that line never
+/// leads the audio, moves forward to it whenever it is further along (a jump
+/// ahead, or a speed change the count missed). A line further back than the
+/// engine can trail is a jump back or a loop, and restarts the count there;
+/// until the engine's line catches up, the count may then trail the audio by
+/// as much as it does. Jumps announced by the caller, at once or when a line
+/// ends, restart the count exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Playhead {
+    /// Line at the start of the next block, while playing
+    line: Option<f64>,
+    frames_per_line: f64,
+    /// Frames of the last block
+    block: u32,
+    /// Frames to render before the engine's line shows the last `jump`
+    settling: u64,
+    /// Line at which the song jumps, and the line it jumps to
+    pending_jump: Option<(f64, f64)>,
+    sample_rate: u32,
+}
+
+impl Playhead {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate.max(1);
+        Self {
+            line: None,
+            // SunVox's default speed, 125 BPM and 6 ticks per line
+            frames_per_line: sample_rate as f64 * 60.0 * 6.0 / (125.0 * 24.0),
+            block: 0,
+            settling: 0,
+            pending_jump: None,
+            sample_rate,
+        }
+    }
+
+    /// Follow the engine's transport before a block is rendered
+    pub fn start_block(&mut self, slot: i32) {
+        if !is_playing(slot) {
+            self.line = None;
+            self.settling = 0;
+            self.pending_jump = None;
+            return;
+        }
+        let bpm = song_bpm(slot).max(1) as f64;
+        let tpl = song_tpl(slot).max(1) as f64;
+        self.frames_per_line = self.sample_rate as f64 * 60.0 * tpl / (bpm * 24.0);
+
+        if self.settling > 0 {
+            return;
+        }
+        // The line is -1 until the engine has started playing
+        let reported = (unsafe { sv_get_current_line2(slot) } as f64 / 32.0).max(0.0);
+        let trail = TRAIL_LINES + TRAIL_BLOCKS * self.block as f64 / self.frames_per_line;
+        self.line = match self.line {
+            Some(line) if reported >= line - trail => Some(line.max(reported)),
+            _ => Some(reported),
+        };
+    }
+
+    /// Position `offset` frames into the block, or `None` while stopped
+    pub fn position_at(&self, offset: u32) -> Option<LinePosition> {
+        let line = self.after_jump(self.line? + offset as f64 / self.frames_per_line);
+        let fixed = (line * 32.0 + 1e-6).floor() as i32;
+        Some(LinePosition::from_fixed(fixed))
+    }
+
+    /// Restart the count at `line`, after a `sv_rewind` to it that takes
+    /// effect with the next block (and a `sv_play`, if stopped)
+    ///
+    /// The engine's line is ignored until it can no longer show where the
+    /// song was before the jump.
+    pub fn jump(&mut self, line: i32) {
+        self.line = Some(line.max(0) as f64);
+        self.pending_jump = None;
+        self.settle();
+    }
+
+    /// Jump to `line` when the line playing `offset` frames into the block
+    /// ends, as a 0x31 effect sent at that frame does
+    pub fn jump_after_line(&mut self, offset: u32, line: i32) {
+        if let Some(position) = self.position_at(offset) {
+            self.pending_jump = Some(((position.line + 1) as f64, line.max(0) as f64));
+        }
+    }
+
+    /// Whether a `jump_after_line` has yet to happen
+    pub fn is_jump_pending(&self) -> bool {
+        self.pending_jump.is_some()
+    }
+
+    /// Frames from the start of the block until the song reaches `line`: 0 if
+    /// it is past it already, `None` while stopped
+    pub fn frames_until(&self, line: f64) -> Option<u32> {
+        let lines = (line - self.line?).max(0.0);
+        Some((lines * self.frames_per_line).ceil() as u32)
+    }
+
+    /// Move past a rendered block of `frames`
+    pub fn advance(&mut self, frames: u32) {
+        self.block = frames;
+        self.settling = self.settling.saturating_sub(frames as u64);
+        let Some(line) = self.line else {
+            return;
+        };
+        let line = line + frames as f64 / self.frames_per_line;
+        self.line = Some(self.after_jump(line));
+        if self
+            .pending_jump
+            .take_if(|&mut (at, _)| line >= at)
+            .is_some()
+        {
+            self.settle();
+        }
+    }
+
+    /// Where the song is at `line` of the count, once the pending jump is
+    /// taken into account
+    fn after_jump(&self, line: f64) -> f64 {
+        match self.pending_jump {
+            Some((at, to)) if line >= at => to + line - at,
+            _ => line,
+        }
+    }
+
+    /// Ignore the engine's line until it can show where the song went
+    fn settle(&mut self) {
+        let trail = TRAIL_LINES * self.frames_per_line + TRAIL_BLOCKS * self.block as f64;
+        self.settling = trail.ceil() as u64;
+    }
+}
+
+/// Records controller changes into patterns while enabled
+#[derive(Debug, Clone)]
+pub struct AutomationRecorder {
+    enabled: bool,
+    /// Length of each automation pattern
+    pattern_lines: i32,
+    /// (module, controller) recorded on each track
+    lanes: Vec<(i32, i32)>,
+    /// Automation patterns by start line
+    patterns: BTreeMap<i32, i32>,
+    /// Timeline row of the automation patterns
+    y: Option<i32>,
+}
+
+impl Default for AutomationRecorder {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl AutomationRecorder {
+    pub fn new(pattern_lines: i32) -> Self {
+        Self {
+            enabled: false,
+            pattern_lines: pattern_lines.max(1),
+            lanes: Vec::new(),
+            patterns: BTreeMap::new(),
+            y: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Forget the patterns and lanes, e.g. after another project was loaded
+    pub fn reset(&mut self) {
+        self.lanes.clear();
+        self.patterns.clear();
+        self.y = None;
+    }
+
+    /// Record a controller change made `offset` frames into the block being
+    /// rendered
+    ///
+    /// `value` is in the pattern format (0x0000..0x8000, or the real value
+    /// for selectors). Returns whether anything was written: nothing is
+    /// recorded while disabled or stopped, or once all 32 lanes are in use.
+    pub fn record(
+        &mut self,
+        slot: i32,
+        playhead: &Playhead,
+        offset: u32,
+        module: i32,
+        ctl: i32,
+        value: u16,
+    ) -> Result<bool> {
+        if !self.enabled {
+            return Ok(false);
+        }
+        let Some(position) = playhead.position_at(offset) else {
+            return Ok(false);
+        };
+        self.record_at(slot, position, module, ctl, value)
+    }
+
+    /// Record a controller change at `position`, whether enabled or not
+    pub fn record_at(
+        &mut self,
+        slot: i32,
+        position: LinePosition,
+        module: i32,
+        ctl: i32,
+        value: u16,
+    ) -> Result<bool> {
+        if position.line < 0 || !module_exists(slot, module) {
+            return Ok(false);
+        }
+        let Some(track) = self.lane(slot, module, ctl)? else {
+            return Ok(false);
+        };
+        let (pattern, x) = self.pattern_at(slot, position.line)?;
+
+        let effect = match position.fraction & 31 {
+            0 => 0,
+            fraction => EFFECT_DELAY + fraction as u16,
+        };
+        let event = SunvoxNote {
+            module: (module + 1) as u16,
+            ctl: (((ctl + 1) as u16) << 8) | effect,
+            ctl_val: value,
+            ..Default::default()
+        };
+        set_pattern_event(slot, pattern, track as i32, position.line - x, event)?;
+        Ok(true)
+    }
+
+    /// Track for a (module, controller) pair, adding a lane if needed
+    fn lane(&mut self, slot: i32, module: i32, ctl: i32) -> Result<Option<usize>> {
+        if let Some(track) = self.lanes.iter().position(|&l| l == (module, ctl)) {
+            return Ok(Some(track));
+        }
+        if self.lanes.len() == MAX_LANES {
+            return Ok(None);
+        }
+        self.lanes.push((module, ctl));
+
+        let tracks = self.lanes.len() as i32;
+        self.patterns.retain(|_, &mut p| pattern_exists(slot, p));
+        for &pattern in self.patterns.values() {
+            set_pattern_size(slot, pattern, tracks, -1)?;
+        }
+        Ok(Some(self.lanes.len() - 1))
+    }
+
+    /// Automation pattern covering `line`, created on first use
+    fn pattern_at(&mut self, slot: i32, line: i32) -> Result<(i32, i32)> {
+        let x = line - line % self.pattern_lines;
+        if let Some(&pattern) = self.patterns.get(&x) {
+            if pattern_exists(slot, pattern) {
+                return Ok((pattern, x));
+            }
+        }
+
+        // Below every existing pattern
+        let y = match self.y {
+            Some(y) => y,
+            None => {
+                let _lock = SlotLock::new(slot);
+                let y = patterns(slot)
+                    .into_iter()
+                    .filter_map(|p| pattern_info(slot, p))
+                    .map(|info| info.y + 32)
+                    .max()
+                    .unwrap_or(0);
+                *self.y.insert(y)
+            }
+        };
+        let tracks = self.lanes.len().max(1) as i32;
+        let pattern = new_pattern(slot, "Automation", x, y, tracks, self.pattern_lines)?;
+        self.patterns.insert(x, pattern);
+        Ok((pattern, x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{controller_value, CtlScale};
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::patterns::{clear_patterns, pattern_event};
+    use crate::project::{
+        apply_pending_events, load_project, rewind, save_project, set_song_speed,
+    };
+    use crate::test_support::{render, with_engine};
+
+    #[test]
+    fn test_record_and_replay() {
+        with_engine(|slot| {
+            clear_patterns(slot).unwrap();
+            clear_modules(slot).unwrap();
+            set_song_speed(slot, 125, 6).unwrap();
+            apply_pending_events();
+            let synth = new_module(slot, "Generator", "Lead", 256, 0, 0).unwrap();
+            connect_modules(slot, synth, OUTPUT_MODULE).unwrap();
+            new_pattern(slot, "Song", 0, 0, 1, 16).unwrap();
+
+            let at = |line, fraction| LinePosition { line, fraction };
+            let mut recorder = AutomationRecorder::new(8);
+            assert!(recorder
+                .record_at(slot, at(2, 0), synth, 0, 0x2000)
+                .unwrap());
+            assert!(recorder
+                .record_at(slot, at(5, 12), synth, 2, 0x6000)
+                .unwrap());
+            assert!(recorder
+                .record_at(slot, at(9, 0), synth, 0, 0x4000)
+                .unwrap());
+            assert!(!recorder.record_at(slot, at(3, 0), 99, 0, 0).unwrap());
+
+            // Two lanes, split over patterns of 8 lines
+            let first = find_automation(slot, 0);
+            let second = find_automation(slot, 8);
+            assert_eq!(pattern_info(slot, first).unwrap().tracks, 2);
+            assert_eq!(pattern_info(slot, first).unwrap().y, 32);
+            let cell = pattern_event(slot, first, 1, 5).unwrap();
+            assert_eq!(cell.ctl, 0x0300 | 0x4C);
+            assert_eq!(cell.ctl_val, 0x6000);
+            assert_eq!(pattern_event(slot, second, 0, 1).unwrap().ctl, 0x0100);
+
+            // Saved and reloaded, playing back to line 3 applies the first
+            // change (volume 256 * 1/4)
+            let path = std::env::temp_dir().join("automation_test.sunvox");
+            let path = path.to_str().unwrap();
+            save_project(slot, path).unwrap();
+            load_project(slot, path).unwrap();
+            let _ = std::fs::remove_file(path);
+            assert_eq!(controller_value(slot, synth, 0, CtlScale::Real), 128);
+            unsafe {
+                sv_play_from_beginning(slot);
+            }
+            render(3 * 6 * 882 + 100);
+            assert_eq!(controller_value(slot, synth, 0, CtlScale::Real), 64);
+            unsafe {
+                sv_stop(slot);
+                sv_stop(slot);
+            }
+        });
+    }
+
+    #[test]
+    fn test_playhead() {
+        with_engine(|slot| {
+            clear_patterns(slot).unwrap();
+            clear_modules(slot).unwrap();
+            set_song_speed(slot, 125, 6).unwrap();
+            apply_pending_events();
+            new_pattern(slot, "Song", 0, 0, 1, 256).unwrap();
+
+            let mut playhead = Playhead::new(44100);
+            playhead.start_block(slot);
+            assert_eq!(playhead.position_at(0), None);
+
+            // Plays 2048-frame blocks (after which the engine's own line can
+            // trail by a line and a half) and returns how far the playhead's
+            // block starts strayed from the first one's, advanced at 5292
+            // frames per line
+            let play = |playhead: &mut Playhead, blocks: usize| {
+                let mut error = 0.0f64;
+                let mut expected = None;
+                for _ in 0..blocks {
+                    playhead.start_block(slot);
+                    let at = |offset| {
+                        let p = playhead.position_at(offset).unwrap();
+                        p.line as f64 + p.fraction as f64 / 32.0
+                    };
+                    let line = at(0);
+                    let expected = expected.get_or_insert(line);
+                    error = error.max((line - *expected).abs());
+                    assert!((at(1323) - line - 0.25).abs() <= 1.0 / 32.0);
+                    *expected += 2048.0 / 5292.0;
+                    render(2048);
+                    playhead.advance(2048);
+                }
+                error
+            };
+            unsafe {
+                sv_play_from_beginning(slot);
+            }
+            assert_eq!(play(&mut playhead, 1), 0.0);
+            assert!(play(&mut playhead, 100) < 1.0 / 32.0);
+
+            // A jump made by the caller is exact, others follow the engine
+            rewind(slot, 32).unwrap();
+            playhead.jump(32);
+            assert_eq!(playhead.position_at(0).unwrap().line, 32);
+            let mut recorder = AutomationRecorder::default();
+            assert!(!recorder
+                .record(slot, &playhead, 1323, OUTPUT_MODULE, 0, 0x4000)
+                .unwrap());
+            recorder.set_enabled(true);
+            assert!(recorder
+                .record(slot, &playhead, 1323, OUTPUT_MODULE, 0, 0x4000)
+                .unwrap());
+            let pattern = find_automation(slot, 0);
+            assert_eq!(pattern_event(slot, pattern, 0, 32).unwrap().ctl, 0x0148);
+            assert!(play(&mut playhead, 100) < 1.0 / 32.0);
+            rewind(slot, 8).unwrap();
+            play(&mut playhead, 4);
+            playhead.start_block(slot);
+            let line = playhead.position_at(0).unwrap().line;
+            assert!((8..=9).contains(&line), "{}", line);
+
+            unsafe {
+                sv_stop(slot);
+                sv_stop(slot);
+            }
+            render(2048);
+            playhead.start_block(slot);
+            assert_eq!(playhead.position_at(0), None);
+        });
+    }
+
+    fn find_automation(slot: i32, x: i32) -> i32 {
+        patterns(slot)
+            .into_iter()
+            .filter_map(|p| pattern_info(slot, p))
+            .find(|info| info.name == "Automation" && info.x == x)
+            .map(|info| info.id)
+            .expect("automation pattern")
+    }
+}
@@ -1,35 +1,325 @@
 // Standalone test application to verify SunVox library works outside plugin sandbox
 // This tests whether the macOS sandbox restriction is the root cause of sv_init() failure
+//
+// Subcommands:
+//   graph <project.sunvox> [--json]   Dump the module graph as DOT (default) or JSON
+//   midi <project.sunvox> <out.mid> [--per-module]
+//                                     Export the patterns as a Standard MIDI File
+//   midi-import <song.mid> <out.sunvox> [--into <project.sunvox>]
+//               [--channel <1-16>=<module>]... [--lines-per-beat <n>]
+//                                     Convert a Standard MIDI File to a SunVox project,
+//                                     optionally into the modules of an existing one
+//   backing-track <project.sunvox> <track.ogg> <out.sunvox>
+//                                     Add an OGG file that plays along with the song
 
 use std::ffi::CString;
-use std::os::raw::{c_char, c_int, c_void};
+use std::os::raw::{c_int, c_void};
 use std::thread;
 use std::time::Duration;
+use sunvox_clap::sunvox_ffi::*;
 
-// SunVox FFI declarations (copied from sunvox_ffi.rs for standalone binary)
-#[link(name = "sunvox")]
-extern "C" {
-    pub fn sv_init(config: *const c_char, freq: c_int, channels: c_int, flags: u32) -> c_int;
-    pub fn sv_deinit() -> c_int;
-    pub fn sv_audio_callback(buf: *mut c_void, frames: c_int, latency: c_int, out_time: u32) -> c_int;
-    pub fn sv_open_slot(slot: c_int) -> c_int;
-    pub fn sv_close_slot(slot: c_int) -> c_int;
-    pub fn sv_load(slot: c_int, name: *const c_char) -> c_int;
-    pub fn sv_play_from_beginning(slot: c_int) -> c_int;
-    pub fn sv_stop(slot: c_int) -> c_int;
-    pub fn sv_get_ticks() -> u32;
-    pub fn sv_get_sample_rate() -> c_int;
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("graph") => graph_command(&args[2..]),
+        Some("midi") => midi_command(&args[2..]),
+        Some("midi-import") => midi_import_command(&args[2..]),
+        Some("backing-track") => backing_track_command(&args[2..]),
+        _ => sandbox_test(),
+    }
 }
 
-// SunVox initialization flags
-const SV_INIT_FLAG_NO_DEBUG_OUTPUT: u32 = 1 << 0;
-const SV_INIT_FLAG_USER_AUDIO_CALLBACK: u32 = 1 << 1;
-const SV_INIT_FLAG_AUDIO_INT16: u32 = 1 << 2;
-const SV_INIT_FLAG_AUDIO_FLOAT32: u32 = 1 << 3;
-const SV_INIT_FLAG_ONE_THREAD: u32 = 1 << 4;
-const SV_INIT_FLAG_OFFLINE: u32 = 1 << 8;
+/// Initialize SunVox without an audio device and load a project into slot 0
+fn open_project(path: &str) -> Result<c_int, String> {
+    let slot = open_empty_project()?;
+    let path_cstring = CString::new(path).map_err(|_| "invalid project path".to_string())?;
+    unsafe {
+        let result = sv_load(slot, path_cstring.as_ptr());
+        if result != 0 {
+            close_project(slot);
+            return Err(format!("failed to load {}: error {}", path, result));
+        }
+    }
 
-fn main() {
+    Ok(slot)
+}
+
+/// Initialize SunVox without an audio device and open slot 0 with an empty project
+fn open_empty_project() -> Result<c_int, String> {
+    let slot = 0;
+    let flags = SV_INIT_FLAG_NO_DEBUG_OUTPUT
+        | SV_INIT_FLAG_USER_AUDIO_CALLBACK
+        | SV_INIT_FLAG_AUDIO_FLOAT32
+        | SV_INIT_FLAG_ONE_THREAD;
+
+    // sv_init returns the library version on success, negative on error
+    let result = unsafe { sv_init(std::ptr::null(), 44100, 2, flags) };
+    if result < 0 {
+        return Err(format!("sv_init() returned {}", result));
+    }
+
+    unsafe {
+        if sv_open_slot(slot) != 0 {
+            sv_deinit();
+            return Err("failed to open slot".to_string());
+        }
+    }
+
+    Ok(slot)
+}
+
+fn close_project(slot: c_int) {
+    unsafe {
+        sv_close_slot(slot);
+        sv_deinit();
+    }
+}
+
+/// `graph <project.sunvox> [--json]`: print the module graph of a project
+fn graph_command(args: &[String]) {
+    let Some(path) = args.iter().find(|a| !a.starts_with("--")) else {
+        eprintln!("Usage: sunvox_standalone_test graph <project.sunvox> [--json]");
+        std::process::exit(2);
+    };
+    let json = args.iter().any(|a| a == "--json");
+
+    let slot = match open_project(path) {
+        Ok(slot) => slot,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = sunvox_clap::graph_export::export_graph(slot);
+    close_project(slot);
+
+    match result {
+        Ok(export) if json => println!("{}", export.to_json()),
+        Ok(export) => print!("{}", export.to_dot()),
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `midi <project.sunvox> <out.mid> [--per-module]`: export the patterns of a project
+fn midi_command(args: &[String]) {
+    use sunvox_clap::midi_export::{export_midi, TrackLayout};
+
+    let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    let [path, out_path] = paths[..] else {
+        eprintln!("Usage: sunvox_standalone_test midi <project.sunvox> <out.mid> [--per-module]");
+        std::process::exit(2);
+    };
+    let layout = if args.iter().any(|a| a == "--per-module") {
+        TrackLayout::PerModule
+    } else {
+        TrackLayout::PerPatternTrack
+    };
+
+    let slot = match open_project(path) {
+        Ok(slot) => slot,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = export_midi(slot, layout);
+    close_project(slot);
+
+    let file = match result {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = std::fs::write(out_path, file.to_bytes()) {
+        eprintln!("❌ Failed to write {}: {}", out_path, e);
+        std::process::exit(1);
+    }
+    println!("✅ Wrote {} tracks to {}", file.tracks.len(), out_path);
+}
+
+/// Arguments of the `midi-import` command
+struct ImportArgs<'a> {
+    midi_path: &'a str,
+    out_path: &'a str,
+    /// Project to import into instead of an empty one
+    project: Option<&'a str>,
+    options: sunvox_clap::midi_import::ImportOptions,
+}
+
+impl<'a> ImportArgs<'a> {
+    /// `None` if the arguments are malformed
+    fn parse(args: &'a [String]) -> Option<Self> {
+        let mut options = sunvox_clap::midi_import::ImportOptions::default();
+        let mut project = None;
+        let mut paths = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--into" => project = Some(args.next()?.as_str()),
+                "--channel" => {
+                    let (channel, module) = args.next()?.split_once('=')?;
+                    let channel: usize = channel.parse().ok()?;
+                    let mapped = options.channel_modules.get_mut(channel.checked_sub(1)?)?;
+                    *mapped = Some(module.parse().ok()?);
+                }
+                "--lines-per-beat" => {
+                    let lines = args.next()?.parse().ok().filter(|&n: &u32| n > 0)?;
+                    options.lines_per_beat = Some(lines);
+                }
+                flag if flag.starts_with("--") => return None,
+                path => paths.push(path),
+            }
+        }
+        let [midi_path, out_path] = paths[..] else {
+            return None;
+        };
+        Some(Self {
+            midi_path,
+            out_path,
+            project,
+            options,
+        })
+    }
+}
+
+/// `midi-import <song.mid> <out.sunvox> [--into <project.sunvox>]
+/// [--channel <1-16>=<module>]... [--lines-per-beat <n>]`: convert a MIDI
+/// file to a project
+///
+/// `--channel` plays a MIDI channel on an existing module, which needs
+/// `--into` since an empty project only has the Output; other channels get a
+/// new Generator each.
+fn midi_import_command(args: &[String]) {
+    use sunvox_clap::midi_file::MidiFile;
+    use sunvox_clap::midi_import::import_midi;
+    use sunvox_clap::module_graph::module_exists;
+    use sunvox_clap::project::save_project;
+
+    let Some(ImportArgs {
+        midi_path,
+        out_path,
+        project,
+        options,
+    }) = ImportArgs::parse(args)
+    else {
+        eprintln!(
+            "Usage: sunvox_standalone_test midi-import <song.mid> <out.sunvox> \
+             [--into <project.sunvox>] [--channel <1-16>=<module>]... [--lines-per-beat <n>]"
+        );
+        std::process::exit(2);
+    };
+
+    let file = match std::fs::read(midi_path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| MidiFile::parse(&data).map_err(|e| e.to_string()))
+    {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("❌ Failed to read {}: {}", midi_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let slot = match project.map_or_else(open_empty_project, open_project) {
+        Ok(slot) => slot,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let missing = options
+        .channel_modules
+        .iter()
+        .flatten()
+        .find(|&&module| module <= 0 || !module_exists(slot, module));
+    if let Some(module) = missing {
+        close_project(slot);
+        eprintln!("❌ Module {} doesn't exist or can't play notes", module);
+        std::process::exit(1);
+    }
+
+    let result = import_midi(slot, &file, &options)
+        .and_then(|summary| save_project(slot, out_path).map(|_| summary));
+    close_project(slot);
+
+    match result {
+        Ok(summary) => {
+            println!(
+                "✅ Imported {} notes into {} patterns ({} dropped)",
+                summary.notes,
+                summary.patterns.len(),
+                summary.dropped_notes
+            );
+        }
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `backing-track <project.sunvox> <track.ogg> <out.sunvox>`: add a backing
+/// track to a project
+fn backing_track_command(args: &[String]) {
+    use sunvox_clap::project::save_project;
+    use sunvox_clap::vorbis_player::BackingTrack;
+
+    let [project_path, track_path, out_path] = args else {
+        eprintln!(
+            "Usage: sunvox_standalone_test backing-track <project.sunvox> <track.ogg> <out.sunvox>"
+        );
+        std::process::exit(2);
+    };
+
+    let slot = match open_project(project_path) {
+        Ok(slot) => slot,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let name = std::path::Path::new(track_path)
+        .file_stem()
+        .map_or_else(|| "Backing track".into(), |s| s.to_string_lossy());
+    let result = BackingTrack::attach_file(slot, &name, track_path)
+        .and_then(|track| save_project(slot, out_path).map(|_| track));
+    close_project(slot);
+
+    match result {
+        Ok(track) => {
+            println!(
+                "✅ Added {} as module {} and pattern {}",
+                track_path, track.module, track.pattern
+            );
+        }
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Library version as returned by `sv_init` (0x20103 is 2.1.3)
+fn version_string(version: c_int) -> String {
+    format!(
+        "{}.{}.{}",
+        (version >> 16) & 0xFF,
+        (version >> 8) & 0xFF,
+        version & 0xFF
+    )
+}
+
+fn sandbox_test() {
     println!("==============================================");
     println!("SunVox Standalone Test - Sandbox Investigation");
     println!("==============================================\n");
@@ -41,6 +331,7 @@ fn main() {
     let sample_rate = 44100;
     let channels = 2;
     let mut success = false;
+    let mut init_flags = 0;
 
     // Test 1: NO FLAGS (like the official C example test1.c)
     println!("Test 1: Initializing SunVox with NO FLAGS");
@@ -50,17 +341,15 @@ fn main() {
     let flags_test1 = 0;
 
     let mut result = unsafe {
-        sv_init(0 as *const i8, sample_rate, channels, flags_test1)
+        sv_init(std::ptr::null(), sample_rate, channels, flags_test1)
     };
 
-    if result == 0 {
-        println!("  ✅ SUCCESS!");
+    if result >= 0 {
+        println!("  ✅ SUCCESS! (SunVox {})", version_string(result));
         success = true;
+        init_flags = flags_test1;
     } else {
-        println!("  ❌ FAILURE: sv_init() returned {} (0x{:X})", result, result);
-        if result == 131331 || result == 0x20103 {
-            println!("     (CoreAudio initialization blocked)");
-        }
+        println!("  ❌ FAILURE: sv_init() returned {}", result);
 
         // Clean up partial initialization
         unsafe { sv_deinit(); }
@@ -75,14 +364,15 @@ fn main() {
         let flags_test2 = SV_INIT_FLAG_OFFLINE;
 
         result = unsafe {
-            sv_init(0 as *const i8, sample_rate, channels, flags_test2)
+            sv_init(std::ptr::null(), sample_rate, channels, flags_test2)
         };
 
-        if result == 0 {
-            println!("  ✅ SUCCESS!");
+        if result >= 0 {
+            println!("  ✅ SUCCESS! (SunVox {})", version_string(result));
             success = true;
+            init_flags = flags_test2;
         } else {
-            println!("  ❌ FAILURE: sv_init() returned {} (0x{:X})", result, result);
+            println!("  ❌ FAILURE: sv_init() returned {}", result);
             unsafe { sv_deinit(); }
         }
     }
@@ -96,14 +386,15 @@ fn main() {
         let flags_test3 = SV_INIT_FLAG_USER_AUDIO_CALLBACK;
 
         result = unsafe {
-            sv_init(0 as *const i8, sample_rate, channels, flags_test3)
+            sv_init(std::ptr::null(), sample_rate, channels, flags_test3)
         };
 
-        if result == 0 {
-            println!("  ✅ SUCCESS!");
+        if result >= 0 {
+            println!("  ✅ SUCCESS! (SunVox {})", version_string(result));
             success = true;
+            init_flags = flags_test3;
         } else {
-            println!("  ❌ FAILURE: sv_init() returned {} (0x{:X})", result, result);
+            println!("  ❌ FAILURE: sv_init() returned {}", result);
             unsafe { sv_deinit(); }
         }
     }
@@ -116,14 +407,15 @@ fn main() {
         let flags_test4 = SV_INIT_FLAG_USER_AUDIO_CALLBACK | SV_INIT_FLAG_OFFLINE;
 
         result = unsafe {
-            sv_init(0 as *const i8, sample_rate, channels, flags_test4)
+            sv_init(std::ptr::null(), sample_rate, channels, flags_test4)
         };
 
-        if result == 0 {
-            println!("  ✅ SUCCESS!");
+        if result >= 0 {
+            println!("  ✅ SUCCESS! (SunVox {})", version_string(result));
             success = true;
+            init_flags = flags_test4;
         } else {
-            println!("  ❌ FAILURE: sv_init() returned {} (0x{:X})", result, result);
+            println!("  ❌ FAILURE: sv_init() returned {}", result);
             unsafe { sv_deinit(); }
         }
     }
@@ -140,14 +432,15 @@ fn main() {
             | SV_INIT_FLAG_OFFLINE;
 
         result = unsafe {
-            sv_init(0 as *const i8, sample_rate, channels, flags_test5)
+            sv_init(std::ptr::null(), sample_rate, channels, flags_test5)
         };
 
-        if result == 0 {
-            println!("  ✅ SUCCESS!");
+        if result >= 0 {
+            println!("  ✅ SUCCESS! (SunVox {})", version_string(result));
             success = true;
+            init_flags = flags_test5;
         } else {
-            println!("  ❌ FAILURE: sv_init() returned {} (0x{:X})", result, result);
+            println!("  ❌ FAILURE: sv_init() returned {}", result);
             unsafe { sv_deinit(); }
         }
     }
@@ -212,11 +505,18 @@ fn main() {
     println!("\nTest 7: Generating audio (5 buffers)");
     let buffer_size = 512;
     let mut buffer = vec![0.0f32; buffer_size * 2]; // Stereo
+    // Without AUDIO_FLOAT32 the engine writes int16 samples
+    let float32 = init_flags & SV_INIT_FLAG_AUDIO_FLOAT32 != 0;
+    let mut int_buffer = vec![0i16; buffer_size * 2];
 
     for i in 0..5 {
         let result = unsafe {
             sv_audio_callback(
-                buffer.as_mut_ptr() as *mut c_void,
+                if float32 {
+                    buffer.as_mut_ptr() as *mut c_void
+                } else {
+                    int_buffer.as_mut_ptr() as *mut c_void
+                },
                 buffer_size as i32,
                 0,
                 sv_get_ticks()
@@ -224,13 +524,22 @@ fn main() {
         };
 
         if result == 1 {
-            // Calculate RMS to see if we have audio
-            let rms: f32 = buffer.iter()
-                .map(|&x| x * x)
-                .sum::<f32>()
-                .sqrt() / buffer.len() as f32;
-
-            println!("  Buffer {}: ✅ Generated (RMS: {:.6})", i + 1, rms);
+            if !float32 {
+                for (sample, &int) in buffer.iter_mut().zip(&int_buffer) {
+                    *sample = int as f32 / 32768.0;
+                }
+            }
+            // Measure the output to see if we have audio
+            let mut levels = [sunvox_clap::metering::ChannelLevel::default(); 2];
+            sunvox_clap::metering::measure(&buffer, &mut levels);
+            println!(
+                "  Buffer {}: ✅ Generated (RMS: {:.1} / {:.1} dBFS, peak: {:.1} / {:.1} dBFS)",
+                i + 1,
+                levels[0].rms_db(),
+                levels[1].rms_db(),
+                levels[0].peak_db(),
+                levels[1].peak_db()
+            );
         } else {
             println!("  Buffer {}: ❌ Failed (returned {})", i + 1, result);
         }
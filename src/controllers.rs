@@ -0,0 +1,143 @@
+// Module controller API
+// Typed access to the controllers (parameters) of SunVox modules
+
+use crate::error::{check, owned_string, Result};
+use crate::sunvox_ffi::*;
+use serde::{Deserialize, Serialize};
+
+/// How a controller value is expressed, matching the `scaled` argument of
+/// `sv_get_module_ctl_value` / `sv_set_module_ctl_value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtlScale {
+    /// Real value as stored inside the controller
+    Real = 0,
+    /// 0x0000..0x8000 for normal controllers (the pattern XXYY format),
+    /// the real value for selectors
+    Scaled = 1,
+    /// Value displayed in the SunVox UI (real value plus display offset)
+    Displayed = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CtlKind {
+    /// Continuous value
+    Normal,
+    /// Enumerated value (waveform type, mode, ...)
+    Selector,
+}
+
+/// Snapshot of a single module controller
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Controller {
+    /// Controller number within the module (from 0)
+    pub index: i32,
+    pub name: String,
+    pub kind: CtlKind,
+    /// Real value
+    pub value: i32,
+    pub min: i32,
+    pub max: i32,
+    /// Offset added to the real value for display
+    pub display_offset: i32,
+}
+
+impl Controller {
+    /// Value as shown in the SunVox UI
+    pub fn display_value(&self) -> i32 {
+        self.value + self.display_offset
+    }
+}
+
+pub fn controller_count(slot: i32, module: i32) -> i32 {
+    unsafe { sv_get_number_of_module_ctls(slot, module) }.max(0)
+}
+
+pub fn controller_name(slot: i32, module: i32, ctl: i32) -> Option<String> {
+    unsafe { owned_string(sv_get_module_ctl_name(slot, module, ctl)) }
+}
+
+pub fn controller_value(slot: i32, module: i32, ctl: i32, scale: CtlScale) -> i32 {
+    unsafe { sv_get_module_ctl_value(slot, module, ctl, scale as i32) }
+}
+
+pub fn set_controller_value(
+    slot: i32,
+    module: i32,
+    ctl: i32,
+    value: i32,
+    scale: CtlScale,
+) -> Result<()> {
+    check("sv_set_module_ctl_value", unsafe {
+        sv_set_module_ctl_value(slot, module, ctl, value, scale as i32)
+    })?;
+    Ok(())
+}
+
+/// Value in the pattern format (0x0000..0x8000, or the real value for
+/// selectors) at `position` (0..1) of a controller's range
+pub fn pattern_value(slot: i32, module: i32, ctl: i32, position: f32) -> u16 {
+    let position = position.clamp(0.0, 1.0);
+    if unsafe { sv_get_module_ctl_type(slot, module, ctl) } != 1 {
+        return (position * 0x8000 as f32).round() as u16;
+    }
+    let (min, max) = unsafe {
+        (
+            sv_get_module_ctl_min(slot, module, ctl, CtlScale::Real as i32),
+            sv_get_module_ctl_max(slot, module, ctl, CtlScale::Real as i32),
+        )
+    };
+    (min as f32 + position * (max - min).max(0) as f32)
+        .round()
+        .max(0.0) as u16
+}
+
+/// Set a controller to an exact real value
+///
+/// `sv_set_module_ctl_value` rounds real values down when it converts them to
+/// the 0x0000..0x8000 event format, so reading a value back and setting it
+/// again can drift by one. Here the scaled value is rounded up instead, so the
+/// module's own conversion lands on `value` exactly.
+pub fn set_controller_real_value(slot: i32, module: i32, ctl: i32, value: i32) -> Result<()> {
+    let (min, max, kind) = unsafe {
+        (
+            sv_get_module_ctl_min(slot, module, ctl, CtlScale::Real as i32),
+            sv_get_module_ctl_max(slot, module, ctl, CtlScale::Real as i32),
+            sv_get_module_ctl_type(slot, module, ctl),
+        )
+    };
+
+    let event_value = if kind == 1 || max <= min {
+        value
+    } else {
+        let range = (max - min) as i64;
+        let offset = (value.clamp(min, max) - min) as i64;
+        ((offset * 0x8000 + range - 1) / range).min(0x8000) as i32
+    };
+
+    check("sv_send_event", unsafe {
+        sv_send_event(slot, 0, 0, 0, module + 1, (ctl + 1) << 8, event_value)
+    })?;
+    Ok(())
+}
+
+/// Read every controller of a module
+pub fn read_controllers(slot: i32, module: i32) -> Vec<Controller> {
+    (0..controller_count(slot, module))
+        .map(|ctl| unsafe {
+            Controller {
+                index: ctl,
+                name: controller_name(slot, module, ctl).unwrap_or_default(),
+                kind: if sv_get_module_ctl_type(slot, module, ctl) == 1 {
+                    CtlKind::Selector
+                } else {
+                    CtlKind::Normal
+                },
+                value: sv_get_module_ctl_value(slot, module, ctl, CtlScale::Real as i32),
+                min: sv_get_module_ctl_min(slot, module, ctl, CtlScale::Real as i32),
+                max: sv_get_module_ctl_max(slot, module, ctl, CtlScale::Real as i32),
+                display_offset: sv_get_module_ctl_offset(slot, module, ctl),
+            }
+        })
+        .collect()
+}
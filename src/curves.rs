@@ -0,0 +1,277 @@
+// Module curve API
+// Reads and writes the curves of MultiSynth, WaveShaper, MultiCtl and generators
+//
+// Each curve is a table of Y = CURVE[X] values stored inside the module (and
+// saved with it). SunVox reads past its own tables for curve numbers a
+// module doesn't have, so curves are only reachable through `ModuleCurve`,
+// which knows the module type, number and length of each one. Values are
+// quantized to the module's storage (8 or 16 bits) on write.
+
+use crate::error::{check, Result, SunVoxError};
+use crate::module_graph::expect_module_type;
+use crate::slot::SlotLock;
+use crate::sunvox_ffi::*;
+
+/// A curve of a specific module type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleCurve {
+    /// MultiSynth: note (0..127) to velocity (0..1)
+    MultiSynthNoteVelocity,
+    /// MultiSynth: velocity (0..256) to velocity (0..1)
+    MultiSynthVelocity,
+    /// MultiSynth: note (0..127) to pitch (0..1); note 0 is 16384/65535,
+    /// note 128 is 49152/65535 and a semitone is 256/65535
+    MultiSynthNotePitch,
+    /// WaveShaper: input (0..255) to output (0..1), both centered on the
+    /// middle of the range
+    WaveShaper,
+    /// MultiCtl: input (0..256) to output (0..1)
+    MultiCtl,
+    /// Generator: drawn waveform (-1..1)
+    GeneratorWaveform,
+    /// Analog Generator: drawn waveform (-1..1)
+    AnalogGeneratorWaveform,
+    /// FMX: custom waveform (-1..1)
+    FmxWaveform,
+}
+
+impl ModuleCurve {
+    pub const ALL: [ModuleCurve; 8] = [
+        ModuleCurve::MultiSynthNoteVelocity,
+        ModuleCurve::MultiSynthVelocity,
+        ModuleCurve::MultiSynthNotePitch,
+        ModuleCurve::WaveShaper,
+        ModuleCurve::MultiCtl,
+        ModuleCurve::GeneratorWaveform,
+        ModuleCurve::AnalogGeneratorWaveform,
+        ModuleCurve::FmxWaveform,
+    ];
+
+    /// Type of the modules that have this curve
+    pub fn module_type(self) -> &'static str {
+        match self {
+            ModuleCurve::MultiSynthNoteVelocity
+            | ModuleCurve::MultiSynthVelocity
+            | ModuleCurve::MultiSynthNotePitch => "MultiSynth",
+            ModuleCurve::WaveShaper => "WaveShaper",
+            ModuleCurve::MultiCtl => "MultiCtl",
+            ModuleCurve::GeneratorWaveform => "Generator",
+            ModuleCurve::AnalogGeneratorWaveform => "Analog generator",
+            ModuleCurve::FmxWaveform => "FMX",
+        }
+    }
+
+    /// Curve number within the module
+    pub fn index(self) -> i32 {
+        match self {
+            ModuleCurve::MultiSynthVelocity => 1,
+            ModuleCurve::MultiSynthNotePitch => 2,
+            _ => 0,
+        }
+    }
+
+    /// Number of items
+    pub fn item_count(self) -> usize {
+        match self {
+            ModuleCurve::MultiSynthNoteVelocity | ModuleCurve::MultiSynthNotePitch => 128,
+            ModuleCurve::MultiSynthVelocity | ModuleCurve::MultiCtl => 257,
+            ModuleCurve::WaveShaper | ModuleCurve::FmxWaveform => 256,
+            ModuleCurve::GeneratorWaveform | ModuleCurve::AnalogGeneratorWaveform => 32,
+        }
+    }
+
+    /// Range of the values
+    pub fn range(self) -> (f32, f32) {
+        match self {
+            ModuleCurve::GeneratorWaveform
+            | ModuleCurve::AnalogGeneratorWaveform
+            | ModuleCurve::FmxWaveform => (-1.0, 1.0),
+            _ => (0.0, 1.0),
+        }
+    }
+
+    /// Curves of a module type
+    pub fn for_module_type(module_type: &str) -> Vec<ModuleCurve> {
+        Self::ALL
+            .into_iter()
+            .filter(|c| c.module_type() == module_type)
+            .collect()
+    }
+}
+
+/// Read a whole curve
+pub fn read_curve(slot: i32, module: i32, curve: ModuleCurve) -> Result<Vec<f32>> {
+    expect_module_type(slot, module, curve.module_type())?;
+    let mut data = vec![0.0f32; curve.item_count()];
+    let _lock = SlotLock::new(slot);
+    let read = check("sv_module_curve", unsafe {
+        sv_module_curve(
+            slot,
+            module,
+            curve.index(),
+            data.as_mut_ptr(),
+            data.len() as i32,
+            0,
+        )
+    })?;
+    data.truncate(read as usize);
+    Ok(data)
+}
+
+/// Replace a whole curve; values outside `curve.range()` are clamped
+pub fn write_curve(slot: i32, module: i32, curve: ModuleCurve, values: &[f32]) -> Result<()> {
+    expect_module_type(slot, module, curve.module_type())?;
+    if values.len() != curve.item_count() {
+        return Err(SunVoxError::InvalidValue(format!(
+            "{} values for {:?}, which has {}",
+            values.len(),
+            curve,
+            curve.item_count()
+        )));
+    }
+    // SunVox only reads the data when writing
+    let mut data = values.to_vec();
+    let _lock = SlotLock::new(slot);
+    let written = check("sv_module_curve", unsafe {
+        sv_module_curve(
+            slot,
+            module,
+            curve.index(),
+            data.as_mut_ptr(),
+            data.len() as i32,
+            1,
+        )
+    })?;
+    if written as usize != data.len() {
+        return Err(SunVoxError::InvalidValue(format!(
+            "{:?} of module {} took {} of {} values",
+            curve,
+            module,
+            written,
+            data.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Common curve shapes, from X = 0 to the last item
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveShape {
+    /// Straight line
+    Linear { from: f32, to: f32 },
+    /// Exponential ramp; positive `curvature` starts slow and ends fast,
+    /// negative the reverse, 0 is a straight line
+    Exponential { from: f32, to: f32, curvature: f32 },
+    /// Velocity response 0..1 as `x^exponent`: below 1 is softer (quiet
+    /// notes get louder), above 1 harder
+    Velocity { exponent: f32 },
+}
+
+impl CurveShape {
+    /// Value at `x` (0..1)
+    pub fn value_at(self, x: f32) -> f32 {
+        match self {
+            CurveShape::Linear { from, to } => from + (to - from) * x,
+            CurveShape::Exponential {
+                from,
+                to,
+                curvature,
+            } => {
+                if curvature.abs() < 1e-6 {
+                    return from + (to - from) * x;
+                }
+                let t = (curvature * x).exp_m1() / curvature.exp_m1();
+                from + (to - from) * t
+            }
+            CurveShape::Velocity { exponent } => x.powf(exponent.max(0.0)),
+        }
+    }
+
+    /// The shape as `len` values
+    pub fn generate(self, len: usize) -> Vec<f32> {
+        sample_fn(len, |x| self.value_at(x))
+    }
+}
+
+/// `len` values of `f(x)` for x evenly spaced from 0 to 1, such as a
+/// transfer function for the WaveShaper
+pub fn sample_fn(len: usize, f: impl Fn(f32) -> f32) -> Vec<f32> {
+    let last = len.saturating_sub(1).max(1) as f32;
+    (0..len).map(|i| f(i as f32 / last)).collect()
+}
+
+/// Replace a whole curve with a shape
+pub fn write_curve_shape(
+    slot: i32,
+    module: i32,
+    curve: ModuleCurve,
+    shape: CurveShape,
+) -> Result<()> {
+    write_curve(slot, module, curve, &shape.generate(curve.item_count()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module_graph::{clear_modules, module_type, new_module};
+    use crate::test_support::with_engine;
+
+    #[test]
+    fn test_read_and_write_curves() {
+        let ramp = CurveShape::Linear { from: 1.0, to: 0.0 }.generate(5);
+        assert_eq!(ramp, [1.0, 0.75, 0.5, 0.25, 0.0]);
+        let exp = CurveShape::Exponential {
+            from: 0.0,
+            to: 1.0,
+            curvature: 3.0,
+        }
+        .generate(5);
+        assert_eq!((exp[0], exp[4]), (0.0, 1.0));
+        assert!(exp[2] < 0.5);
+        let soft = CurveShape::Velocity { exponent: 0.5 };
+        assert!((soft.value_at(0.25) - 0.5).abs() < 1e-6);
+
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            for curve in ModuleCurve::ALL {
+                let module = new_module(slot, curve.module_type(), "Curves", 0, 0, 0).unwrap();
+                assert_eq!(
+                    module_type(slot, module).as_deref(),
+                    Some(curve.module_type())
+                );
+                assert_eq!(
+                    read_curve(slot, module, curve).unwrap().len(),
+                    curve.item_count()
+                );
+
+                let (low, high) = curve.range();
+                let shape = CurveShape::Exponential {
+                    from: high,
+                    to: low,
+                    curvature: -2.0,
+                };
+                write_curve_shape(slot, module, curve, shape).unwrap();
+                let read = read_curve(slot, module, curve).unwrap();
+                for (value, expected) in read.iter().zip(shape.generate(curve.item_count())) {
+                    // 8-bit curves are the coarsest
+                    assert!((value - expected).abs() < 0.01, "{:?}", curve);
+                }
+                assert!(write_curve(slot, module, curve, &[0.5; 3]).is_err());
+            }
+
+            let multictl = new_module(slot, "MultiCtl", "Ctl", 0, 0, 0).unwrap();
+            assert!(matches!(
+                read_curve(slot, multictl, ModuleCurve::WaveShaper),
+                Err(SunVoxError::WrongModuleType { .. })
+            ));
+            assert_eq!(
+                ModuleCurve::for_module_type("MultiSynth"),
+                [
+                    ModuleCurve::MultiSynthNoteVelocity,
+                    ModuleCurve::MultiSynthVelocity,
+                    ModuleCurve::MultiSynthNotePitch
+                ]
+            );
+        });
+    }
+}
@@ -0,0 +1,66 @@
+// Error type shared by the safe SunVox wrappers
+
+use std::ffi::CString;
+use std::fmt;
+
+/// Errors returned by the safe wrappers around the SunVox library
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SunVoxError {
+    /// A SunVox function returned a negative error code
+    Call { function: &'static str, code: i32 },
+    /// A string argument contained an interior NUL byte
+    InvalidString(String),
+    /// A lookup (module, pattern, ...) found nothing
+    NotFound(String),
+    /// A module was not of the type the operation needs
+    WrongModuleType { module: i32, expected: &'static str },
+    /// SunVox ignored a value that is out of range for its target
+    InvalidValue(String),
+}
+
+impl fmt::Display for SunVoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SunVoxError::Call { function, code } => {
+                write!(f, "{} failed with code {} (0x{:x})", function, code, code)
+            }
+            SunVoxError::InvalidString(s) => write!(f, "string contains a NUL byte: {:?}", s),
+            SunVoxError::NotFound(what) => write!(f, "not found: {}", what),
+            SunVoxError::WrongModuleType { module, expected } => {
+                write!(f, "module {} is not a {} module", module, expected)
+            }
+            SunVoxError::InvalidValue(what) => write!(f, "invalid value: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SunVoxError {}
+
+pub type Result<T> = std::result::Result<T, SunVoxError>;
+
+/// Turn a SunVox return code into a `Result`, keeping non-negative values
+pub fn check(function: &'static str, code: i32) -> Result<i32> {
+    if code < 0 {
+        Err(SunVoxError::Call { function, code })
+    } else {
+        Ok(code)
+    }
+}
+
+/// Convert a Rust string into a C string for passing to SunVox
+pub fn c_string(s: &str) -> Result<CString> {
+    CString::new(s).map_err(|_| SunVoxError::InvalidString(s.to_string()))
+}
+
+/// Copy a NUL-terminated string owned by SunVox into a `String`
+///
+/// Returns `None` for NULL pointers.
+///
+/// # Safety
+/// `ptr` must be NULL or point to a valid NUL-terminated string.
+pub unsafe fn owned_string(ptr: *const std::os::raw::c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    Some(std::ffi::CStr::from_ptr(ptr).to_string_lossy().into_owned())
+}
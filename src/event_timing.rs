@@ -0,0 +1,374 @@
+// Timed event API
+// Maps frame offsets in an audio block to SunVox system ticks for `sv_set_event_t`
+//
+// SunVox stamps every `sv_send_event` with a time in system ticks (the wall
+// clock unless `sv_set_event_t` is used) and processes it once the output
+// time passed to `sv_audio_callback` reaches that stamp. The check only
+// happens at the start of the engine's internal render chunks, which are up
+// to one SunVox tick (about 20 ms) long, so `render_block` also splits the
+// block at every event offset to make each event start a chunk.
+//
+// When `out_time` comes from a `SampleClock` rather than `sv_get_ticks`,
+// automatic (wall-clock) stamps no longer line up with the output time, so
+// events without a frame of their own are sent in immediate mode instead.
+// The clock may run ahead of the wall clock (offline bounces) but is
+// re-synced to it whenever it falls behind.
+
+use crate::error::{check, Result};
+use crate::sunvox_ffi::*;
+use std::iter::Peekable;
+
+/// Current value of the SunVox system tick counter
+pub fn ticks() -> u32 {
+    unsafe { sv_get_ticks() }
+}
+
+pub fn ticks_per_second() -> u32 {
+    unsafe { sv_get_ticks_per_second() }
+}
+
+/// Timing of one audio block, as passed to `sv_audio_callback`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTime {
+    /// Output time of the first frame, in system ticks
+    pub out_time: u32,
+    pub sample_rate: u32,
+    pub ticks_per_second: u32,
+}
+
+impl BlockTime {
+    pub fn new(out_time: u32, sample_rate: u32) -> Self {
+        Self {
+            out_time,
+            sample_rate: sample_rate.max(1),
+            ticks_per_second: ticks_per_second(),
+        }
+    }
+
+    /// System tick of the frame `offset` frames into the block
+    pub fn tick_at(&self, offset: u32) -> u32 {
+        let delta = offset as u64 * self.ticks_per_second as u64 / self.sample_rate as u64;
+        self.out_time.wrapping_add(delta as u32)
+    }
+}
+
+/// Virtual output clock that advances by the number of rendered frames and
+/// is re-synced to the wall clock when it falls behind
+///
+/// Converting the total frame count (rather than adding up per-block tick
+/// deltas) keeps the clock free of rounding drift, and lets it run ahead of
+/// the wall clock when rendering faster than realtime. It can't stay behind
+/// the wall clock though: see `catch_up`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleClock {
+    /// System tick at frame 0
+    origin: u32,
+    frames: u64,
+    sample_rate: u32,
+    ticks_per_second: u32,
+}
+
+impl SampleClock {
+    /// Start counting from the current system tick
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            origin: ticks(),
+            frames: 0,
+            sample_rate: sample_rate.max(1),
+            ticks_per_second: ticks_per_second(),
+        }
+    }
+
+    /// Frames rendered so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Timing of the next block
+    pub fn block(&self) -> BlockTime {
+        let delta = self.frames as u128 * self.ticks_per_second as u128 / self.sample_rate as u128;
+        BlockTime {
+            out_time: self.origin.wrapping_add(delta as u32),
+            sample_rate: self.sample_rate,
+            ticks_per_second: self.ticks_per_second,
+        }
+    }
+
+    pub fn advance(&mut self, frames: u32) {
+        self.frames += frames as u64;
+    }
+
+    /// Move the clock forward to the system tick counter if it fell behind
+    ///
+    /// SunVox stamps its own transport commands (`sv_play`, `sv_stop`,
+    /// `sv_rewind`) with the wall clock and holds them back until `out_time`
+    /// reaches the stamp, so a clock left behind while the host was not
+    /// processing would delay them. Rendering itself does not depend on
+    /// `out_time` as long as events are sent in immediate mode.
+    pub fn catch_up(&mut self) {
+        let behind = ticks().wrapping_sub(self.block().out_time) as i32;
+        if behind > 0 {
+            self.origin = self.origin.wrapping_add(behind as u32);
+        }
+    }
+}
+
+/// An event to be processed at a frame offset within a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedEvent {
+    /// Frame offset from the start of the block
+    pub offset: u32,
+    /// Track of the virtual pattern used for live input
+    pub track: i32,
+    pub event: SunvoxNote,
+}
+
+/// Render a block of interleaved float audio, processing every event on its
+/// frame
+///
+/// `events` must be sorted by offset. Returns whether SunVox produced any
+/// audio (`sv_audio_callback` returned 1 for at least one part).
+pub fn render_block(
+    slot: i32,
+    buffer: &mut [f32],
+    channels: usize,
+    time: BlockTime,
+    events: &[TimedEvent],
+) -> Result<bool> {
+    render_block_split(slot, buffer, channels, time, events, None, || Ok(()))
+}
+
+/// `render_block`, stopping at frame `split` to call `at_split`
+///
+/// Whatever `at_split` changes (pattern mutes, events sent in immediate mode)
+/// takes effect from that frame on.
+pub fn render_block_split(
+    slot: i32,
+    buffer: &mut [f32],
+    channels: usize,
+    time: BlockTime,
+    events: &[TimedEvent],
+    split: Option<u32>,
+    at_split: impl FnOnce() -> Result<()>,
+) -> Result<bool> {
+    let channels = channels.max(1);
+    let frames = (buffer.len() / channels) as u32;
+    let mut pending = events.iter().peekable();
+
+    let middle = split.map_or(frames, |split| split.min(frames));
+    let mut audio = render_frames(slot, buffer, channels, time, &mut pending, 0, middle)?;
+    if split.is_some() {
+        at_split()?;
+    }
+    audio |= render_frames(slot, buffer, channels, time, &mut pending, middle, frames)?;
+
+    // Events past the end of the block are handled at the start of the next
+    for event in pending {
+        send_event(slot, event.track, event.event)?;
+    }
+    Ok(audio)
+}
+
+/// Render frames `start..end` of a block, splitting them at every event
+fn render_frames<'a>(
+    slot: i32,
+    buffer: &mut [f32],
+    channels: usize,
+    time: BlockTime,
+    pending: &mut Peekable<impl Iterator<Item = &'a TimedEvent>>,
+    mut start: u32,
+    end: u32,
+) -> Result<bool> {
+    let mut audio = false;
+    while start < end {
+        while let Some(event) = pending.next_if(|e| e.offset <= start) {
+            send_event_at(slot, time.tick_at(start), event.track, event.event)?;
+        }
+        let part_end = pending.peek().map_or(end, |e| e.offset.min(end));
+
+        let part = &mut buffer[start as usize * channels..part_end as usize * channels];
+        audio |= unsafe {
+            sv_audio_callback(
+                part.as_mut_ptr() as *mut std::os::raw::c_void,
+                (part_end - start) as i32,
+                0,
+                time.tick_at(start),
+            )
+        } == 1;
+        start = part_end;
+    }
+    Ok(audio)
+}
+
+/// Stamp the following events with `t` (system ticks)
+pub fn set_event_time(slot: i32, t: u32) -> Result<()> {
+    check("sv_set_event_t", unsafe {
+        sv_set_event_t(slot, 1, t as i32)
+    })?;
+    Ok(())
+}
+
+/// Process the following events as soon as possible (at the start of the
+/// next render chunk), whatever `out_time` the audio callback is given
+pub fn set_immediate_events(slot: i32) -> Result<()> {
+    check("sv_set_event_t", unsafe { sv_set_event_t(slot, 1, 0) })?;
+    Ok(())
+}
+
+/// Return to automatic event timing, which stamps events with the wall clock
+/// (`sv_get_ticks`)
+pub fn reset_event_time(slot: i32) -> Result<()> {
+    check("sv_set_event_t", unsafe { sv_set_event_t(slot, 0, 0) })?;
+    Ok(())
+}
+
+/// Send an event to a track of the virtual pattern used for live input
+pub fn send_event(slot: i32, track: i32, event: SunvoxNote) -> Result<()> {
+    check("sv_send_event", unsafe {
+        sv_send_event(
+            slot,
+            track,
+            event.note as i32,
+            event.vel as i32,
+            event.module as i32,
+            event.ctl as i32,
+            event.ctl_val as i32,
+        )
+    })?;
+    Ok(())
+}
+
+/// Send an event that is processed at system tick `t`
+///
+/// The slot is left in immediate mode afterwards: the timestamp set with
+/// `sv_set_event_t` is kept per slot even across `sv_deinit`, and a stale one
+/// would hold back every later `sv_send_event` on the slot.
+pub fn send_event_at(slot: i32, t: u32, track: i32, event: SunvoxNote) -> Result<()> {
+    set_event_time(slot, t)?;
+    let result = send_event(slot, track, event);
+    set_immediate_events(slot)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module_graph::{connect_modules, new_module, OUTPUT_MODULE};
+    use crate::project::load_project;
+    use crate::test_support::{resource_path, with_engine};
+
+    #[test]
+    fn test_onsets_match_requested_offsets() {
+        let wrapping = BlockTime {
+            out_time: u32::MAX - 9,
+            sample_rate: 44100,
+            ticks_per_second: 50000,
+        };
+        assert_eq!(wrapping.tick_at(44100), 50000 - 10);
+
+        // A clock that fell behind the wall clock jumps to it, one that is
+        // ahead (offline rendering) stays where it is
+        let mut clock = SampleClock::new(44100);
+        clock.origin = clock.origin.wrapping_sub(clock.ticks_per_second);
+        clock.catch_up();
+        assert!(ticks().wrapping_sub(clock.block().out_time) < clock.ticks_per_second / 10);
+        clock.advance(441000);
+        let ahead = clock.block();
+        clock.catch_up();
+        assert_eq!(clock.block(), ahead);
+
+        with_engine(|slot| {
+            let synth = new_module(slot, "Generator", "Tone", 256, 0, 0).unwrap();
+            connect_modules(slot, synth, OUTPUT_MODULE).unwrap();
+            let event = |offset, note| TimedEvent {
+                offset,
+                track: 0,
+                event: SunvoxNote {
+                    note,
+                    module: synth as u16 + 1,
+                    ..Default::default()
+                },
+            };
+
+            const BLOCK: usize = 512;
+            let mut buffer = vec![0.0f32; BLOCK * 2];
+            let mut clock = SampleClock::new(44100);
+            let mut render = |events: &[TimedEvent], buffer: &mut [f32]| {
+                render_block(slot, buffer, 2, clock.block(), events).unwrap();
+                clock.advance(BLOCK as u32);
+            };
+
+            for requested in [0, 1, 37, 300, 480] {
+                render(&[event(requested, 60)], &mut buffer);
+                let onset = buffer
+                    .chunks(2)
+                    .position(|frame| frame[0].abs() > 1e-5)
+                    .expect("note produced no audio");
+                // The generator's waveform starts at zero
+                assert!(
+                    onset.abs_diff(requested as usize) <= 1,
+                    "requested frame {}, onset at {}",
+                    requested,
+                    onset
+                );
+
+                render(&[event(100, NOTECMD_NOTE_OFF)], &mut buffer);
+                let last = buffer.chunks(2).rposition(|frame| frame[0].abs() > 1e-5);
+                assert_eq!(last, Some(99));
+                render(&[], &mut buffer);
+            }
+        });
+    }
+
+    #[test]
+    fn test_render_independent_of_wall_clock() {
+        use std::time::Duration;
+
+        with_engine(|slot| {
+            // Render the song with a timed note and one sent between blocks,
+            // once as fast as possible and once slower than realtime (10 ms
+            // blocks with 15 ms pauses)
+            let mut takes = Vec::new();
+            for pause in [None, Some(Duration::from_millis(15))] {
+                load_project(slot, &resource_path("song01.sunvox")).unwrap();
+                unsafe {
+                    sv_play_from_beginning(slot);
+                }
+                let note = |note| SunvoxNote {
+                    note,
+                    module: 2,
+                    ..Default::default()
+                };
+
+                let mut clock = SampleClock::new(44100);
+                let mut audio = Vec::new();
+                let mut buffer = vec![0.0f32; 441 * 2];
+                for block in 0..30 {
+                    let mut events = Vec::new();
+                    match block {
+                        10 => events.push(TimedEvent {
+                            offset: 123,
+                            track: 0,
+                            event: note(49),
+                        }),
+                        20 => send_event(slot, 1, note(56)).unwrap(),
+                        _ => {}
+                    }
+                    render_block(slot, &mut buffer, 2, clock.block(), &events).unwrap();
+                    clock.advance(441);
+                    audio.extend_from_slice(&buffer);
+                    if let Some(pause) = pause {
+                        std::thread::sleep(pause);
+                    }
+                }
+                unsafe {
+                    sv_stop(slot);
+                    sv_stop(slot);
+                }
+                assert_eq!(clock.frames(), 30 * 441);
+                takes.push(audio);
+            }
+            assert!(takes[0] == takes[1], "offline and realtime renders differ");
+        });
+    }
+}
@@ -0,0 +1,189 @@
+// Instrument mode
+// A minimal project built around one sample or module preset, played from MIDI
+//
+// The project has no patterns and a single module connected to the Output:
+// a Sampler for sample files (WAV, OGG, XI, ...), or the module saved in a
+// `.sunsynth` preset. The file contents are kept in `Instrument`, which
+// serializes them as base64 so the instrument can be stored in plugin state
+// and rebuilt without the original file.
+
+use crate::error::Result;
+use crate::module_graph::{clear_modules, connect_modules, load_module_bytes, OUTPUT_MODULE};
+use crate::patterns::clear_patterns;
+use crate::project::set_song_name;
+use crate::sampler::new_sampler_from_bytes;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// First bytes of a `.sunsynth` module preset
+pub const SUNSYNTH_SIGNATURE: &[u8; 4] = b"SSYN";
+
+/// A sample or `.sunsynth` file and its name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrument {
+    /// File name without the extension
+    pub name: String,
+    #[serde(with = "base64")]
+    pub data: Vec<u8>,
+}
+
+impl Instrument {
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+        }
+    }
+
+    /// Read a sample or `.sunsynth` file; the instrument is named after it
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map_or_else(|| "Instrument".into(), |s| s.to_string_lossy());
+        Ok(Self::new(&name, std::fs::read(path)?))
+    }
+
+    /// Whether the file is a `.sunsynth` module preset rather than a sample
+    pub fn is_preset(&self) -> bool {
+        self.data.starts_with(SUNSYNTH_SIGNATURE)
+    }
+
+    /// Replace the project in `slot` with a module playing this file,
+    /// connected to the Output; returns the module number
+    pub fn build(&self, slot: i32) -> Result<i32> {
+        clear_patterns(slot)?;
+        clear_modules(slot)?;
+        set_song_name(slot, &self.name)?;
+        let module = if self.is_preset() {
+            load_module_bytes(slot, &self.data, 256, 512, 0)?
+        } else {
+            new_sampler_from_bytes(slot, &self.name, &self.data, 256, 512)?
+        };
+        connect_modules(slot, module, OUTPUT_MODULE)?;
+        Ok(module)
+    }
+}
+
+/// Standard base64 with padding, for `#[serde(with = "base64")]`
+mod base64 {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn encode(data: &[u8]) -> String {
+        let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+        for chunk in data.chunks(3) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(text: &str) -> Option<Vec<u8>> {
+        let text = text.trim_end_matches('=').as_bytes();
+        let mut out = Vec::with_capacity(text.len() * 3 / 4);
+        for chunk in text.chunks(4) {
+            if chunk.len() == 1 {
+                return None;
+            }
+            let mut bits = 0u32;
+            for (i, &c) in chunk.iter().enumerate() {
+                let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+                bits |= value << (18 - 6 * i);
+            }
+            for i in 0..chunk.len() - 1 {
+                out.push((bits >> (16 - 8 * i)) as u8);
+            }
+        }
+        Some(out)
+    }
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        decode(&text).ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_timing::{send_event, set_immediate_events};
+    use crate::module_graph::{module_inputs, module_type, ModuleGraph};
+    use crate::patterns::patterns;
+    use crate::project::load_project;
+    use crate::scope::ScopeCapture;
+    use crate::sunvox_ffi::*;
+    use crate::test_support::{render, resource_path, with_engine};
+
+    #[test]
+    fn test_build_from_stored_file() {
+        for (data, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+        ] {
+            assert_eq!(base64::encode(data), text);
+            assert_eq!(base64::decode(text).unwrap(), data);
+        }
+        assert!(base64::decode("Zm9v!").is_none());
+
+        let instrument = Instrument::from_file(resource_path("drums.ogg")).unwrap();
+        assert_eq!(instrument.name, "drums");
+        assert!(!instrument.is_preset());
+        let json = serde_json::to_string(&instrument).unwrap();
+        let stored: Instrument = serde_json::from_str(&json).unwrap();
+        assert_eq!(stored, instrument);
+        let preset = Instrument::from_file(resource_path("organ.sunsynth")).unwrap();
+        assert!(preset.is_preset());
+
+        with_engine(|slot| {
+            set_immediate_events(slot).unwrap();
+            for (instrument, module_type_name) in [(&stored, "Sampler"), (&preset, "SpectraVoice")]
+            {
+                load_project(slot, &resource_path("song01.sunvox")).unwrap();
+                let module = instrument.build(slot).unwrap();
+                assert!(patterns(slot).is_empty());
+                assert_eq!(ModuleGraph::read(slot).unwrap().nodes.len(), 2);
+                assert_eq!(module_type(slot, module).as_deref(), Some(module_type_name));
+                assert_eq!(module_inputs(slot, OUTPUT_MODULE), [module]);
+
+                let mut capture = ScopeCapture::new(4096);
+                let output = capture.add(OUTPUT_MODULE, 0);
+                let note = SunvoxNote {
+                    note: 61,
+                    vel: 129,
+                    module: module as u16 + 1,
+                    ..Default::default()
+                };
+                send_event(slot, 0, note).unwrap();
+                for _ in 0..4 {
+                    render(1024);
+                    capture.capture(slot, 1024);
+                }
+                assert!(output.peak(4096) > 0.01);
+                let off = SunvoxNote {
+                    note: NOTECMD_NOTE_OFF,
+                    ..Default::default()
+                };
+                send_event(slot, 0, off).unwrap();
+                render(1024);
+            }
+        });
+    }
+}
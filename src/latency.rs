@@ -0,0 +1,52 @@
+// Processing latency
+// Works out the delay the render configuration adds, to report to the host
+//
+// SunVox renders straight into the host's buffers, and `render_block`
+// applies every event on its own frame of the block it arrives in instead of
+// holding it back, so nothing is delayed as long as SunVox runs at the host's
+// sample rate. There is no resampler: when the rates differ the project would
+// play at the wrong speed and pitch, and the configuration is rejected.
+
+use crate::sunvox_ffi::sv_get_sample_rate;
+
+/// How SunVox renders for the host, as far as it decides the latency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderConfig {
+    /// Sample rate of the host's buffers
+    pub host_rate: u32,
+    /// Sample rate SunVox renders at
+    pub engine_rate: u32,
+}
+
+impl RenderConfig {
+    /// Configuration of the initialized engine rendering for a host at
+    /// `host_rate` (SunVox may not use the rate `sv_init` was given)
+    pub fn current(host_rate: u32) -> Self {
+        Self {
+            host_rate,
+            engine_rate: unsafe { sv_get_sample_rate() }.max(0) as u32,
+        }
+    }
+
+    /// Latency in host frames to report with `set_latency_samples`, or
+    /// `None` when the configuration can't play correctly
+    pub fn latency(&self) -> Option<u32> {
+        (self.engine_rate == self.host_rate).then_some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::with_engine;
+
+    #[test]
+    fn test_latency_of_render_config() {
+        with_engine(|_| {
+            let config = RenderConfig::current(44100);
+            assert_eq!(config.engine_rate, 44100);
+            assert_eq!(config.latency(), Some(0));
+            assert_eq!(RenderConfig::current(48000).latency(), None);
+        });
+    }
+}
@@ -0,0 +1,432 @@
+// Pattern launcher
+// Plays patterns as clips triggered by MIDI notes instead of running the song
+//
+// A note launches the pattern named after it in SunVox's notation, where MIDI
+// note 60 is "C5" and sharps are written "C#5" or "c5". While the launcher is
+// active every pattern is muted except the clips that play. A launch takes
+// effect at the next bar of the playing clip: the song jumps to the first
+// line of the new clip, which then loops until another one replaces it.
+// Clips that start on the same line play together, so launching one at the
+// line of the playing clip adds it as a layer.
+//
+// Bars and loop points are found on the plugin's `Playhead`, and the block is
+// split half a line before them to switch the mutes and send the 0x31 jump,
+// which the engine carries out exactly where the line ends.
+
+use crate::automation::{is_playing, Playhead};
+use crate::error::Result;
+use crate::patterns::{find_pattern, is_pattern_muted, pattern_info, patterns, set_pattern_mute};
+use crate::project::{play, rewind, song_tpl};
+use crate::slot::SlotLock;
+use crate::transport::jump_after_line;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Pattern names that launch on a MIDI note, e.g. ["C#5", "c5"] for note 61
+pub fn clip_names(note: u8) -> Vec<String> {
+    let name = NOTE_NAMES[note as usize % 12];
+    let octave = note / 12;
+    let mut names = vec![format!("{}{}", name, octave)];
+    if let Some(natural) = name.strip_suffix('#') {
+        names.push(format!("{}{}", natural.to_lowercase(), octave));
+    }
+    names
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Clip {
+    pattern: i32,
+    /// First line on the timeline
+    x: i32,
+    lines: i32,
+}
+
+/// Clips waiting for the next bar
+#[derive(Debug, Clone)]
+struct Launch {
+    clips: Vec<Clip>,
+    /// Offset into the playing clips at which the launch happens
+    due: i32,
+}
+
+/// Lines in a bar of `beats_per_bar` beats at the project's speed
+///
+/// A beat is 24 SunVox ticks, as in the engine's BPM.
+pub fn bar_lines(slot: i32, beats_per_bar: i32) -> i32 {
+    (24 / song_tpl(slot).max(1)).max(1) * beats_per_bar.max(1)
+}
+
+/// Launches patterns as looping clips
+#[derive(Debug, Clone)]
+pub struct PatternLauncher {
+    /// Lines per bar (see `bar_lines`); launches wait for the next bar line
+    pub bar_lines: i32,
+    active: bool,
+    /// Mute state of every pattern before the launcher took over
+    saved_mutes: Vec<(i32, bool)>,
+    playing: Vec<Clip>,
+    pending: Option<Launch>,
+}
+
+impl Default for PatternLauncher {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+impl PatternLauncher {
+    pub fn new(bar_lines: i32) -> Self {
+        Self {
+            bar_lines,
+            active: false,
+            saved_mutes: Vec::new(),
+            playing: Vec::new(),
+            pending: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Patterns of the clips that are playing
+    pub fn playing(&self) -> Vec<i32> {
+        self.playing.iter().map(|c| c.pattern).collect()
+    }
+
+    /// Take over the project: mute every pattern until clips are launched
+    pub fn start(&mut self, slot: i32) -> Result<()> {
+        if self.active {
+            return Ok(());
+        }
+        let _lock = SlotLock::new(slot);
+        self.saved_mutes = patterns(slot)
+            .into_iter()
+            .map(|p| (p, is_pattern_muted(slot, p)))
+            .collect();
+        for &(pattern, _) in &self.saved_mutes {
+            set_pattern_mute(slot, pattern, true)?;
+        }
+        self.active = true;
+        Ok(())
+    }
+
+    /// Give the project back, with the patterns muted as they were before
+    pub fn stop(&mut self, slot: i32) -> Result<()> {
+        if !self.active {
+            return Ok(());
+        }
+        self.active = false;
+        self.playing.clear();
+        self.pending = None;
+        let _lock = SlotLock::new(slot);
+        for (pattern, muted) in self.saved_mutes.drain(..) {
+            if pattern_info(slot, pattern).is_some() {
+                set_pattern_mute(slot, pattern, muted)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Launch the clip for a MIDI note at the next bar of the playing clips,
+    /// `offset` frames into the block
+    ///
+    /// Returns false when the launcher is inactive or no pattern has the
+    /// note's name.
+    pub fn launch(
+        &mut self,
+        slot: i32,
+        note: u8,
+        playhead: &mut Playhead,
+        offset: u32,
+    ) -> Result<bool> {
+        if !self.active {
+            return Ok(false);
+        }
+        let Some(clip) = clip_names(note)
+            .iter()
+            .find_map(|name| find_pattern(slot, name).ok())
+            .and_then(|pattern| pattern_info(slot, pattern))
+            .map(|info| Clip {
+                pattern: info.id,
+                x: info.x,
+                lines: info.lines.max(1),
+            })
+        else {
+            return Ok(false);
+        };
+
+        let position = playhead.position_at(offset);
+        let Some(position) = position.filter(|_| !self.playing.is_empty()) else {
+            // Nothing to wait for: start right away
+            self.switch(slot, vec![clip])?;
+            rewind(slot, clip.x)?;
+            if !is_playing(slot) {
+                play(slot)?;
+            }
+            playhead.jump(clip.x);
+            return Ok(true);
+        };
+
+        let due = self.next_bar(position.line - self.origin());
+        match &mut self.pending {
+            Some(launch) if launch.clips[0].x == clip.x => {
+                if !launch.clips.contains(&clip) {
+                    launch.clips.push(clip);
+                }
+            }
+            pending => {
+                *pending = Some(Launch {
+                    clips: vec![clip],
+                    due,
+                })
+            }
+        }
+        Ok(true)
+    }
+
+    /// Find the frame of the block at which `cue` has to be called, if the
+    /// next bar with a launch or the loop point comes up in it; call before
+    /// rendering each block of `frames`
+    ///
+    /// A bar the song is already past (it was moved) is made up for at once
+    /// with a rewind.
+    pub fn update(
+        &mut self,
+        slot: i32,
+        playhead: &mut Playhead,
+        frames: u32,
+    ) -> Result<Option<u32>> {
+        if !self.active || self.playing.is_empty() || playhead.is_jump_pending() {
+            return Ok(None);
+        }
+        let Some(position) = playhead.position_at(0) else {
+            // Autostop ended the song: loop
+            let line = self.origin();
+            rewind(slot, line)?;
+            play(slot)?;
+            playhead.jump(line);
+            return Ok(None);
+        };
+
+        let boundary = self.origin() + self.pending.as_ref().map_or(self.length(), |l| l.due);
+        if position.line >= boundary {
+            // Lines played past the bar, kept to stay on the beat
+            let carry = position.line - boundary;
+            if let Some(line) = self.next(slot)? {
+                let line = line + carry % self.length();
+                rewind(slot, line)?;
+                playhead.jump(line);
+            }
+            return Ok(None);
+        }
+        // Half a line early, as the playhead may be a little off
+        let cue = playhead.frames_until(boundary as f64 - 0.5);
+        Ok(cue.filter(|&cue| cue < frames))
+    }
+
+    /// Switch to the launched clips, or loop, `offset` frames into the block
+    /// as found by `update`
+    ///
+    /// Returns the line the song jumps to when the playing line ends, if any.
+    pub fn cue(&mut self, slot: i32, playhead: &mut Playhead, offset: u32) -> Result<Option<i32>> {
+        let line = self.next(slot)?;
+        if let Some(line) = line {
+            jump_after_line(slot, line)?;
+            playhead.jump_after_line(offset, line);
+        }
+        Ok(line)
+    }
+
+    /// Carry out the launch due at the next bar, or loop the playing clips
+    ///
+    /// Returns the line the song continues at, or `None` for layers joining
+    /// in place.
+    fn next(&mut self, slot: i32) -> Result<Option<i32>> {
+        match self.pending.take() {
+            Some(launch) if launch.clips[0].x == self.origin() => {
+                // Layers join the playing clips in place, or at the loop point
+                let looped = launch.due >= self.length();
+                let mut clips = self.playing.clone();
+                clips.extend(
+                    launch
+                        .clips
+                        .into_iter()
+                        .filter(|c| !self.playing.contains(c)),
+                );
+                self.switch(slot, clips)?;
+                Ok(looped.then(|| self.origin()))
+            }
+            Some(launch) => {
+                self.switch(slot, launch.clips)?;
+                Ok(Some(self.origin()))
+            }
+            None => Ok(Some(self.origin())),
+        }
+    }
+
+    /// Make `clips` the playing clips
+    fn switch(&mut self, slot: i32, clips: Vec<Clip>) -> Result<()> {
+        let _lock = SlotLock::new(slot);
+        for clip in &self.playing {
+            if !clips.contains(clip) {
+                set_pattern_mute(slot, clip.pattern, true)?;
+            }
+        }
+        for clip in &clips {
+            set_pattern_mute(slot, clip.pattern, false)?;
+        }
+        self.playing = clips;
+        Ok(())
+    }
+
+    fn origin(&self) -> i32 {
+        self.playing.first().map_or(0, |c| c.x)
+    }
+
+    fn length(&self) -> i32 {
+        self.playing.iter().map(|c| c.lines).max().unwrap_or(1)
+    }
+
+    /// Offset of the bar line after `offset`, or the loop point if that comes
+    /// first
+    fn next_bar(&self, offset: i32) -> i32 {
+        let bar = self.bar_lines.max(1);
+        ((offset.max(0) / bar + 1) * bar).min(self.length())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{set_controller_value, CtlScale};
+    use crate::event_timing::{render_block_split, ticks, BlockTime};
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::patterns::{clear_patterns, new_pattern, set_pattern_event};
+    use crate::project::{apply_pending_events, set_song_speed};
+    use crate::sunvox_ffi::*;
+
+    use crate::test_support::with_engine;
+
+    /// Frames per line at 125 BPM and 6 ticks per line
+    const LINE: usize = 5292;
+
+    #[test]
+    fn test_launch_on_next_bar() {
+        assert_eq!(clip_names(60), ["C5"]);
+        assert_eq!(clip_names(61), ["C#5", "c5"]);
+
+        with_engine(|slot| {
+            assert_eq!(bar_lines(slot, 3), 12);
+            clear_patterns(slot).unwrap();
+            clear_modules(slot).unwrap();
+            set_song_speed(slot, 125, 6).unwrap();
+            apply_pending_events();
+            assert_eq!(bar_lines(slot, 4), 16);
+
+            // Clips beep for two lines on the lines given, with no attack or
+            // release, so the beeps tell when the clips played
+            let beep = new_module(slot, "Generator", "Beep", 0, 0, 0).unwrap();
+            connect_modules(slot, beep, OUTPUT_MODULE).unwrap();
+            for ctl in [3, 4] {
+                set_controller_value(slot, beep, ctl, 0, CtlScale::Real).unwrap();
+            }
+            let clip = |name: &str, x: i32, lines: i32, beeps: &[i32]| {
+                let pattern = new_pattern(slot, name, x, 0, 1, lines).unwrap();
+                for &line in beeps {
+                    for (line, note) in [(line, 60), (line + 2, NOTECMD_NOTE_OFF)] {
+                        let event = SunvoxNote {
+                            note,
+                            module: beep as u16 + 1,
+                            ..Default::default()
+                        };
+                        set_pattern_event(slot, pattern, 0, line, event).unwrap();
+                    }
+                }
+                pattern
+            };
+            let intro = clip("C5", 0, 32, &[0, 8, 16, 24]);
+            let bass = clip("D5", 0, 32, &[]);
+            let verse = clip("c5", 64, 8, &[0, 4]);
+            let other = clip("Other", 96, 8, &[]);
+            set_pattern_mute(slot, other, true).unwrap();
+
+            // Plays blocks of `block` frames through `update` and `cue`,
+            // launching `notes` at the start of the first block after their
+            // line, and returns the lines (from the first) at which beeps
+            // started
+            let play = |launcher: &mut PatternLauncher, block: usize, notes: &[(usize, u8)]| {
+                let mut playhead = Playhead::new(44100);
+                let mut buffer = vec![0.0f32; block * 2];
+                let mut frame = 0;
+                let mut silent = usize::MAX;
+                let mut beeps: Vec<usize> = Vec::new();
+                while frame < 42 * LINE {
+                    playhead.start_block(slot);
+                    for &(line, note) in notes {
+                        if (frame..frame + block).contains(&(line * LINE)) {
+                            assert!(launcher.launch(slot, note, &mut playhead, 0).unwrap());
+                        }
+                    }
+                    let cue = launcher.update(slot, &mut playhead, block as u32).unwrap();
+                    let time = BlockTime::new(ticks(), 44100);
+                    render_block_split(slot, &mut buffer, 2, time, &[], cue, || {
+                        let offset = cue.unwrap_or(0);
+                        launcher.cue(slot, &mut playhead, offset).map(|_| ())
+                    })
+                    .unwrap();
+                    playhead.advance(block as u32);
+
+                    for (i, sample) in buffer.iter().step_by(2).enumerate() {
+                        if sample.abs() < 1e-4 {
+                            silent += 1;
+                            continue;
+                        }
+                        if silent > 1000 {
+                            beeps.push(frame + i);
+                        }
+                        silent = 0;
+                    }
+                    frame += block;
+                }
+                unsafe {
+                    sv_stop(slot);
+                    sv_stop(slot);
+                }
+                let first = beeps[0];
+                beeps.iter().map(|&b| b - first).collect::<Vec<_>>()
+            };
+
+            let mut launcher = PatternLauncher::new(8);
+            let mut playhead = Playhead::new(44100);
+            assert!(!launcher.launch(slot, 60, &mut playhead, 0).unwrap());
+            launcher.start(slot).unwrap();
+            assert!((0..4).all(|p| is_pattern_muted(slot, p)));
+            assert!(!launcher.launch(slot, 70, &mut playhead, 0).unwrap());
+
+            for block in [256, 2048, 4096] {
+                // The first clip starts at once, a layer on the same line
+                // joins at the next bar, in place, and another scene replaces
+                // them at the bar after its launch, then loops
+                let notes = [(0, 60), (3, 62), (11, 61)];
+                let beeps = play(&mut launcher, block, &notes);
+                let lines = [0, 8, 16, 20, 24, 28, 32, 36, 40];
+                assert_eq!(beeps.len(), lines.len(), "{}: {:?}", block, beeps);
+                for (beep, line) in beeps.iter().zip(lines) {
+                    let error = *beep as i64 - (line * LINE) as i64;
+                    assert!(error.abs() < 32, "{}: {:?}", block, beeps);
+                }
+                assert_eq!(launcher.playing(), [verse]);
+                assert!(is_pattern_muted(slot, intro) && is_pattern_muted(slot, bass));
+                assert!(!is_pattern_muted(slot, verse));
+
+                launcher.stop(slot).unwrap();
+                assert!((0..3).all(|p| !is_pattern_muted(slot, p)));
+                assert!(is_pattern_muted(slot, other));
+                launcher.start(slot).unwrap();
+            }
+        });
+    }
+}
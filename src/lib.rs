@@ -1,12 +1,64 @@
 use nih_plug::prelude::*;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::ffi::CString;
 use std::fs::OpenOptions;
 use std::io::Write;
 
 // SunVox FFI bindings
-mod sunvox_ffi;
+pub mod sunvox_ffi;
 use sunvox_ffi::*;
+use automation::{is_playing, AutomationRecorder, Playhead};
+use controllers::{pattern_value, set_controller_value, CtlScale};
+use event_timing::{render_block_split, set_immediate_events, SampleClock, TimedEvent};
+use latency::RenderConfig;
+use launcher::PatternLauncher;
+use live_input::LiveVoices;
+use metamodule::ChannelLayers;
+use metering::OutputMeter;
+use mpe::{controller_event, Expression, MpeConfig, CC_TIMBRE, MANAGER_BEND_RANGE};
+use note_recording::{NoteRecorder, Quantize, RecordMode, RecordTarget};
+use instrument::Instrument;
+use scala::ScalaTuning;
+use scope::ScopeCapture;
+use silence::{is_audible, Activity, SilenceDetector};
+use transport::{EndOfSong, LoopRegion, SongPosition};
+use tuning::{master_tune_cents, GlobalTuning, ModuleTuning};
+
+// Safe wrappers over the FFI bindings
+pub mod automation;
+pub mod controllers;
+pub mod curves;
+pub mod error;
+pub mod event_timing;
+pub mod graph_export;
+pub mod instrument;
+pub mod latency;
+pub mod launcher;
+pub mod live_input;
+pub mod metamodule;
+pub mod metering;
+pub mod midi_export;
+pub mod midi_file;
+pub mod midi_import;
+pub mod module_graph;
+pub mod mpe;
+pub mod note_recording;
+pub mod patterns;
+pub mod pitch;
+pub mod project;
+pub mod project_format;
+pub mod sampler;
+pub mod scala;
+pub mod scope;
+pub mod silence;
+pub mod slot;
+pub mod transport;
+pub mod tuning;
+pub mod vorbis_player;
+
+#[cfg(test)]
+mod test_support;
 
 // Debug logging helper
 fn debug_log(msg: &str) {
@@ -20,6 +72,75 @@ fn debug_log(msg: &str) {
     }
 }
 
+/// Build the instrument stored in the plugin state in `slot`, or load the
+/// example project if there is none (or it failed)
+fn build_project(params: &SunVoxPluginParams, slot: i32) -> BuiltProject {
+    let instrument_module = load_instrument(params, slot);
+    let loaded = instrument_module.is_some() || load_song(slot);
+    let tuning = GlobalTuning::capture(slot).unwrap_or_else(|e| {
+        nih_log!("⚠ Reading the module tuning failed: {}", e);
+        GlobalTuning::default()
+    });
+    BuiltProject {
+        slot,
+        loaded,
+        instrument_module,
+        tuning,
+    }
+}
+
+/// Build the instrument stored in the plugin state, if any, and return the
+/// module playing it
+fn load_instrument(params: &SunVoxPluginParams, slot: i32) -> Option<i32> {
+    let instrument = params.instrument.read().ok()?.clone()?;
+    match instrument.build(slot) {
+        Ok(module) => {
+            debug_log(&format!("SUCCESS: Instrument {:?} built", instrument.name));
+            nih_log!("✓ Playing {:?} from MIDI", instrument.name);
+            Some(module)
+        }
+        Err(e) => {
+            debug_log(&format!("ERROR: instrument failed: {}", e));
+            nih_log!("⚠ Failed to load instrument {:?}: {}", instrument.name, e);
+            None
+        }
+    }
+}
+
+/// Load the example SunVox project
+fn load_song(slot: i32) -> bool {
+    // Try to find the song in the bundle's Resources directory (macOS)
+    // or fall back to relative path (Linux)
+    debug_log("Attempting to load SunVox project...");
+    let song_paths = [
+        "/Users/mark/Library/Audio/Plug-Ins/CLAP/sunvox_clap.clap/Contents/Resources/song01.sunvox",
+        "song01.sunvox",
+        "Resources/song01.sunvox",
+    ];
+
+    for path_str in &song_paths {
+        debug_log(&format!("Trying path: {}", path_str));
+        let project_path = match CString::new(*path_str) {
+            Ok(p) => p,
+            Err(_) => {
+                debug_log("  Failed to create CString");
+                continue;
+            }
+        };
+
+        let result = unsafe { sv_load(slot, project_path.as_ptr()) };
+        if result == 0 {
+            debug_log(&format!("SUCCESS: Loaded from {}", path_str));
+            nih_log!("✓ SunVox project loaded from: {}", path_str);
+            return true;
+        } else {
+            debug_log(&format!("  Failed with error: {}", result));
+            nih_log!("⚠ Failed to load from {}: error {}", path_str, result);
+        }
+    }
+    false
+}
+
 /// A CLAP plugin integrating SunVox modular synthesizer.
 /// Phase 2: Now initializes SunVox for audio generation.
 struct SunVoxPlugin {
@@ -29,18 +150,404 @@ struct SunVoxPlugin {
     sunvox_initialized: bool,
     sunvox_slot: i32,
     sample_rate: f32,
+
+    // Output time passed to SunVox, advanced by the rendered frame count
+    // (started once SunVox is initialized)
+    clock: Option<SampleClock>,
+    // Song position of the frames of the block being rendered
+    playhead: Playhead,
+
+    // Writes controller changes into patterns while "Record Automation" is on
+    automation: AutomationRecorder,
+    // Module, controller and pattern value last sent by each macro
+    macros_sent: [Option<(i32, i32, u16)>; MACRO_COUNT],
+
+    // Tracks of the virtual pattern playing the held MIDI notes
+    voices: LiveVoices,
+    // Writes MIDI notes into a pattern while "Record Notes" is on
+    note_recorder: NoteRecorder,
+
+    // Plays patterns as clips triggered by MIDI notes in "Pattern Launcher" mode
+    launcher: PatternLauncher,
+
+    // Start line and loop region, and the last value of the "Jump" trigger
+    song_position: SongPosition,
+    jump_pressed: bool,
+
+    // Tells when a stopped song has faded out
+    silence: SilenceDetector,
+
+    // Peak and RMS of the last output block, readable from other threads
+    meter: Arc<OutputMeter>,
+    // Oscilloscope captures of module channels (the master output by default)
+    scope: ScopeCapture,
+
+    // Module playing the MIDI input in instrument mode
+    instrument_module: Option<i32>,
+    // MetaModules playing the notes of MIDI channels that have a layer
+    layers: ChannelLayers,
+    // Projects built off the audio thread after the instrument changed, and
+    // whether one is on its way
+    builds: Arc<Mutex<ProjectBuilds>>,
+    building: bool,
+
+    // Tuning of the modules that take notes, offset by the tuning parameters
+    tuning: GlobalTuning,
 }
 
+/// Longest time the host keeps processing after the song stopped, while the
+/// modules fade out
+const RELEASE_TAIL_SECONDS: f32 = 2.0;
+
+/// Environment variable naming a sample or `.sunsynth` file for the
+/// instrument mode, used while the plugin state holds no instrument
+const INSTRUMENT_FILE_ENV: &str = "SUNVOX_CLAP_INSTRUMENT";
+
+/// Work done on the background thread
+///
+/// Building a project allocates and may read files, so after the instrument
+/// changed it is built in the slot that isn't playing, and `process` only
+/// swaps the two slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProjectTask {
+    /// Open a slot and build the project in it
+    Build(i32),
+    /// Close a slot that no longer plays
+    CloseSlot(i32),
+}
+
+/// A project built in a slot, ready to be swapped in
+#[derive(Debug)]
+struct BuiltProject {
+    slot: i32,
+    /// Whether the instrument or the example project loaded
+    loaded: bool,
+    instrument_module: Option<i32>,
+    tuning: GlobalTuning,
+}
+
+/// Hands built projects over from the background thread
+///
+/// A build holds the lock while it works, so `process` (which only tries the
+/// lock) skips it until the project is ready, and `deactivate` waits for it
+/// before shutting SunVox down.
+#[derive(Debug, Default)]
+struct ProjectBuilds {
+    /// Whether SunVox is initialized, so slots may be built and closed
+    engine: bool,
+    built: Option<BuiltProject>,
+}
+
+/// Environment variables naming a Scala scale (`.scl`) and keyboard mapping
+/// (`.kbm`) to tune MIDI notes to, used while the plugin state holds no tuning
+const SCALE_FILE_ENV: &str = "SUNVOX_CLAP_SCALE";
+const KEYBOARD_MAP_FILE_ENV: &str = "SUNVOX_CLAP_KEYBOARD_MAP";
+
+/// End-of-song policies offered by the "End of Song" parameter
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum EndOfSongParam {
+    #[name = "Stop"]
+    Stop,
+    #[name = "Loop to Start"]
+    LoopToStart,
+    #[name = "Loop to Marker"]
+    LoopToMarker,
+    #[name = "Hold Silence"]
+    HoldSilence,
+}
+
+/// Number of macro parameters
+const MACRO_COUNT: usize = 8;
+
+/// Polyphonic modulation ID of the "Modulation" parameter
+const MODULATION_POLY_ID: u32 = 0;
+
+/// A host-automatable value driving one module controller
 #[derive(Params)]
-struct SunVoxPluginParams {}
+struct MacroParams {
+    /// Position within the controller's range
+    #[id = "macro"]
+    pub value: FloatParam,
+
+    /// Module of the controller (0 is off)
+    #[id = "macro_module"]
+    pub module: IntParam,
+
+    /// Controller of the module, counting from 1 (0 is off)
+    #[id = "macro_ctl"]
+    pub ctl: IntParam,
+}
+
+impl MacroParams {
+    fn new(index: usize) -> Self {
+        let name = format!("Macro {}", index + 1);
+        Self {
+            value: FloatParam::new(&name, 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(1))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            module: IntParam::new(format!("{} Module", name), 0, IntRange::Linear { min: 0, max: 255 })
+                .with_value_to_string(Arc::new(controller_to_string)),
+            ctl: IntParam::new(format!("{} Controller", name), 0, IntRange::Linear { min: 0, max: 32 })
+                .with_value_to_string(Arc::new(controller_to_string)),
+        }
+    }
+
+    /// Module and controller the macro drives, if set
+    fn target(&self) -> Option<(i32, i32)> {
+        let (module, ctl) = (self.module.value(), self.ctl.value());
+        (module > 0 && ctl > 0).then_some((module, ctl - 1))
+    }
+}
+
+#[derive(Params)]
+struct SunVoxPluginParams {
+    /// Record the changes of the macros into "Automation" patterns during
+    /// playback
+    #[id = "record_automation"]
+    pub record_automation: BoolParam,
+
+    /// Macros setting module controllers from host automation
+    #[nested(array, group = "Macros")]
+    pub macros: [MacroParams; MACRO_COUNT],
+
+    /// Module that plays incoming MIDI notes
+    #[id = "midi_module"]
+    pub midi_module: IntParam,
+
+    /// Record incoming MIDI notes into the record pattern during playback
+    #[id = "record_notes"]
+    pub record_notes: BoolParam,
+
+    /// Grid recorded notes are rounded to, in lines (0 keeps 32nds of a line)
+    #[id = "record_quantize"]
+    pub record_quantize: IntParam,
+
+    /// Erase the record tracks as the playhead passes instead of overdubbing
+    #[id = "record_replace"]
+    pub record_replace: BoolParam,
+
+    #[id = "record_pattern"]
+    pub record_pattern: IntParam,
+
+    #[id = "record_first_track"]
+    pub record_first_track: IntParam,
+
+    #[id = "record_last_track"]
+    pub record_last_track: IntParam,
+
+    /// MIDI notes launch the patterns named after them instead of playing
+    #[id = "pattern_launcher"]
+    pub pattern_launcher: BoolParam,
+
+    /// Line playback starts at
+    #[id = "start_line"]
+    pub start_line: IntParam,
+
+    #[id = "loop_enabled"]
+    pub loop_enabled: BoolParam,
+
+    #[id = "loop_start"]
+    pub loop_start: IntParam,
+
+    /// First line after the loop region
+    #[id = "loop_end"]
+    pub loop_end: IntParam,
+
+    #[id = "jump_line"]
+    pub jump_line: IntParam,
+
+    /// Moves the playhead to the jump line when switched on
+    #[id = "jump"]
+    pub jump: BoolParam,
+
+    #[id = "end_of_song"]
+    pub end_of_song: EnumParam<EndOfSongParam>,
+
+    /// Line "Loop to Marker" continues from
+    #[id = "end_marker"]
+    pub end_marker: IntParam,
+
+    /// Semitones added to every note
+    #[id = "transpose"]
+    pub transpose: IntParam,
+
+    /// Cents added to every note
+    #[id = "fine_tune"]
+    pub fine_tune: FloatParam,
+
+    /// Pitch of A4 the project is tuned to
+    #[id = "master_tune"]
+    pub master_tune: FloatParam,
+
+    /// Semitones of a full pitch bend on MPE member channels (MIDI channels
+    /// 2..16); channel 1 always bends by 2
+    #[id = "bend_range"]
+    pub bend_range: IntParam,
+
+    /// Controller of the MIDI module set by note pressure (0 is off)
+    #[id = "pressure_ctl"]
+    pub pressure_ctl: IntParam,
+
+    /// Controller of the MIDI module set by timbre, CC 74 (0 is off)
+    #[id = "timbre_ctl"]
+    pub timbre_ctl: IntParam,
+
+    /// Controller of the MIDI module set by note panning (0 is off)
+    #[id = "pan_ctl"]
+    pub pan_ctl: IntParam,
+
+    /// Value sent to the modulation controller, which hosts with polyphonic
+    /// modulation can offset per note
+    #[id = "modulation"]
+    pub modulation: FloatParam,
+
+    /// Controller of the MIDI module set by the modulation (0 is off)
+    #[id = "modulation_ctl"]
+    pub modulation_ctl: IntParam,
+
+    /// Sample or synth preset played instead of the project, stored with the
+    /// plugin state
+    #[persist = "instrument"]
+    pub instrument: RwLock<Option<Instrument>>,
+
+    /// Set when `instrument` changed and the project has to be rebuilt
+    instrument_changed: AtomicBool,
+
+    /// Scala tuning MIDI notes are played in instead of 12-TET, stored with
+    /// the plugin state
+    #[persist = "scale"]
+    pub scale: RwLock<Option<ScalaTuning>>,
+
+    /// Set when `scale` changed
+    scale_changed: AtomicBool,
+}
+
+impl SunVoxPluginParams {
+    /// Play a sample (WAV, OGG, XI, ...) or a `.sunsynth` preset from MIDI
+    /// instead of the project; `None` goes back to the project
+    pub fn set_instrument(&self, instrument: Option<Instrument>) {
+        if let Ok(mut stored) = self.instrument.write() {
+            *stored = instrument;
+            self.instrument_changed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Read a sample or `.sunsynth` file and play it from MIDI
+    pub fn load_instrument_file(&self, path: &str) -> std::io::Result<()> {
+        self.set_instrument(Some(Instrument::from_file(path)?));
+        Ok(())
+    }
+
+    /// Tune MIDI notes to a Scala scale; `None` goes back to 12-TET
+    pub fn set_scale(&self, scale: Option<ScalaTuning>) {
+        if let Ok(mut stored) = self.scale.write() {
+            *stored = scale;
+            self.scale_changed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Read a `.scl` file, and optionally a `.kbm` file, and tune MIDI notes
+    /// to them
+    pub fn load_scale_files(&self, scale: &str, keyboard_map: Option<&str>) -> std::io::Result<()> {
+        self.set_scale(Some(ScalaTuning::from_files(scale, keyboard_map)?));
+        Ok(())
+    }
+}
+
+/// Controller parameters count from 1 like the pattern's controller column
+fn controller_to_string(ctl: i32) -> String {
+    match ctl {
+        0 => String::from("Off"),
+        n => n.to_string(),
+    }
+}
+
+impl Default for SunVoxPluginParams {
+    fn default() -> Self {
+        Self {
+            record_automation: BoolParam::new("Record Automation", false),
+            macros: std::array::from_fn(MacroParams::new),
+            midi_module: IntParam::new("MIDI Module", 1, IntRange::Linear { min: 1, max: 255 }),
+            record_notes: BoolParam::new("Record Notes", false),
+            record_quantize: IntParam::new("Record Quantize", 0, IntRange::Linear { min: 0, max: 16 })
+                .with_value_to_string(Arc::new(|lines| match lines {
+                    0 => String::from("Off"),
+                    1 => String::from("1 line"),
+                    n => format!("{} lines", n),
+                })),
+            record_replace: BoolParam::new("Record Replace", false),
+            record_pattern: IntParam::new("Record Pattern", 0, IntRange::Linear { min: 0, max: 255 }),
+            record_first_track: IntParam::new("Record First Track", 0, IntRange::Linear { min: 0, max: 31 }),
+            record_last_track: IntParam::new("Record Last Track", 31, IntRange::Linear { min: 0, max: 31 }),
+            pattern_launcher: BoolParam::new("Pattern Launcher", false),
+            start_line: IntParam::new("Start Line", 0, IntRange::Linear { min: 0, max: 65535 }),
+            loop_enabled: BoolParam::new("Loop", false),
+            loop_start: IntParam::new("Loop Start", 0, IntRange::Linear { min: 0, max: 65535 }),
+            loop_end: IntParam::new("Loop End", 64, IntRange::Linear { min: 1, max: 65536 }),
+            jump_line: IntParam::new("Jump Line", 0, IntRange::Linear { min: 0, max: 65535 }),
+            jump: BoolParam::new("Jump", false),
+            end_of_song: EnumParam::new("End of Song", EndOfSongParam::LoopToStart),
+            end_marker: IntParam::new("End Marker", 0, IntRange::Linear { min: 0, max: 65535 }),
+            transpose: IntParam::new("Transpose", 0, IntRange::Linear { min: -48, max: 48 })
+                .with_unit(" st"),
+            fine_tune: FloatParam::new("Fine Tune", 0.0, FloatRange::Linear { min: -100.0, max: 100.0 })
+                .with_unit(" ct")
+                .with_step_size(0.1),
+            master_tune: FloatParam::new("Master Tune", 440.0, FloatRange::Linear { min: 415.0, max: 466.0 })
+                .with_unit(" Hz")
+                .with_step_size(0.1),
+            bend_range: IntParam::new("MPE Bend Range", 48, IntRange::Linear { min: 0, max: 96 })
+                .with_unit(" st"),
+            pressure_ctl: IntParam::new("Pressure Controller", 0, IntRange::Linear { min: 0, max: 32 })
+                .with_value_to_string(Arc::new(controller_to_string)),
+            timbre_ctl: IntParam::new("Timbre Controller", 0, IntRange::Linear { min: 0, max: 32 })
+                .with_value_to_string(Arc::new(controller_to_string)),
+            pan_ctl: IntParam::new("Pan Controller", 0, IntRange::Linear { min: 0, max: 32 })
+                .with_value_to_string(Arc::new(controller_to_string)),
+            modulation: FloatParam::new("Modulation", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_poly_modulation_id(MODULATION_POLY_ID)
+                .with_unit(" %")
+                .with_value_to_string(formatters::v2s_f32_percentage(1))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            modulation_ctl: IntParam::new("Modulation Controller", 0, IntRange::Linear { min: 0, max: 32 })
+                .with_value_to_string(Arc::new(controller_to_string)),
+            instrument: RwLock::new(None),
+            instrument_changed: AtomicBool::new(false),
+            scale: RwLock::new(None),
+            scale_changed: AtomicBool::new(false),
+        }
+    }
+}
 
 impl Default for SunVoxPlugin {
     fn default() -> Self {
+        let mut scope = ScopeCapture::default();
+        scope.add(module_graph::OUTPUT_MODULE, 0);
+        scope.add(module_graph::OUTPUT_MODULE, 1);
+
         Self {
-            params: Arc::new(SunVoxPluginParams {}),
+            params: Arc::new(SunVoxPluginParams::default()),
             sunvox_initialized: false,
             sunvox_slot: 0,
             sample_rate: 44100.0,
+            clock: None,
+            playhead: Playhead::new(44100),
+            automation: AutomationRecorder::default(),
+            macros_sent: [None; MACRO_COUNT],
+            voices: LiveVoices::new(),
+            note_recorder: NoteRecorder::default(),
+            launcher: PatternLauncher::default(),
+            song_position: SongPosition::default(),
+            jump_pressed: false,
+            silence: SilenceDetector::new(44100.0, RELEASE_TAIL_SECONDS),
+            meter: Arc::new(OutputMeter::new()),
+            scope,
+            instrument_module: None,
+            layers: ChannelLayers::new(),
+            builds: Arc::new(Mutex::new(ProjectBuilds::default())),
+            building: false,
+            tuning: GlobalTuning::default(),
         }
     }
 }
@@ -65,26 +572,70 @@ impl Plugin for SunVoxPlugin {
         },
     ];
 
-    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = ProjectTask;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let builds = self.builds.clone();
+        Box::new(move |task| {
+            let Ok(mut builds) = builds.lock() else {
+                return;
+            };
+            if !builds.engine {
+                return;
+            }
+            match task {
+                ProjectTask::Build(slot) => {
+                    let result = unsafe { sv_open_slot(slot) };
+                    builds.built = Some(if result != 0 {
+                        nih_log!("⚠ Failed to open SunVox slot: {}", result);
+                        BuiltProject {
+                            slot,
+                            loaded: false,
+                            instrument_module: None,
+                            tuning: GlobalTuning::default(),
+                        }
+                    } else {
+                        if let Err(e) = set_immediate_events(slot) {
+                            debug_log(&format!("ERROR: {}", e));
+                        }
+                        build_project(&params, slot)
+                    });
+                }
+                ProjectTask::CloseSlot(slot) => unsafe {
+                    sv_close_slot(slot);
+                },
+            }
+        })
+    }
+
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
         debug_log("=== SunVox Plugin Initialize START ===");
 
+        // Hosts initialize again after restoring the state (and with it a
+        // stored instrument)
+        if self.sunvox_initialized {
+            self.deactivate();
+        }
+
         // Store the sample rate from the host
         self.sample_rate = buffer_config.sample_rate;
+        self.silence = SilenceDetector::new(buffer_config.sample_rate, RELEASE_TAIL_SECONDS);
+        self.playhead = Playhead::new(buffer_config.sample_rate as u32);
+        self.macros_sent = [None; MACRO_COUNT];
         debug_log(&format!("Sample rate: {}", buffer_config.sample_rate));
 
         // Initialize SunVox in offline mode with float32 audio
@@ -102,7 +653,8 @@ impl Plugin for SunVoxPlugin {
                 flags,
             );
 
-            if result != 0 {
+            // sv_init returns the library version on success, negative on error
+            if result < 0 {
                 debug_log(&format!("ERROR: sv_init failed with code: {} (0x{:x})", result, result));
                 nih_log!("⚠ SunVox initialization failed with code: {} (0x{:x})", result, result);
                 nih_log!("⚠ This may be expected in some environments");
@@ -114,6 +666,25 @@ impl Plugin for SunVoxPlugin {
             debug_log("SUCCESS: sv_init succeeded");
             nih_log!("✓ SunVox initialized successfully at {} Hz", buffer_config.sample_rate);
 
+            // Nothing resamples SunVox's output, so it has to render at the
+            // host's rate
+            let render_config = RenderConfig::current(buffer_config.sample_rate as u32);
+            match render_config.latency() {
+                Some(latency) => context.set_latency_samples(latency),
+                None => {
+                    debug_log(&format!("ERROR: SunVox runs at {} Hz", render_config.engine_rate));
+                    nih_log!(
+                        "⚠ SunVox runs at {} Hz instead of the host's {} Hz",
+                        render_config.engine_rate,
+                        render_config.host_rate
+                    );
+                    nih_log!("⚠ Audio generation will be disabled");
+                    sv_deinit();
+                    self.sunvox_initialized = false;
+                    return true; // Still return true so plugin loads
+                }
+            }
+
             // Open slot 0 for playback
             debug_log(&format!("Opening slot {}", self.sunvox_slot));
             let result = sv_open_slot(self.sunvox_slot);
@@ -128,40 +699,28 @@ impl Plugin for SunVoxPlugin {
             debug_log("SUCCESS: Slot opened");
             nih_log!("✓ SunVox slot {} opened", self.sunvox_slot);
 
-            // Load an example SunVox project for testing
-            // Try to find the song in the bundle's Resources directory (macOS)
-            // or fall back to relative path (Linux)
-            debug_log("Attempting to load SunVox project...");
-            let song_paths = [
-                "/Users/mark/Library/Audio/Plug-Ins/CLAP/sunvox_clap.clap/Contents/Resources/song01.sunvox",
-                "song01.sunvox",
-                "Resources/song01.sunvox",
-            ];
-
-            let mut loaded = false;
-            for path_str in &song_paths {
-                debug_log(&format!("Trying path: {}", path_str));
-                let project_path = match CString::new(*path_str) {
-                    Ok(p) => p,
-                    Err(_) => {
-                        debug_log("  Failed to create CString");
-                        continue;
+            // An instrument stored in the plugin state replaces the project
+            if let Ok(path) = std::env::var(INSTRUMENT_FILE_ENV) {
+                let stored = self.params.instrument.read().is_ok_and(|i| i.is_some());
+                if !stored {
+                    if let Err(e) = self.params.load_instrument_file(&path) {
+                        nih_log!("⚠ Failed to read instrument file {}: {}", path, e);
                     }
-                };
-
-                let result = sv_load(self.sunvox_slot, project_path.as_ptr());
-                if result == 0 {
-                    debug_log(&format!("SUCCESS: Loaded from {}", path_str));
-                    nih_log!("✓ SunVox project loaded from: {}", path_str);
-                    loaded = true;
-                    break;
-                } else {
-                    debug_log(&format!("  Failed with error: {}", result));
-                    nih_log!("⚠ Failed to load from {}: error {}", path_str, result);
                 }
             }
+            self.params.instrument_changed.store(false, Ordering::Release);
+            if let Ok(path) = std::env::var(SCALE_FILE_ENV) {
+                let stored = self.params.scale.read().is_ok_and(|s| s.is_some());
+                if !stored {
+                    let keyboard_map = std::env::var(KEYBOARD_MAP_FILE_ENV).ok();
+                    if let Err(e) = self.params.load_scale_files(&path, keyboard_map.as_deref()) {
+                        nih_log!("⚠ Failed to read scale {}: {}", path, e);
+                    }
+                }
+            }
+            let built = build_project(&self.params, self.sunvox_slot);
 
-            if !loaded {
+            if !built.loaded {
                 debug_log("ERROR: Could not load project from any path");
                 nih_log!("⚠ Could not load SunVox project from any path");
                 nih_log!("⚠ Audio generation will be disabled");
@@ -170,19 +729,27 @@ impl Plugin for SunVoxPlugin {
                 self.sunvox_initialized = false;
                 return true; // Still return true so plugin loads
             }
+            self.instrument_module = built.instrument_module;
+            self.tuning = built.tuning;
 
             // Start playback
-            debug_log("Starting playback...");
-            let result = sv_play_from_beginning(self.sunvox_slot);
-            if result != 0 {
-                debug_log(&format!("ERROR: playback failed: {}", result));
-                nih_log!("⚠ Failed to start SunVox playback: {}", result);
-            } else {
-                debug_log("SUCCESS: Playback started");
-                nih_log!("✓ SunVox playback started");
+            if self.instrument_module.is_none() {
+                self.start_playback();
+            }
+
+            // Events without a frame of their own must not be stamped with
+            // the wall clock, which the virtual output clock does not follow
+            if let Err(e) = set_immediate_events(self.sunvox_slot) {
+                debug_log(&format!("ERROR: {}", e));
             }
+            self.clock = Some(SampleClock::new(buffer_config.sample_rate as u32));
+            self.params.scale_changed.store(false, Ordering::Release);
+            self.update_pitch_table();
 
             self.sunvox_initialized = true;
+            if let Ok(mut builds) = self.builds.lock() {
+                builds.engine = true;
+            }
             debug_log("=== SunVox Plugin Initialize COMPLETE (success) ===");
         }
 
@@ -192,13 +759,25 @@ impl Plugin for SunVoxPlugin {
     fn deactivate(&mut self) {
         // Clean up SunVox when plugin is deactivated
         if self.sunvox_initialized {
+            // Waits for a build in progress
+            if let Ok(mut builds) = self.builds.lock() {
+                builds.engine = false;
+                builds.built = None;
+            }
+            self.building = false;
             unsafe {
                 nih_log!("Cleaning up SunVox...");
                 sv_close_slot(self.sunvox_slot);
+                sv_close_slot(self.spare_slot());
                 sv_deinit();
                 nih_log!("✓ SunVox cleaned up");
             }
             self.sunvox_initialized = false;
+            self.clock = None;
+            self.voices = LiveVoices::new();
+            self.launcher = PatternLauncher::default();
+            self.layers = ChannelLayers::new();
+            self.tuning = GlobalTuning::default();
         }
     }
 
@@ -206,7 +785,7 @@ impl Plugin for SunVoxPlugin {
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
         // Skip audio generation if SunVox is not initialized
         if !self.sunvox_initialized {
@@ -221,32 +800,430 @@ impl Plugin for SunVoxPlugin {
             return ProcessStatus::Normal;
         }
 
+        // One build at a time: a change made meanwhile waits for it
+        if !self.building && self.params.instrument_changed.swap(false, Ordering::AcqRel) {
+            context.execute_background(ProjectTask::Build(self.spare_slot()));
+            self.building = true;
+        }
+        if self.building {
+            let built = self.builds.try_lock().ok().and_then(|mut b| b.built.take());
+            if let Some(built) = built {
+                self.building = false;
+                let unused = self.swap_project(built);
+                context.execute_background(ProjectTask::CloseSlot(unused));
+            }
+        }
+        if self.params.scale_changed.swap(false, Ordering::AcqRel) {
+            self.update_pitch_table();
+        }
+        self.automation
+            .set_enabled(self.params.record_automation.value());
+        self.update_note_recorder();
+        self.update_launcher();
+        self.update_song_position();
+        self.update_tuning();
+        self.playhead.start_block(self.sunvox_slot);
+        self.update_macros();
+
         // Generate audio from SunVox
-        unsafe {
-            let num_frames = buffer.samples() as i32;
+        let num_frames = buffer.samples();
 
-            // Create interleaved buffer for SunVox (LRLRLR...)
-            let mut sunvox_buffer = vec![0.0f32; (num_frames * 2) as usize];
+        // MIDI notes are played on the virtual pattern at their frame, by
+        // their channel's layer if it has one (note offs go to the module
+        // the note started on)
+        let module = self
+            .instrument_module
+            .unwrap_or_else(|| self.params.midi_module.value());
+        let mpe = self.mpe_config();
+        let mut notes = Vec::new();
+        let mut events = Vec::new();
+        while let Some(event) = context.next_event() {
+            if self.launcher.is_active() {
+                if let NoteEvent::NoteOn { timing, note, .. } = event {
+                    if let Err(e) = self.launcher.launch(self.sunvox_slot, note, &mut self.playhead, timing) {
+                        nih_log!("⚠ Pattern launch failed: {}", e);
+                    }
+                }
+                continue;
+            }
+            let timed = match event {
+                NoteEvent::NoteOn { timing, voice_id, channel, note, velocity } => {
+                    let velocity = (velocity * 127.0).round() as u8;
+                    let module = self.layers.route(channel, module);
+                    self.voices.note_on(timing, channel, note, velocity, module, voice_id)
+                }
+                NoteEvent::NoteOff { timing, channel, note, .. } => {
+                    self.voices.note_off(timing, channel, note)
+                }
+                NoteEvent::MidiPitchBend { timing, channel, value } => {
+                    let semitones = mpe.bend_semitones(channel, value);
+                    events.extend(self.voices.pitch_bend(timing, channel, semitones));
+                    continue;
+                }
+                NoteEvent::PolyTuning { timing, channel, note, tuning, .. } => {
+                    self.voices.note_tuning(timing, channel, note, tuning)
+                }
+                NoteEvent::PolyPressure { timing, channel, note, pressure, .. } => {
+                    mpe.controller(Expression::Pressure).and_then(|ctl| {
+                        self.voices.note_controller(timing, channel, note, ctl, pressure)
+                    })
+                }
+                NoteEvent::PolyBrightness { timing, channel, note, brightness, .. } => {
+                    mpe.controller(Expression::Timbre).and_then(|ctl| {
+                        self.voices.note_controller(timing, channel, note, ctl, brightness)
+                    })
+                }
+                NoteEvent::MidiChannelPressure { timing, channel, pressure } => {
+                    if let Some(ctl) = mpe.controller(Expression::Pressure) {
+                        let changes = self.voices.channel_controller(timing, channel, ctl, pressure);
+                        events.extend(changes);
+                    }
+                    continue;
+                }
+                NoteEvent::MidiCC { timing, channel, cc: CC_TIMBRE, value } => {
+                    if let Some(ctl) = mpe.controller(Expression::Timbre) {
+                        let changes = self.voices.channel_controller(timing, channel, ctl, value);
+                        events.extend(changes);
+                    }
+                    continue;
+                }
+                NoteEvent::PolyVolume { timing, channel, note, gain, .. } => {
+                    self.voices.note_volume(timing, channel, note, gain)
+                }
+                NoteEvent::PolyPan { timing, channel, note, pan, .. } => {
+                    mpe.controller(Expression::Pan).and_then(|ctl| {
+                        self.voices.note_controller(timing, channel, note, ctl, (pan + 1.0) / 2.0)
+                    })
+                }
+                NoteEvent::PolyModulation { timing, voice_id, poly_modulation_id: MODULATION_POLY_ID, normalized_offset } => {
+                    let value = self.params.modulation.unmodulated_normalized_value() + normalized_offset;
+                    mpe.controller(Expression::Modulation).and_then(|ctl| {
+                        self.voices.voice_controller(timing, voice_id, ctl, value)
+                    })
+                }
+                NoteEvent::MonoAutomation { timing, poly_modulation_id: MODULATION_POLY_ID, normalized_value } => {
+                    mpe.controller(Expression::Modulation).map(|ctl| TimedEvent {
+                        offset: timing,
+                        track: 0,
+                        event: controller_event(module, ctl, normalized_value),
+                    })
+                }
+                // Vibrato and expression are left out on purpose, see mpe.rs
+                _ => continue,
+            };
+            events.extend(timed);
+            notes.push(event);
+        }
 
-            // Call SunVox to generate audio
-            let result = sv_audio_callback(
-                sunvox_buffer.as_mut_ptr() as *mut std::os::raw::c_void,
-                num_frames,
-                0, // latency
-                sv_get_ticks(),
-            );
+        // Bars follow the host's time signature at the project's speed
+        let beats_per_bar = context.transport().time_sig_numerator.unwrap_or(4);
+        self.launcher.bar_lines = launcher::bar_lines(self.sunvox_slot, beats_per_bar);
 
-            // Copy SunVox audio to output (result 1 = audio, 0 = silence)
-            let channels = buffer.as_slice();
+        // Launches, clip loops and loop region wraps happen at their frame,
+        // between two parts of the render
+        let slot = self.sunvox_slot;
+        let (launcher, song_position, playhead) =
+            (&mut self.launcher, &mut self.song_position, &mut self.playhead);
+        let launching = launcher.is_active();
+        let failed = if launching { "Pattern launcher" } else { "Loop region" };
+        let cue = if launching {
+            launcher.update(slot, playhead, num_frames as u32)
+        } else {
+            song_position.update(slot, playhead, num_frames as u32)
+        };
+        let cue = cue.unwrap_or_else(|e| {
+            nih_log!("⚠ {} failed: {}", failed, e);
+            None
+        });
+        let at_cue = || {
+            let offset = cue.unwrap_or(0);
+            let jumped = if launching {
+                launcher.cue(slot, playhead, offset)
+            } else {
+                song_position.cue(slot, playhead, offset)
+            };
+            if let Err(e) = jumped {
+                nih_log!("⚠ {} failed: {}", failed, e);
+            }
+            Ok(())
+        };
+
+        // Create interleaved buffer for SunVox (LRLRLR...)
+        let mut sunvox_buffer = vec![0.0f32; num_frames * 2];
+
+        // The output time comes from the frame counter, so offline bounces
+        // render like realtime playback, but is re-synced to the wall clock
+        // whenever it falls behind (after the host paused processing), since
+        // SunVox holds back its wall-clock stamped transport commands until
+        // out_time reaches them. Latency is 0: out_time already is the time
+        // of this block.
+        let clock = self
+            .clock
+            .get_or_insert_with(|| SampleClock::new(self.sample_rate as u32));
+        clock.catch_up();
+        let produced_audio =
+            match render_block_split(slot, &mut sunvox_buffer, 2, clock.block(), &events, cue, at_cue) {
+                Ok(produced_audio) => produced_audio,
+                Err(e) => {
+                    nih_log!("⚠ SunVox render failed: {}", e);
+                    true
+                }
+            };
+        clock.advance(num_frames as u32);
+        self.meter.update(self.sunvox_slot, &sunvox_buffer, produced_audio);
+        self.scope.capture(self.sunvox_slot, num_frames);
+
+        if let Err(e) = self.record_notes(&notes, module, num_frames as u32) {
+            nih_log!("⚠ Note recording failed: {}", e);
+        }
+        self.playhead.advance(num_frames as u32);
+
+        // Copy SunVox audio to output; a silent block is all zeros
+        let channels = buffer.as_slice();
+        if produced_audio {
             for (channel_idx, channel) in channels.iter_mut().enumerate() {
                 for (sample_idx, sample) in channel.iter_mut().enumerate() {
                     // SunVox buffer is interleaved: LRLRLR...
                     *sample = sunvox_buffer[sample_idx * 2 + channel_idx];
                 }
             }
+        } else {
+            for channel in channels.iter_mut() {
+                channel.fill(0.0);
+            }
+        }
+
+        // Once the song has stopped and faded out, the host may suspend
+        // processing (live notes wake the plugin up again)
+        let running = is_playing(self.sunvox_slot)
+            || self.voices.held() > 0
+            || self.song_position.end_of_song() == EndOfSong::HoldSilence;
+        let audible = is_audible(self.sunvox_slot, &sunvox_buffer, produced_audio);
+        match self.silence.update(num_frames as u32, running, audible) {
+            Activity::Running => ProcessStatus::KeepAlive,
+            Activity::Tail(frames) => ProcessStatus::Tail(frames),
+            Activity::Silent => ProcessStatus::Normal,
+        }
+    }
+}
+
+impl SunVoxPlugin {
+    /// Apply the record parameters to the note recorder
+    fn update_note_recorder(&mut self) {
+        let params = &self.params;
+        self.note_recorder.quantize = match params.record_quantize.value() {
+            0 => Quantize::Off,
+            lines => Quantize::Lines(lines as u32),
+        };
+        self.note_recorder.mode = if params.record_replace.value() {
+            RecordMode::Replace
+        } else {
+            RecordMode::Overdub
+        };
+        self.note_recorder.set_target(RecordTarget {
+            pattern: params.record_pattern.value(),
+            tracks: params.record_first_track.value()..=params.record_last_track.value(),
+        });
+        self.note_recorder.set_enabled(params.record_notes.value());
+    }
+
+    fn start_playback(&mut self) {
+        debug_log("Starting playback...");
+        self.song_position.start_line = self.params.start_line.value();
+        if let Err(e) = self.song_position.play_from_start(self.sunvox_slot) {
+            debug_log(&format!("ERROR: playback failed: {}", e));
+            nih_log!("⚠ Failed to start SunVox playback: {}", e);
+        } else {
+            debug_log("SUCCESS: Playback started");
+            nih_log!("✓ SunVox playback started");
+        }
+    }
+
+    /// Slot the next project is built in while the current one plays
+    fn spare_slot(&self) -> i32 {
+        1 - self.sunvox_slot
+    }
+
+    /// Switch to a project built after the instrument changed, and return
+    /// the slot to close: the one switched from, or the new one if nothing
+    /// loaded in it
+    fn swap_project(&mut self, built: BuiltProject) -> i32 {
+        if !built.loaded {
+            nih_log!("⚠ Keeping the current project");
+            return built.slot;
         }
+        let slot = self.sunvox_slot;
+        if let Err(e) = self.launcher.stop(slot) {
+            nih_log!("⚠ Pattern launcher failed: {}", e);
+        }
+        // Stopping twice silences every module, so the releases aren't sent
+        self.voices.all_notes_off(0);
+        unsafe {
+            sv_stop(slot);
+            sv_stop(slot);
+        }
+        self.automation.reset();
+        self.macros_sent = [None; MACRO_COUNT];
+
+        self.sunvox_slot = built.slot;
+        self.instrument_module = built.instrument_module;
+        self.layers = ChannelLayers::new();
+        self.tuning = built.tuning;
+        let end_of_song = self.song_position.end_of_song();
+        if let Err(e) = self.song_position.set_end_of_song(built.slot, end_of_song) {
+            nih_log!("⚠ Setting the end of song failed: {}", e);
+        }
+        if self.instrument_module.is_none() {
+            self.start_playback();
+        }
+        slot
+    }
+
+    /// Pitch bend ranges and expression controllers from the parameters
+    fn mpe_config(&self) -> MpeConfig {
+        let controller = |ctl: i32| (ctl > 0).then_some(ctl - 1);
+        MpeConfig {
+            bend_range: self.params.bend_range.value() as f32,
+            manager_bend_range: MANAGER_BEND_RANGE,
+            pressure_ctl: controller(self.params.pressure_ctl.value()),
+            timbre_ctl: controller(self.params.timbre_ctl.value()),
+            pan_ctl: controller(self.params.pan_ctl.value()),
+            modulation_ctl: controller(self.params.modulation_ctl.value()),
+        }
+    }
+
+    /// Play MIDI notes at the pitches of the stored Scala tuning, or as
+    /// 12-TET note numbers without one
+    fn update_pitch_table(&mut self) {
+        let scale = match self.params.scale.read() {
+            Ok(scale) => scale.clone(),
+            Err(_) => return,
+        };
+        let table = scale.and_then(|scale| match scale.pitch_table() {
+            Ok(table) => Some(table),
+            Err(e) => {
+                nih_log!("⚠ Invalid scale: {}", e);
+                None
+            }
+        });
+        self.voices.set_pitch_table(table);
+    }
 
-        ProcessStatus::Normal
+    /// Apply the transpose, fine tune and master tune parameters to the
+    /// notes played from now on
+    fn update_tuning(&mut self) {
+        let params = &self.params;
+        let cents = params.transpose.value() as f32 * 100.0
+            + params.fine_tune.value()
+            + master_tune_cents(params.master_tune.value());
+        if let Err(e) = self.tuning.apply(self.sunvox_slot, ModuleTuning::from_cents(cents)) {
+            nih_log!("⚠ Tuning failed: {}", e);
+        }
+    }
+
+    /// Enter or leave pattern launcher mode following its parameter
+    fn update_launcher(&mut self) {
+        let enabled = self.params.pattern_launcher.value();
+        if enabled == self.launcher.is_active() {
+            return;
+        }
+        let result = if enabled {
+            self.launcher.start(self.sunvox_slot)
+        } else {
+            self.launcher.stop(self.sunvox_slot)
+        };
+        if let Err(e) = result {
+            nih_log!("⚠ Pattern launcher failed: {}", e);
+        }
+    }
+
+    /// Apply the start line, loop and jump parameters
+    fn update_song_position(&mut self) {
+        let params = &self.params;
+        self.song_position.start_line = params.start_line.value();
+        self.song_position.loop_region = if params.loop_enabled.value() {
+            LoopRegion::new(params.loop_start.value(), params.loop_end.value())
+        } else {
+            None
+        };
+
+        let end_of_song = match params.end_of_song.value() {
+            EndOfSongParam::Stop => EndOfSong::Stop,
+            EndOfSongParam::LoopToStart => EndOfSong::LoopToStart,
+            EndOfSongParam::LoopToMarker => EndOfSong::LoopToMarker(params.end_marker.value()),
+            EndOfSongParam::HoldSilence => EndOfSong::HoldSilence,
+        };
+        if end_of_song != self.song_position.end_of_song() {
+            if let Err(e) = self.song_position.set_end_of_song(self.sunvox_slot, end_of_song) {
+                nih_log!("⚠ Setting the end-of-song policy failed: {}", e);
+            }
+        }
+
+        let jump = params.jump.value();
+        if jump && !self.jump_pressed {
+            let line = params.jump_line.value();
+            match self.song_position.jump_to(self.sunvox_slot, line) {
+                Ok(()) => self.playhead.jump(line),
+                Err(e) => nih_log!("⚠ Jump failed: {}", e),
+            }
+        }
+        self.jump_pressed = jump;
+    }
+
+    /// Send the macros that changed to their controllers, and record them
+    /// while "Record Automation" is on
+    ///
+    /// Host automation splits the block at every parameter change, so a change
+    /// is made at the start of the block. Immediate events take effect there
+    /// too, which is where the playhead records them.
+    fn update_macros(&mut self) {
+        let slot = self.sunvox_slot;
+        for (macro_params, sent) in self.params.macros.iter().zip(&mut self.macros_sent) {
+            let Some((module, ctl)) = macro_params.target() else {
+                *sent = None;
+                continue;
+            };
+            let value = pattern_value(slot, module, ctl, macro_params.value.value());
+            if *sent == Some((module, ctl, value)) {
+                continue;
+            }
+            *sent = Some((module, ctl, value));
+            if let Err(e) = set_controller_value(slot, module, ctl, value as i32, CtlScale::Scaled) {
+                nih_log!("⚠ Macro failed: {}", e);
+                continue;
+            }
+            if let Err(e) = self.automation.record(slot, &self.playhead, 0, module, ctl, value) {
+                nih_log!("⚠ Automation recording failed: {}", e);
+            }
+        }
+    }
+
+    /// Write the notes of the block just rendered into the record pattern,
+    /// each at the song position of its frame
+    fn record_notes(&mut self, notes: &[NoteEvent<()>], module: i32, frames: u32) -> error::Result<()> {
+        let slot = self.sunvox_slot;
+        if !self.note_recorder.is_enabled() {
+            return Ok(());
+        }
+        let Some(end) = self.playhead.position_at(frames.saturating_sub(1)) else {
+            return Ok(());
+        };
+        for note in notes {
+            let position = self.playhead.position_at(note.timing()).unwrap_or(end);
+            self.note_recorder.advance(slot, position)?;
+            match *note {
+                NoteEvent::NoteOn { channel, note, velocity, .. } => {
+                    let velocity = (velocity * 127.0).round() as u8;
+                    let module = self.layers.route(channel, module);
+                    self.note_recorder.note_on(slot, position, channel, note, velocity, module)?;
+                }
+                NoteEvent::NoteOff { channel, note, .. } => {
+                    self.note_recorder.note_off(slot, position, channel, note)?;
+                }
+                _ => {}
+            }
+        }
+        self.note_recorder.advance(slot, end)
     }
 }
 
@@ -260,6 +1237,11 @@ impl ClapPlugin for SunVoxPlugin {
         ClapFeature::Synthesizer,
         ClapFeature::Stereo,
     ];
+    // Lets hosts offset "Modulation" per note, one voice per live track
+    const CLAP_POLY_MODULATION_CONFIG: Option<PolyModulationConfig> = Some(PolyModulationConfig {
+        max_voice_capacity: live_input::LIVE_TRACKS as u32,
+        supports_overlapping_voices: true,
+    });
 }
 
 nih_export_clap!(SunVoxPlugin);
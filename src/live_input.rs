@@ -0,0 +1,376 @@
+// Live MIDI input
+// Turns incoming MIDI notes into events on the tracks of SunVox's virtual pattern
+//
+// Every track of the virtual pattern plays one note at a time, so each held
+// note gets a track of its own. Tracks are handed out round-robin, which lets
+// released notes ring out before their track is reused. With a pitch table
+// notes are played at its pitches through NOTECMD_SET_PITCH instead of as
+// 12-TET note numbers, as are notes started while bent. Pitch bend, MPE
+// expression and note volume reach a held note through the track it plays on.
+
+use crate::event_timing::TimedEvent;
+use crate::midi_import::{sunvox_note, sunvox_velocity};
+use crate::mpe::{controller_event, glide_event, MPE_MANAGER_CHANNEL};
+use crate::pitch::{note_pitch, PITCH_PER_SEMITONE};
+use crate::scala::PitchTable;
+use crate::sunvox_ffi::*;
+
+/// Tracks of the virtual pattern (MAX_PATTERN_TRACKS in the engine)
+pub const LIVE_TRACKS: usize = 32;
+
+/// MIDI channels, each with a pitch bend of its own
+const MIDI_CHANNELS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Voice {
+    channel: u8,
+    note: u8,
+    module: i32,
+    /// Unbent pitch
+    pitch: i32,
+    /// Per-note tuning in semitones
+    tuning: f32,
+    /// SunVox velocity the note started with
+    velocity: u8,
+    /// Host identifier of the note
+    voice_id: Option<i32>,
+}
+
+/// Assigns held MIDI notes to tracks of the virtual pattern
+#[derive(Debug, Clone, Default)]
+pub struct LiveVoices {
+    tracks: [Option<Voice>; LIVE_TRACKS],
+    next: usize,
+    pitch_table: Option<PitchTable>,
+    /// Pitch bend of each channel in semitones
+    bends: [f32; MIDI_CHANNELS],
+}
+
+impl LiveVoices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Play notes at the pitches of a tuning, or as note numbers with `None`
+    pub fn set_pitch_table(&mut self, table: Option<PitchTable>) {
+        self.pitch_table = table;
+    }
+
+    /// Event that starts a MIDI note on `module`, at `offset` frames into the
+    /// block
+    ///
+    /// When all tracks are busy the oldest assignment is taken over. Notes
+    /// the pitch table leaves unmapped don't play. `voice_id` is the host's
+    /// identifier for the note, if it has one.
+    pub fn note_on(
+        &mut self,
+        offset: u32,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        module: i32,
+        voice_id: Option<i32>,
+    ) -> Option<TimedEvent> {
+        let pitch = match &self.pitch_table {
+            Some(table) => table.pitch(note)? as i32,
+            None => note_pitch(sunvox_note(note)?),
+        };
+        let voice = Voice {
+            channel,
+            note,
+            module,
+            pitch,
+            tuning: 0.0,
+            velocity: sunvox_velocity(velocity),
+            voice_id,
+        };
+        let bent = self.bent_pitch(&voice);
+        let (sv_note, ctl_val) = match &self.pitch_table {
+            None if bent == pitch => (sunvox_note(note)?, 0),
+            _ => (NOTECMD_SET_PITCH, bent.clamp(0, note_pitch(1)) as u16),
+        };
+        let track = match self.find(channel, note) {
+            Some(track) => track,
+            None => {
+                let track = (0..LIVE_TRACKS)
+                    .map(|i| (self.next + i) % LIVE_TRACKS)
+                    .find(|&t| self.tracks[t].is_none())
+                    .unwrap_or(self.next);
+                self.next = (track + 1) % LIVE_TRACKS;
+                track
+            }
+        };
+        self.tracks[track] = Some(voice);
+
+        Some(TimedEvent {
+            offset,
+            track: track as i32,
+            event: SunvoxNote {
+                note: sv_note,
+                vel: voice.velocity,
+                module: (module + 1) as u16,
+                ctl: 0,
+                ctl_val,
+            },
+        })
+    }
+
+    /// Bend every note held on `channel` by `semitones`, or every held note
+    /// for the MPE manager channel
+    ///
+    /// Notes started later on the channel are bent as well.
+    pub fn pitch_bend(&mut self, offset: u32, channel: u8, semitones: f32) -> Vec<TimedEvent> {
+        let Some(bend) = self.bends.get_mut(channel as usize) else {
+            return Vec::new();
+        };
+        *bend = semitones;
+        (0..LIVE_TRACKS)
+            .filter(|&track| {
+                self.tracks[track]
+                    .is_some_and(|v| channel == MPE_MANAGER_CHANNEL || v.channel == channel)
+            })
+            .filter_map(|track| self.glide(offset, track))
+            .collect()
+    }
+
+    /// Tune a held note by `semitones` on top of the channel bend
+    pub fn note_tuning(
+        &mut self,
+        offset: u32,
+        channel: u8,
+        note: u8,
+        semitones: f32,
+    ) -> Option<TimedEvent> {
+        let track = self.find(channel, note)?;
+        self.tracks[track].as_mut()?.tuning = semitones;
+        self.glide(offset, track)
+    }
+
+    /// Event that sets controller `ctl` of the module a held note plays on
+    pub fn note_controller(
+        &self,
+        offset: u32,
+        channel: u8,
+        note: u8,
+        ctl: i32,
+        value: f32,
+    ) -> Option<TimedEvent> {
+        let track = self.find(channel, note)?;
+        Some(controller(offset, track, self.tracks[track]?, ctl, value))
+    }
+
+    /// Event that sets controller `ctl` of the module the note with the host
+    /// identifier `voice_id` plays on
+    pub fn voice_controller(
+        &self,
+        offset: u32,
+        voice_id: i32,
+        ctl: i32,
+        value: f32,
+    ) -> Option<TimedEvent> {
+        let track = self
+            .tracks
+            .iter()
+            .position(|v| v.is_some_and(|v| v.voice_id == Some(voice_id)))?;
+        Some(controller(offset, track, self.tracks[track]?, ctl, value))
+    }
+
+    /// Event that scales the volume of a held note by `gain` (linear, 1.0 is
+    /// the velocity it started with)
+    ///
+    /// This sets the velocity of the playing note, which can't go above the
+    /// full velocity of 129.
+    pub fn note_volume(&self, offset: u32, channel: u8, note: u8, gain: f32) -> Option<TimedEvent> {
+        let track = self.find(channel, note)?;
+        let voice = self.tracks[track]?;
+        // Velocity 0 plays at the full velocity
+        let level = match voice.velocity {
+            0 => 128.0,
+            vel => (vel - 1) as f32,
+        };
+        Some(TimedEvent {
+            offset,
+            track: track as i32,
+            event: SunvoxNote {
+                vel: 1 + (level * gain.max(0.0)).round().min(128.0) as u8,
+                module: (voice.module + 1) as u16,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Events that set controller `ctl` for every note held on `channel`
+    pub fn channel_controller(
+        &self,
+        offset: u32,
+        channel: u8,
+        ctl: i32,
+        value: f32,
+    ) -> Vec<TimedEvent> {
+        (0..LIVE_TRACKS)
+            .filter_map(|track| Some((track, self.tracks[track]?)))
+            .filter(|(_, voice)| voice.channel == channel)
+            .map(|(track, voice)| controller(offset, track, voice, ctl, value))
+            .collect()
+    }
+
+    /// Event that releases a held MIDI note
+    pub fn note_off(&mut self, offset: u32, channel: u8, note: u8) -> Option<TimedEvent> {
+        let track = self.find(channel, note)?;
+        let voice = self.tracks[track].take()?;
+        Some(release(offset, track, voice))
+    }
+
+    /// Events that release every held note
+    pub fn all_notes_off(&mut self, offset: u32) -> Vec<TimedEvent> {
+        (0..LIVE_TRACKS)
+            .filter_map(|track| Some(release(offset, track, self.tracks[track].take()?)))
+            .collect()
+    }
+
+    pub fn held(&self) -> usize {
+        self.tracks.iter().flatten().count()
+    }
+
+    /// Pitch of a voice with its tuning and the bends of its channel and
+    /// the manager channel
+    fn bent_pitch(&self, voice: &Voice) -> i32 {
+        let mut semitones = voice.tuning + self.bends[voice.channel as usize % MIDI_CHANNELS];
+        if voice.channel != MPE_MANAGER_CHANNEL {
+            semitones += self.bends[MPE_MANAGER_CHANNEL as usize];
+        }
+        voice.pitch - (semitones * PITCH_PER_SEMITONE as f32).round() as i32
+    }
+
+    fn glide(&self, offset: u32, track: usize) -> Option<TimedEvent> {
+        let voice = self.tracks[track]?;
+        Some(TimedEvent {
+            offset,
+            track: track as i32,
+            event: glide_event(self.bent_pitch(&voice), voice.module),
+        })
+    }
+
+    fn find(&self, channel: u8, note: u8) -> Option<usize> {
+        self.tracks
+            .iter()
+            .position(|v| v.is_some_and(|v| v.channel == channel && v.note == note))
+    }
+}
+
+fn controller(offset: u32, track: usize, voice: Voice, ctl: i32, value: f32) -> TimedEvent {
+    TimedEvent {
+        offset,
+        track: track as i32,
+        event: controller_event(voice.module, ctl, value),
+    }
+}
+
+fn release(offset: u32, track: usize, voice: Voice) -> TimedEvent {
+    TimedEvent {
+        offset,
+        track: track as i32,
+        event: SunvoxNote {
+            note: NOTECMD_NOTE_OFF,
+            module: (voice.module + 1) as u16,
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpe::EFFECT_PORTAMENTO;
+    use crate::scala::{KeyboardMap, Scale};
+
+    #[test]
+    fn test_voice_allocation() {
+        let mut voices = LiveVoices::new();
+        let first = voices.note_on(0, 0, 60, 127, 2, None).unwrap();
+        let second = voices.note_on(5, 1, 60, 63, 2, None).unwrap();
+        assert_eq!((first.track, second.track), (0, 1));
+        assert_eq!(first.event.note, 61);
+        assert_eq!(first.event.vel, 0);
+        assert_eq!(second.event.vel, 64);
+        assert_eq!(second.event.module, 3);
+        // MIDI note 127 has no SunVox equivalent
+        assert!(voices.note_on(0, 0, 127, 100, 2, None).is_none());
+
+        let off = voices.note_off(9, 0, 60).unwrap();
+        assert_eq!((off.offset, off.track), (9, 0));
+        assert_eq!(off.event.note, NOTECMD_NOTE_OFF);
+        assert!(voices.note_off(9, 0, 60).is_none());
+
+        // The released track is reused only after the others
+        assert_eq!(voices.note_on(0, 0, 62, 100, 2, None).unwrap().track, 2);
+        for note in 0..LIVE_TRACKS as u8 {
+            voices.note_on(0, 2, note, 100, 2, None);
+        }
+        assert_eq!(voices.held(), LIVE_TRACKS);
+        assert_eq!(voices.all_notes_off(0).len(), LIVE_TRACKS);
+        assert_eq!(voices.held(), 0);
+
+        // Bends glide the notes of their channel, or all notes on the
+        // manager channel, and apply to notes started later
+        let low = voices.note_on(0, 1, 60, 100, 2, None).unwrap();
+        let high = voices.note_on(0, 2, 64, 100, 2, None).unwrap();
+        let glides = voices.pitch_bend(8, 1, 2.0);
+        assert_eq!(glides.len(), 1);
+        assert_eq!(
+            (glides[0].track, glides[0].event.ctl),
+            (low.track, EFFECT_PORTAMENTO)
+        );
+        assert_eq!(glides[0].event.ctl_val as i32, note_pitch(61) - 512);
+        assert_eq!(voices.pitch_bend(8, MPE_MANAGER_CHANNEL, -1.0).len(), 2);
+        let tuned = voices.note_tuning(9, 2, 64, 0.5).unwrap();
+        assert_eq!(tuned.event.ctl_val as i32, note_pitch(65) + 128);
+        let bent = voices.note_on(0, 1, 67, 100, 2, None).unwrap();
+        assert_eq!(bent.event.note, NOTECMD_SET_PITCH);
+        assert_eq!(bent.event.ctl_val as i32, note_pitch(68) - 256);
+        assert_eq!(voices.channel_controller(0, 1, 4, 0.5).len(), 2);
+        let pressure = voices.note_controller(0, 2, 64, 4, 1.0).unwrap();
+        assert_eq!(pressure.track, high.track);
+        assert_eq!(
+            (pressure.event.ctl, pressure.event.ctl_val),
+            (0x0500, 0x8000)
+        );
+
+        // Volume sets the velocity of the playing note (101 started it),
+        // polyphonic modulation finds the note by its host identifier
+        let quiet = voices.note_volume(0, 2, 64, 0.5).unwrap();
+        assert_eq!(
+            (quiet.track, quiet.event.note, quiet.event.vel),
+            (high.track, 0, 51)
+        );
+        assert_eq!(voices.note_volume(0, 2, 64, 4.0).unwrap().event.vel, 129);
+        let voiced = voices.note_on(0, 3, 48, 127, 2, Some(7)).unwrap();
+        let modulation = voices.voice_controller(0, 7, 1, 0.25).unwrap();
+        assert_eq!(
+            (
+                modulation.track,
+                modulation.event.ctl,
+                modulation.event.ctl_val
+            ),
+            (voiced.track, 0x0200, 0x2000)
+        );
+        assert!(voices.voice_controller(0, 8, 1, 0.25).is_none());
+        voices.pitch_bend(0, 1, 0.0);
+        voices.pitch_bend(0, MPE_MANAGER_CHANNEL, 0.0);
+        voices.all_notes_off(0);
+
+        // Tuned notes play at their pitch, unmapped ones not at all
+        let map = KeyboardMap {
+            first_note: 48,
+            ..Default::default()
+        };
+        voices.set_pitch_table(Some(
+            PitchTable::new(&Scale::equal_temperament(19), &map).unwrap(),
+        ));
+        let tuned = voices.note_on(0, 0, 70, 100, 2, None).unwrap();
+        assert_eq!(tuned.event.note, NOTECMD_SET_PITCH);
+        // 36.8 cents below SunVox's A5 (pitch 12800)
+        assert_eq!(tuned.event.ctl_val, 12894);
+        assert!(voices.note_on(0, 0, 47, 100, 2, None).is_none());
+    }
+}
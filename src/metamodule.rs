@@ -0,0 +1,284 @@
+// MetaModule API
+// Loads whole projects into MetaModules and plays them as layers
+//
+// A MetaModule runs a project of its own inside the slot. Notes sent to it go
+// to its "Input module", so a project loaded into a MetaModule plays like an
+// instrument, and several of them can be layered in one slot. The first five
+// controllers are the MetaModule's own; the ones after them are the user
+// controllers the loaded project defines, each mapped to a controller inside
+// it.
+
+use crate::controllers::{read_controllers, set_controller_real_value, Controller};
+use crate::error::{c_string, check, Result};
+use crate::module_graph::{
+    connect_modules, expect_module_type, new_module, remove_module, OUTPUT_MODULE,
+};
+use crate::sunvox_ffi::*;
+use std::os::raw::c_void;
+
+/// Module type of the MetaModule
+pub const METAMODULE: &str = "MetaModule";
+
+/// MetaModule controllers (real values)
+pub const META_CTL_VOLUME: i32 = 0;
+/// Module inside the project that receives notes
+pub const META_CTL_INPUT_MODULE: i32 = 1;
+/// Whether (and how) the project's own patterns play
+pub const META_CTL_PLAY_PATTERNS: i32 = 2;
+pub const META_CTL_BPM: i32 = 3;
+pub const META_CTL_TPL: i32 = 4;
+
+/// Number of the first user controller
+pub const USER_CTLS_OFFSET: i32 = 5;
+
+/// Name SunVox gives the user controllers a project doesn't map
+const UNMAPPED_CTL_NAME: &str = "...";
+
+/// MIDI channels
+const CHANNELS: usize = 16;
+
+/// Load a project file into a MetaModule, replacing what it played
+pub fn load_metamodule_file(slot: i32, module: i32, path: &str) -> Result<()> {
+    expect_module_type(slot, module, METAMODULE)?;
+    let path_c = c_string(path)?;
+    check("sv_metamodule_load", unsafe {
+        sv_metamodule_load(slot, module, path_c.as_ptr())
+    })?;
+    Ok(())
+}
+
+/// Load a project into a MetaModule from the contents of a file
+///
+/// SunVox takes data it doesn't recognize as an (empty) MOD song, so this
+/// succeeds for data that isn't a project at all.
+pub fn load_metamodule_bytes(slot: i32, module: i32, data: &[u8]) -> Result<()> {
+    expect_module_type(slot, module, METAMODULE)?;
+    // SunVox only reads the data, but its signature takes a mutable pointer
+    check("sv_metamodule_load_from_memory", unsafe {
+        sv_metamodule_load_from_memory(
+            slot,
+            module,
+            data.as_ptr() as *mut c_void,
+            data.len() as u32,
+        )
+    })?;
+    Ok(())
+}
+
+/// Create a MetaModule playing a project file; returns its number
+///
+/// The module is removed again if the file can't be loaded.
+pub fn new_metamodule_from_file(slot: i32, name: &str, path: &str, x: i32, y: i32) -> Result<i32> {
+    let module = new_module(slot, METAMODULE, name, x, y, 0)?;
+    load_metamodule_file(slot, module, path)
+        .inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+        .map(|_| module)
+}
+
+/// Create a MetaModule playing a project from memory; returns its number
+pub fn new_metamodule_from_bytes(
+    slot: i32,
+    name: &str,
+    data: &[u8],
+    x: i32,
+    y: i32,
+) -> Result<i32> {
+    let module = new_module(slot, METAMODULE, name, x, y, 0)?;
+    load_metamodule_bytes(slot, module, data)
+        .inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+        .map(|_| module)
+}
+
+/// The user controllers a MetaModule's project maps to one of its
+/// controllers, numbered as module controllers
+///
+/// A MetaModule always has a few user controllers; the ones its project
+/// leaves unmapped are named "..." and skipped here.
+pub fn user_controllers(slot: i32, module: i32) -> Vec<Controller> {
+    let mut controllers = read_controllers(slot, module);
+    controllers.drain(..controllers.len().min(USER_CTLS_OFFSET as usize));
+    controllers.retain(|ctl| ctl.name != UNMAPPED_CTL_NAME);
+    controllers
+}
+
+/// Set user controller `n` (from 0) to a real value; like other controller
+/// events it takes effect in the next rendered block
+pub fn set_user_controller(slot: i32, module: i32, n: i32, value: i32) -> Result<()> {
+    set_controller_real_value(slot, module, USER_CTLS_OFFSET + n, value)
+}
+
+/// MetaModules played by MIDI channels, one layer per channel
+#[derive(Debug, Clone, Default)]
+pub struct ChannelLayers {
+    modules: [Option<i32>; CHANNELS],
+}
+
+impl ChannelLayers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a project as the layer of a MIDI channel, connected to the
+    /// Output; returns the MetaModule
+    ///
+    /// The layer the channel had before stays in the project.
+    pub fn add(&mut self, slot: i32, channel: u8, name: &str, data: &[u8]) -> Result<i32> {
+        let x = 256 + 128 * (channel as i32 % 8);
+        let y = 384 + 128 * (channel as i32 / 8);
+        let module = new_metamodule_from_bytes(slot, name, data, x, y)?;
+        if let Err(e) = connect_modules(slot, module, OUTPUT_MODULE) {
+            let _ = remove_module(slot, module);
+            return Err(e);
+        }
+        self.assign(channel, Some(module));
+        Ok(module)
+    }
+
+    /// Play a channel with a module already in the project, or with nothing
+    pub fn assign(&mut self, channel: u8, module: Option<i32>) {
+        if let Some(layer) = self.modules.get_mut(channel as usize) {
+            *layer = module;
+        }
+    }
+
+    /// Module playing a MIDI channel
+    pub fn module_for(&self, channel: u8) -> Option<i32> {
+        self.modules.get(channel as usize).copied().flatten()
+    }
+
+    /// Module a note on a MIDI channel goes to: the channel's layer, or
+    /// `fallback` if it has none
+    pub fn route(&self, channel: u8, fallback: i32) -> i32 {
+        self.module_for(channel).unwrap_or(fallback)
+    }
+
+    /// Channels with a layer, and their modules
+    pub fn layers(&self) -> impl Iterator<Item = (u8, i32)> + '_ {
+        self.modules
+            .iter()
+            .enumerate()
+            .filter_map(|(channel, module)| Some((channel as u8, (*module)?)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers().next().is_none()
+    }
+
+    /// Remove a layer's module from the project and from every channel
+    pub fn remove(&mut self, slot: i32, module: i32) -> Result<()> {
+        for layer in self.modules.iter_mut() {
+            if *layer == Some(module) {
+                *layer = None;
+            }
+        }
+        remove_module(slot, module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{controller_count, controller_value, CtlScale};
+    use crate::error::SunVoxError;
+    use crate::event_timing::{send_event, set_immediate_events};
+    use crate::live_input::LiveVoices;
+    use crate::module_graph::{clear_modules, module_exists, module_inputs, ModuleGraph};
+    use crate::project::load_project;
+    use crate::scope::ScopeCapture;
+    use crate::test_support::{render, resource_path, with_engine};
+
+    #[test]
+    fn test_layers_per_channel() {
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            set_immediate_events(slot).unwrap();
+
+            let generator = new_module(slot, "Generator", "Gen", 0, 0, 0).unwrap();
+            assert!(matches!(
+                load_metamodule_file(slot, generator, &resource_path("song02.sunvox")),
+                Err(SunVoxError::WrongModuleType { .. })
+            ));
+            remove_module(slot, generator).unwrap();
+
+            let mut layers = ChannelLayers::new();
+            assert!(layers.is_empty());
+            let song = std::fs::read(resource_path("song02.sunvox")).unwrap();
+            let lower = layers.add(slot, 0, "Lower", &song).unwrap();
+            let upper =
+                new_metamodule_from_file(slot, "Upper", &resource_path("song03.sunvox"), 0, 0)
+                    .unwrap();
+            connect_modules(slot, upper, OUTPUT_MODULE).unwrap();
+            layers.assign(9, Some(upper));
+            assert!(new_metamodule_from_file(slot, "Missing", "missing.sunvox", 0, 0).is_err());
+            assert!(!module_exists(slot, upper + 1));
+            assert_eq!(
+                layers.layers().collect::<Vec<_>>(),
+                [(0, lower), (9, upper)]
+            );
+            assert_eq!(layers.module_for(9), Some(upper));
+            assert_eq!(layers.module_for(1), None);
+            assert_eq!(layers.route(1, OUTPUT_MODULE), OUTPUT_MODULE);
+            assert_eq!(module_inputs(slot, OUTPUT_MODULE).len(), 2);
+
+            // song02 maps no user controllers
+            assert!(controller_count(slot, lower) > USER_CTLS_OFFSET);
+            assert!(user_controllers(slot, lower).is_empty());
+
+            // Each layer plays its channel's notes, as the plugin routes
+            // them, and releases them on the same module
+            let mut capture = ScopeCapture::new(8192);
+            let rings = [capture.add(lower, 0), capture.add(upper, 0)];
+            let mut voices = LiveVoices::new();
+            for channel in [0, 9] {
+                let module = layers.route(channel, OUTPUT_MODULE);
+                let on = voices.note_on(0, channel, 61, 127, module, None).unwrap();
+                assert_eq!(on.event.module, module as u16 + 1);
+                send_event(slot, on.track, on.event).unwrap();
+            }
+            for _ in 0..8 {
+                render(1024);
+                capture.capture(slot, 1024);
+            }
+            for ring in rings {
+                assert!(ring.peak(8192) > 0.0);
+            }
+            for (channel, module) in [(0, lower), (9, upper)] {
+                let off = voices.note_off(0, channel, 61).unwrap();
+                assert_eq!(off.event.note, NOTECMD_NOTE_OFF);
+                assert_eq!(off.event.module, module as u16 + 1);
+                send_event(slot, off.track, off.event).unwrap();
+            }
+            render(1024);
+
+            layers.remove(slot, upper).unwrap();
+            assert_eq!(layers.module_for(9), None);
+
+            // The MetaModule of song03 maps seven, after its own controllers
+            load_project(slot, &resource_path("song03.sunvox")).unwrap();
+            let meta = ModuleGraph::read(slot)
+                .unwrap()
+                .nodes
+                .into_iter()
+                .find(|node| node.module_type == METAMODULE)
+                .unwrap()
+                .id;
+            let user = user_controllers(slot, meta);
+            let names: Vec<_> = user.iter().map(|ctl| ctl.name.as_str()).collect();
+            assert_eq!(
+                names,
+                ["Waveform", "Noise", "Attack", "Release", "Low", "Middle", "High"]
+            );
+            assert_eq!(user[0].index, USER_CTLS_OFFSET);
+            set_user_controller(slot, meta, 1, user[1].max).unwrap();
+            render(64);
+            assert_eq!(
+                controller_value(slot, meta, USER_CTLS_OFFSET + 1, CtlScale::Real),
+                user[1].max
+            );
+        });
+    }
+}
@@ -0,0 +1,169 @@
+// Output metering
+// Peak and RMS levels per channel of the slot output
+//
+// Levels are measured from the rendered (interleaved) buffer. The engine's
+// own meter, `sv_get_current_signal_level`, is kept alongside: it is coarse
+// (0..255 from a single sample) and follows the wall clock, but it is what
+// SunVox itself displays. `OutputMeter` publishes the latest values through
+// atomics, so a UI thread can read them while the audio thread renders.
+
+use crate::sunvox_ffi::*;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+/// Output channels of a slot
+pub const METER_CHANNELS: usize = 2;
+
+/// Level reported for silence, in dBFS
+pub const MIN_DB: f32 = -120.0;
+
+/// Output level of a channel (0..255), as heard at the current system time
+pub fn signal_level(slot: i32, channel: i32) -> u8 {
+    unsafe { sv_get_current_signal_level(slot, channel) }.clamp(0, 255) as u8
+}
+
+/// Linear gain to dBFS, no lower than `MIN_DB`
+pub fn gain_to_db(gain: f32) -> f32 {
+    if gain > 0.0 {
+        (20.0 * gain.log10()).max(MIN_DB)
+    } else {
+        MIN_DB
+    }
+}
+
+/// Levels of one channel over a block (linear, 1.0 = full scale)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelLevel {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl ChannelLevel {
+    pub fn peak_db(&self) -> f32 {
+        gain_to_db(self.peak)
+    }
+
+    pub fn rms_db(&self) -> f32 {
+        gain_to_db(self.rms)
+    }
+}
+
+/// Peak and RMS of every channel of an interleaved buffer with
+/// `levels.len()` channels, written into `levels`
+///
+/// Nothing is allocated, so it can run on the audio thread.
+pub fn measure(buffer: &[f32], levels: &mut [ChannelLevel]) {
+    let channels = levels.len().max(1);
+    let frames = buffer.len() / channels;
+    for (c, level) in levels.iter_mut().enumerate() {
+        let samples = buffer[..frames * channels].iter().skip(c).step_by(channels);
+        let mut peak = 0.0f32;
+        let mut squares = 0.0f64;
+        for &sample in samples {
+            peak = peak.max(sample.abs());
+            squares += sample as f64 * sample as f64;
+        }
+        *level = ChannelLevel {
+            peak,
+            rms: if frames == 0 {
+                0.0
+            } else {
+                (squares / frames as f64).sqrt() as f32
+            },
+        };
+    }
+}
+
+/// Latest output levels, shared between the audio thread and readers
+#[derive(Debug, Default)]
+pub struct OutputMeter {
+    /// `f32` bits
+    peak: [AtomicU32; METER_CHANNELS],
+    rms: [AtomicU32; METER_CHANNELS],
+    signal: [AtomicU8; METER_CHANNELS],
+}
+
+impl OutputMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Measure a rendered stereo block
+    ///
+    /// `produced_audio` is the result of `sv_audio_callback`; a silent block
+    /// is not scanned.
+    pub fn update(&self, slot: i32, buffer: &[f32], produced_audio: bool) {
+        let mut levels = [ChannelLevel::default(); METER_CHANNELS];
+        if produced_audio {
+            measure(buffer, &mut levels);
+        }
+        for (c, level) in levels.iter().enumerate() {
+            self.peak[c].store(level.peak.to_bits(), Ordering::Relaxed);
+            self.rms[c].store(level.rms.to_bits(), Ordering::Relaxed);
+            self.signal[c].store(signal_level(slot, c as i32), Ordering::Relaxed);
+        }
+    }
+
+    /// Levels of the last block
+    pub fn levels(&self) -> [ChannelLevel; METER_CHANNELS] {
+        std::array::from_fn(|c| ChannelLevel {
+            peak: f32::from_bits(self.peak[c].load(Ordering::Relaxed)),
+            rms: f32::from_bits(self.rms[c].load(Ordering::Relaxed)),
+        })
+    }
+
+    /// Engine meter values (0..255) read after the last block
+    pub fn signal_levels(&self) -> [u8; METER_CHANNELS] {
+        std::array::from_fn(|c| self.signal[c].load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::load_project;
+    use crate::test_support::{render, resource_path, with_engine};
+
+    #[test]
+    fn test_peak_and_rms() {
+        // Full-scale square wave on the left, half-scale DC on the right
+        let buffer: Vec<f32> = (0..64)
+            .flat_map(|i| [if i % 2 == 0 { 1.0 } else { -1.0 }, 0.5])
+            .collect();
+        let mut levels = [ChannelLevel::default(); 2];
+        measure(&buffer, &mut levels);
+        assert_eq!((levels[0].peak, levels[0].rms), (1.0, 1.0));
+        assert_eq!((levels[1].peak, levels[1].rms), (0.5, 0.5));
+        assert!((levels[1].rms_db() + 6.0206).abs() < 1e-3);
+        assert_eq!(gain_to_db(0.0), MIN_DB);
+        measure(&[], &mut levels);
+        assert_eq!(levels, [ChannelLevel::default(); 2]);
+
+        // A sine has an RMS of peak / sqrt(2), whatever the block length
+        let sine: Vec<f32> = (0..1000)
+            .map(|i| (i as f32 * std::f32::consts::TAU / 100.0).sin())
+            .collect();
+        let mut level = [ChannelLevel::default()];
+        measure(&sine, &mut level);
+        let level = level[0];
+        assert!((level.rms - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+
+        with_engine(|slot| {
+            load_project(slot, &resource_path("song01.sunvox")).unwrap();
+            unsafe {
+                sv_play_from_beginning(slot);
+            }
+            let meter = OutputMeter::new();
+            let buffer = render(4410);
+            meter.update(slot, &buffer, true);
+            for level in meter.levels() {
+                assert!(level.rms > 0.0 && level.rms <= level.peak);
+            }
+            meter.update(slot, &buffer, false);
+            assert_eq!(meter.levels(), [ChannelLevel::default(); 2]);
+            unsafe {
+                sv_stop(slot);
+                sv_stop(slot);
+            }
+        });
+    }
+}
@@ -0,0 +1,294 @@
+// Pattern to MIDI export
+// Converts the notes of a SunVox project into a Standard MIDI File
+//
+// Timing follows the project's time map, so tempo and ticks-per-line changes
+// made with the 0x0F effect are preserved as tempo events. Effects other than
+// the speed change (note delays, retriggers, controller slides) are ignored.
+
+use crate::error::Result;
+use crate::midi_file::{bpm_to_tempo, MidiEvent, MidiFile, MidiTrack};
+use crate::module_graph::module_name;
+use crate::patterns::{is_pattern_muted, pattern_info, patterns, read_pattern, PatternInfo};
+use crate::project::{song_length_lines, song_name, time_map_speed};
+use crate::sunvox_ffi::NOTECMD_NOTE_OFF;
+use std::collections::BTreeMap;
+
+/// MIDI resolution of exported files
+pub const TICKS_PER_QUARTER: u16 = 96;
+
+/// SunVox runs at 24 ticks per beat
+const MIDI_TICKS_PER_SUNVOX_TICK: u32 = TICKS_PER_QUARTER as u32 / 24;
+
+/// How notes are distributed over MIDI tracks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackLayout {
+    /// One MIDI track for every track of every pattern
+    PerPatternTrack,
+    /// One MIDI track for every module that plays notes
+    PerModule,
+}
+
+/// A note found in a pattern, with its position in song lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineNote {
+    pattern: i32,
+    track: i32,
+    /// SunVox module number (not + 1)
+    module: i32,
+    note: u8,
+    vel: u8,
+    start: i32,
+    end: i32,
+}
+
+/// SunVox note (1..127) to MIDI note number
+pub fn midi_note(note: u8) -> u8 {
+    note.saturating_sub(1).min(127)
+}
+
+/// SunVox velocity (0 = default, 1..129) to MIDI velocity (1..127)
+pub fn midi_velocity(vel: u8) -> u8 {
+    if vel == 0 {
+        127
+    } else {
+        (vel - 1).clamp(1, 127)
+    }
+}
+
+/// Export the project in `slot` as a format 1 MIDI file
+///
+/// Track 0 is the conductor track with the song name and tempo changes;
+/// muted patterns are skipped.
+pub fn export_midi(slot: i32, layout: TrackLayout) -> Result<MidiFile> {
+    let line_ticks = line_ticks(slot)?;
+    let tick_at = |line: i32| line_ticks[(line.max(0) as usize).min(line_ticks.len() - 1)];
+
+    let mut conductor = MidiTrack::default();
+    conductor.push(0, MidiEvent::TrackName(song_name(slot)));
+    let mut last_bpm = 0;
+    for (line, (bpm, _)) in time_map_speed(slot, 0, line_ticks.len() - 1)?
+        .into_iter()
+        .enumerate()
+    {
+        if bpm != last_bpm && bpm > 0 {
+            conductor.push(
+                tick_at(line as i32),
+                MidiEvent::Tempo(bpm_to_tempo(bpm as f64)),
+            );
+            last_bpm = bpm;
+        }
+    }
+
+    let notes: Vec<LineNote> = patterns(slot)
+        .into_iter()
+        .filter(|&p| !is_pattern_muted(slot, p))
+        .filter_map(|p| pattern_info(slot, p))
+        .flat_map(|info| pattern_notes(&info, &read_pattern(slot, info.id)))
+        .collect();
+
+    // Group notes by destination track, keeping a stable order
+    let mut groups: BTreeMap<(i32, i32), (String, u8, MidiTrack)> = BTreeMap::new();
+    let mut pattern_names = BTreeMap::new();
+    for note in &notes {
+        let (key, channel) = match layout {
+            TrackLayout::PerPatternTrack => ((note.pattern, note.track), note.track as u8 % 16),
+            TrackLayout::PerModule => ((note.module, 0), 0),
+        };
+        let (_, _, track) = groups.entry(key).or_insert_with(|| {
+            let name = match layout {
+                TrackLayout::PerPatternTrack => {
+                    let pattern = pattern_names.entry(note.pattern).or_insert_with(|| {
+                        pattern_info(slot, note.pattern).map_or_else(String::new, |i| i.name)
+                    });
+                    format!("{} / track {}", pattern, note.track + 1)
+                }
+                TrackLayout::PerModule => module_name(slot, note.module).unwrap_or_default(),
+            };
+            (name, channel, MidiTrack::default())
+        });
+
+        let midi_note = midi_note(note.note);
+        track.push(
+            tick_at(note.start),
+            MidiEvent::NoteOn {
+                channel,
+                note: midi_note,
+                velocity: midi_velocity(note.vel),
+            },
+        );
+        track.push(
+            tick_at(note.end),
+            MidiEvent::NoteOff {
+                channel,
+                note: midi_note,
+                velocity: 64,
+            },
+        );
+    }
+
+    // In the per-module layout, spread modules over the 16 channels
+    if layout == TrackLayout::PerModule {
+        for (index, (_, channel, track)) in groups.values_mut().enumerate() {
+            *channel = (index % 16) as u8;
+            for event in &mut track.events {
+                if let MidiEvent::NoteOn { channel: c, .. }
+                | MidiEvent::NoteOff { channel: c, .. } = &mut event.kind
+                {
+                    *c = *channel;
+                }
+            }
+        }
+    }
+
+    let mut tracks = vec![conductor];
+    for (name, _, mut track) in groups.into_values() {
+        track.push(0, MidiEvent::TrackName(name));
+        tracks.push(track);
+    }
+    for track in &mut tracks {
+        track.sort();
+    }
+
+    Ok(MidiFile {
+        format: 1,
+        ticks_per_quarter: TICKS_PER_QUARTER,
+        tracks,
+    })
+}
+
+/// MIDI tick at the start of every song line, plus one entry for the end
+fn line_ticks(slot: i32) -> Result<Vec<u32>> {
+    let lines = song_length_lines(slot) as usize;
+    let speeds = time_map_speed(slot, 0, lines + 1)?;
+    let mut ticks = Vec::with_capacity(speeds.len() + 1);
+    let mut tick = 0u32;
+    ticks.push(tick);
+    for (_, tpl) in speeds {
+        tick += tpl.max(1) as u32 * MIDI_TICKS_PER_SUNVOX_TICK;
+        ticks.push(tick);
+    }
+    Ok(ticks)
+}
+
+/// Find the notes of one pattern; a note lasts until the next note or
+/// note-off on the same track, or until the end of the pattern
+fn pattern_notes(info: &PatternInfo, data: &[crate::sunvox_ffi::SunvoxNote]) -> Vec<LineNote> {
+    let tracks = info.tracks.max(1);
+    let mut notes = Vec::new();
+
+    for track in 0..tracks {
+        let mut active: Option<LineNote> = None;
+        for line in 0..info.lines {
+            let Some(cell) = data.get((line * tracks + track) as usize) else {
+                break;
+            };
+            let position = info.x + line;
+
+            let starts_note = (1..NOTECMD_NOTE_OFF).contains(&cell.note) && cell.module > 0;
+            let ends_note = starts_note || cell.note >= NOTECMD_NOTE_OFF;
+            if ends_note {
+                if let Some(mut note) = active.take() {
+                    note.end = position;
+                    notes.push(note);
+                }
+            }
+            if starts_note {
+                active = Some(LineNote {
+                    pattern: info.id,
+                    track,
+                    module: cell.module as i32 - 1,
+                    note: cell.note,
+                    vel: cell.vel,
+                    start: position,
+                    end: position,
+                });
+            }
+        }
+        if let Some(mut note) = active {
+            note.end = info.x + info.lines;
+            notes.push(note);
+        }
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::patterns::{clear_patterns, new_pattern, set_pattern_event};
+    use crate::project::{apply_pending_events, set_song_speed};
+    use crate::sunvox_ffi::SunvoxNote;
+    use crate::test_support::with_engine;
+
+    #[test]
+    fn test_export_notes_and_tempo() {
+        with_engine(|slot| {
+            clear_patterns(slot).unwrap();
+            clear_modules(slot).unwrap();
+            set_song_speed(slot, 125, 6).unwrap();
+            apply_pending_events();
+
+            let synth = new_module(slot, "Generator", "Lead", 256, 0, 0).unwrap();
+            connect_modules(slot, synth, OUTPUT_MODULE).unwrap();
+            let pattern = new_pattern(slot, "Intro", 0, 0, 1, 16).unwrap();
+            let note = |note, vel| SunvoxNote {
+                note,
+                vel,
+                module: synth as u16 + 1,
+                ..Default::default()
+            };
+            set_pattern_event(slot, pattern, 0, 0, note(61, 0)).unwrap();
+            set_pattern_event(
+                slot,
+                pattern,
+                0,
+                4,
+                SunvoxNote {
+                    note: NOTECMD_NOTE_OFF,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            set_pattern_event(slot, pattern, 0, 8, note(65, 65)).unwrap();
+
+            let file = export_midi(slot, TrackLayout::PerModule).unwrap();
+            assert_eq!(file.tracks.len(), 2);
+            assert!(file.tracks[0]
+                .events
+                .iter()
+                .any(|e| e.tick == 0 && e.kind == MidiEvent::Tempo(480_000)));
+
+            // 6 ticks per line, 4 MIDI ticks per SunVox tick
+            let notes: Vec<(u32, MidiEvent)> = file.tracks[1]
+                .events
+                .iter()
+                .filter(|e| !matches!(e.kind, MidiEvent::TrackName(_)))
+                .map(|e| (e.tick, e.kind.clone()))
+                .collect();
+            let on = |note, velocity| MidiEvent::NoteOn {
+                channel: 0,
+                note,
+                velocity,
+            };
+            let off = |note| MidiEvent::NoteOff {
+                channel: 0,
+                note,
+                velocity: 64,
+            };
+            assert_eq!(
+                notes,
+                vec![
+                    (0, on(60, 127)),
+                    (96, off(60)),
+                    (192, on(64, 64)),
+                    (384, off(64)),
+                ]
+            );
+
+            let bytes = file.to_bytes();
+            assert_eq!(&bytes[..4], b"MThd");
+            assert_eq!(&bytes[12..14], &TICKS_PER_QUARTER.to_be_bytes());
+        });
+    }
+}
@@ -0,0 +1,529 @@
+// Standard MIDI File (SMF) support
+// In-memory representation of MIDI files with a reader and a writer
+
+use std::fmt;
+
+/// Errors found while parsing a MIDI file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidiFileError {
+    /// The data does not start with an `MThd` chunk
+    InvalidHeader,
+    /// The data ends in the middle of a chunk or event
+    UnexpectedEnd,
+    /// SMPTE time division (frames per second) is not supported
+    UnsupportedDivision(u16),
+    /// A channel event without a status byte and no running status
+    MissingStatus { track: usize, offset: usize },
+}
+
+impl fmt::Display for MidiFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiFileError::InvalidHeader => write!(f, "not a Standard MIDI File"),
+            MidiFileError::UnexpectedEnd => write!(f, "unexpected end of MIDI data"),
+            MidiFileError::UnsupportedDivision(division) => {
+                write!(f, "unsupported SMPTE time division 0x{:04x}", division)
+            }
+            MidiFileError::MissingStatus { track, offset } => write!(
+                f,
+                "track {}: event without status byte at offset {}",
+                track, offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MidiFileError {}
+
+/// A MIDI file with events stored at absolute tick positions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiFile {
+    /// 0 = single track, 1 = simultaneous tracks
+    pub format: u16,
+    /// Ticks per quarter note
+    pub ticks_per_quarter: u16,
+    pub tracks: Vec<MidiTrack>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MidiTrack {
+    pub events: Vec<TrackEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackEvent {
+    /// Absolute position in ticks
+    pub tick: u32,
+    pub kind: MidiEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidiEvent {
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    /// 14-bit value, 0x2000 = center
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    /// Microseconds per quarter note
+    Tempo(u32),
+    TimeSignature {
+        numerator: u8,
+        denominator_pow2: u8,
+    },
+    TrackName(String),
+}
+
+impl MidiTrack {
+    pub fn push(&mut self, tick: u32, kind: MidiEvent) {
+        self.events.push(TrackEvent { tick, kind });
+    }
+
+    /// Sort events by time; at equal ticks, note-offs come before note-ons
+    /// so retriggered notes are not cut short
+    pub fn sort(&mut self) {
+        self.events.sort_by_key(|e| {
+            let order = match e.kind {
+                MidiEvent::TrackName(_) => 0,
+                MidiEvent::Tempo(_) | MidiEvent::TimeSignature { .. } => 1,
+                MidiEvent::NoteOff { .. } => 2,
+                MidiEvent::NoteOn { .. } => 4,
+                _ => 3,
+            };
+            (e.tick, order)
+        });
+    }
+}
+
+impl MidiFile {
+    /// Parse SMF bytes
+    ///
+    /// Events that have no `MidiEvent` counterpart (aftertouch, SysEx, other
+    /// meta events) are skipped; note-ons with velocity 0 become note-offs.
+    pub fn parse(data: &[u8]) -> Result<Self, MidiFileError> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.bytes(4).ok() != Some(b"MThd".as_slice()) {
+            return Err(MidiFileError::InvalidHeader);
+        }
+        let header_len = reader.u32()? as usize;
+        let format = reader.u16()?;
+        let track_count = reader.u16()?;
+        let division = reader.u16()?;
+        reader.bytes(header_len.saturating_sub(6))?;
+        if division & 0x8000 != 0 {
+            return Err(MidiFileError::UnsupportedDivision(division));
+        }
+
+        let mut tracks = Vec::new();
+        while tracks.len() < track_count as usize && reader.pos < data.len() {
+            let id = reader.bytes(4)?;
+            let len = reader.u32()? as usize;
+            let chunk = reader.bytes(len)?;
+            // Unknown chunks must be ignored
+            if id == b"MTrk" {
+                tracks.push(parse_track(chunk, tracks.len())?);
+            }
+        }
+
+        Ok(MidiFile {
+            format,
+            ticks_per_quarter: division,
+            tracks,
+        })
+    }
+
+    /// Serialize to SMF bytes; track events must be sorted by tick
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&self.format.to_be_bytes());
+        out.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.ticks_per_quarter.to_be_bytes());
+
+        for track in &self.tracks {
+            let data = encode_track(track);
+            out.extend_from_slice(b"MTrk");
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&data);
+        }
+        out
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], MidiFileError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len());
+        let end = end.ok_or(MidiFileError::UnexpectedEnd)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Next byte, without consuming it
+    fn peek(&self) -> Result<u8, MidiFileError> {
+        self.data
+            .get(self.pos)
+            .copied()
+            .ok_or(MidiFileError::UnexpectedEnd)
+    }
+
+    fn u8(&mut self) -> Result<u8, MidiFileError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MidiFileError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, MidiFileError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Variable-length quantity, at most 4 bytes
+    fn var_len(&mut self) -> Result<u32, MidiFileError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+}
+
+fn parse_track(chunk: &[u8], index: usize) -> Result<MidiTrack, MidiFileError> {
+    let mut reader = Reader {
+        data: chunk,
+        pos: 0,
+    };
+    let mut track = MidiTrack::default();
+    let mut tick = 0u32;
+    let mut running_status = None;
+
+    while reader.pos < chunk.len() {
+        tick = tick.saturating_add(reader.var_len()?);
+        let offset = reader.pos;
+
+        let status = match reader.peek()? {
+            0xFF => {
+                reader.pos += 1;
+                let meta_type = reader.u8()?;
+                let len = reader.var_len()? as usize;
+                let payload = reader.bytes(len)?;
+                match (meta_type, payload) {
+                    (0x2F, _) => break,
+                    (0x03, name) => track.push(
+                        tick,
+                        MidiEvent::TrackName(String::from_utf8_lossy(name).into_owned()),
+                    ),
+                    (0x51, [a, b, c]) => {
+                        track.push(tick, MidiEvent::Tempo(u32::from_be_bytes([0, *a, *b, *c])))
+                    }
+                    (0x58, [numerator, denominator_pow2, ..]) => track.push(
+                        tick,
+                        MidiEvent::TimeSignature {
+                            numerator: *numerator,
+                            denominator_pow2: *denominator_pow2,
+                        },
+                    ),
+                    _ => {}
+                }
+                continue;
+            }
+            0xF0 | 0xF7 => {
+                reader.pos += 1;
+                let len = reader.var_len()? as usize;
+                reader.bytes(len)?;
+                running_status = None;
+                continue;
+            }
+            byte if byte & 0x80 != 0 => {
+                reader.pos += 1;
+                running_status = Some(byte);
+                byte
+            }
+            _ => running_status.ok_or(MidiFileError::MissingStatus {
+                track: index,
+                offset,
+            })?,
+        };
+
+        let channel = status & 0x0F;
+        let kind = match status & 0xF0 {
+            0x80 => {
+                let (note, velocity) = (reader.u8()?, reader.u8()?);
+                Some(MidiEvent::NoteOff {
+                    channel,
+                    note,
+                    velocity,
+                })
+            }
+            0x90 => {
+                let (note, velocity) = (reader.u8()?, reader.u8()?);
+                Some(if velocity == 0 {
+                    MidiEvent::NoteOff {
+                        channel,
+                        note,
+                        velocity: 64,
+                    }
+                } else {
+                    MidiEvent::NoteOn {
+                        channel,
+                        note,
+                        velocity,
+                    }
+                })
+            }
+            0xB0 => {
+                let (controller, value) = (reader.u8()?, reader.u8()?);
+                Some(MidiEvent::ControlChange {
+                    channel,
+                    controller,
+                    value,
+                })
+            }
+            0xC0 => Some(MidiEvent::ProgramChange {
+                channel,
+                program: reader.u8()?,
+            }),
+            0xE0 => {
+                let (lsb, msb) = (reader.u8()?, reader.u8()?);
+                Some(MidiEvent::PitchBend {
+                    channel,
+                    value: (lsb as u16 & 0x7F) | ((msb as u16 & 0x7F) << 7),
+                })
+            }
+            // Polyphonic aftertouch
+            0xA0 => {
+                reader.bytes(2)?;
+                None
+            }
+            // Channel pressure
+            _ => {
+                reader.u8()?;
+                None
+            }
+        };
+        if let Some(kind) = kind {
+            track.push(tick, kind);
+        }
+    }
+
+    Ok(track)
+}
+
+fn encode_track(track: &MidiTrack) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut last_tick = 0;
+
+    for event in &track.events {
+        write_var_len(&mut data, event.tick.saturating_sub(last_tick));
+        last_tick = last_tick.max(event.tick);
+
+        match &event.kind {
+            MidiEvent::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => data.extend_from_slice(&[0x80 | (channel & 15), note & 127, velocity & 127]),
+            MidiEvent::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => data.extend_from_slice(&[0x90 | (channel & 15), note & 127, velocity & 127]),
+            MidiEvent::ControlChange {
+                channel,
+                controller,
+                value,
+            } => data.extend_from_slice(&[0xB0 | (channel & 15), controller & 127, value & 127]),
+            MidiEvent::ProgramChange { channel, program } => {
+                data.extend_from_slice(&[0xC0 | (channel & 15), program & 127])
+            }
+            MidiEvent::PitchBend { channel, value } => data.extend_from_slice(&[
+                0xE0 | (channel & 15),
+                (value & 127) as u8,
+                ((value >> 7) & 127) as u8,
+            ]),
+            MidiEvent::Tempo(us_per_quarter) => {
+                data.extend_from_slice(&[0xFF, 0x51, 0x03]);
+                data.extend_from_slice(&us_per_quarter.to_be_bytes()[1..]);
+            }
+            MidiEvent::TimeSignature {
+                numerator,
+                denominator_pow2,
+            } => data.extend_from_slice(&[0xFF, 0x58, 0x04, *numerator, *denominator_pow2, 24, 8]),
+            MidiEvent::TrackName(name) => {
+                data.extend_from_slice(&[0xFF, 0x03]);
+                write_var_len(&mut data, name.len() as u32);
+                data.extend_from_slice(name.as_bytes());
+            }
+        }
+    }
+
+    // End of track
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+    data
+}
+
+/// Write a variable-length quantity (7 bits per byte, MSB first)
+fn write_var_len(out: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = [0u8; 5];
+    let mut i = bytes.len() - 1;
+    bytes[i] = (value & 0x7F) as u8;
+    value >>= 7;
+    while value > 0 {
+        i -= 1;
+        bytes[i] = 0x80 | (value & 0x7F) as u8;
+        value >>= 7;
+    }
+    out.extend_from_slice(&bytes[i..]);
+}
+
+/// Microseconds per quarter note for a tempo in BPM
+pub fn bpm_to_tempo(bpm: f64) -> u32 {
+    (60_000_000.0 / bpm.max(1.0)).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_parse() {
+        let mut conductor = MidiTrack::default();
+        conductor.push(0, MidiEvent::TrackName("Song".to_string()));
+        conductor.push(0, MidiEvent::Tempo(bpm_to_tempo(140.0)));
+        let mut notes = MidiTrack::default();
+        for (tick, kind) in [
+            (
+                0,
+                MidiEvent::ProgramChange {
+                    channel: 2,
+                    program: 5,
+                },
+            ),
+            (
+                0,
+                MidiEvent::NoteOn {
+                    channel: 2,
+                    note: 60,
+                    velocity: 100,
+                },
+            ),
+            (
+                200,
+                MidiEvent::NoteOff {
+                    channel: 2,
+                    note: 60,
+                    velocity: 64,
+                },
+            ),
+            (
+                20_000,
+                MidiEvent::PitchBend {
+                    channel: 2,
+                    value: 0x2345,
+                },
+            ),
+            (
+                20_000,
+                MidiEvent::ControlChange {
+                    channel: 2,
+                    controller: 7,
+                    value: 90,
+                },
+            ),
+        ] {
+            notes.push(tick, kind);
+        }
+        let file = MidiFile {
+            format: 1,
+            ticks_per_quarter: 480,
+            tracks: vec![conductor, notes],
+        };
+
+        assert_eq!(MidiFile::parse(&file.to_bytes()), Ok(file));
+    }
+
+    #[test]
+    fn test_parse_running_status() {
+        // Format 0, 96 PPQ; note on, then a note-on with velocity 0 using
+        // running status
+        let data = [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96, //
+            b'M', b'T', b'r', b'k', 0, 0, 0, 11, //
+            0x00, 0x91, 64, 90, //
+            0x60, 64, 0, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let file = MidiFile::parse(&data).unwrap();
+        let events: Vec<_> = file.tracks[0]
+            .events
+            .iter()
+            .map(|e| (e.tick, e.kind.clone()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    0,
+                    MidiEvent::NoteOn {
+                        channel: 1,
+                        note: 64,
+                        velocity: 90
+                    }
+                ),
+                (
+                    96,
+                    MidiEvent::NoteOff {
+                        channel: 1,
+                        note: 64,
+                        velocity: 64
+                    }
+                ),
+            ]
+        );
+        assert_eq!(MidiFile::parse(b"RIFF"), Err(MidiFileError::InvalidHeader));
+        assert_eq!(
+            MidiFile::parse(&data[..20]),
+            Err(MidiFileError::UnexpectedEnd)
+        );
+        // A track that ends after a delta time
+        let truncated = [&data[..18], &[0, 0, 0, 1, 0x00]].concat();
+        assert_eq!(
+            MidiFile::parse(&truncated),
+            Err(MidiFileError::UnexpectedEnd)
+        );
+    }
+}
@@ -0,0 +1,386 @@
+// MIDI to pattern import
+// Writes the notes and tempo changes of a Standard MIDI File into a SunVox project
+//
+// The layout follows SunVox's own MIDI import: a one-track "Tempo" pattern
+// holds tempo changes, and every MIDI channel gets one pattern with as many
+// tracks as it has simultaneous notes (up to the SunVox limit of 32).
+
+use crate::error::Result;
+use crate::midi_file::{MidiEvent, MidiFile};
+use crate::module_graph::{connect_modules, new_module, OUTPUT_MODULE};
+use crate::patterns::{new_pattern, set_pattern_event};
+use crate::project::{apply_pending_events, set_song_speed, song_tpl, EFFECT_SET_SPEED};
+use crate::sunvox_ffi::{SunvoxNote, NOTECMD_NOTE_OFF};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Maximum number of tracks in a SunVox pattern
+pub const MAX_PATTERN_TRACKS: usize = 32;
+
+/// Vertical distance between imported patterns on the timeline
+const PATTERN_Y_STEP: i32 = 32;
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Module that plays each MIDI channel; channels without one get a new
+    /// Generator module connected to the output
+    pub channel_modules: [Option<i32>; 16],
+    /// Quantization grid; defaults to the project's lines per beat
+    /// (24 / ticks per line)
+    pub lines_per_beat: Option<u32>,
+}
+
+/// What `import_midi` created
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// New patterns, the tempo pattern first if there is one
+    pub patterns: Vec<i32>,
+    /// Modules created for unmapped channels
+    pub modules: Vec<i32>,
+    pub notes: usize,
+    /// Notes that could not be placed: more than 32 at once on a channel, or
+    /// MIDI note 127 (which SunVox uses for note-off)
+    pub dropped_notes: usize,
+}
+
+/// A note quantized to song lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineNote {
+    start: i32,
+    end: i32,
+    note: u8,
+    velocity: u8,
+}
+
+/// MIDI note number to SunVox note (1..127), if representable
+pub fn sunvox_note(note: u8) -> Option<u8> {
+    let note = note.checked_add(1)?;
+    (note < NOTECMD_NOTE_OFF).then_some(note)
+}
+
+/// MIDI velocity to SunVox velocity; full velocity maps to the default (0)
+pub fn sunvox_velocity(velocity: u8) -> u8 {
+    if velocity >= 127 {
+        0
+    } else {
+        velocity + 1
+    }
+}
+
+/// Import a MIDI file into the project in `slot`, starting at line 0
+///
+/// The first tempo event sets the project BPM, so like
+/// `ProjectDocument::rebuild` this renders a few frames and must not run on a
+/// slot that is being played. The result can be stored with `save_project`.
+pub fn import_midi(slot: i32, file: &MidiFile, options: &ImportOptions) -> Result<ImportSummary> {
+    let tpl = song_tpl(slot).max(1);
+    let lines_per_beat = options
+        .lines_per_beat
+        .unwrap_or((24 / tpl).max(1) as u32)
+        .max(1) as u64;
+    let ppq = file.ticks_per_quarter.max(1) as u64;
+    let to_line = |tick: u32| ((tick as u64 * lines_per_beat + ppq / 2) / ppq) as i32;
+
+    // Tempo changes, the last one wins when several fall on the same line
+    let mut tempos = BTreeMap::new();
+    for track in &file.tracks {
+        for event in &track.events {
+            if let MidiEvent::Tempo(us_per_quarter) = event.kind {
+                let bpm = (60_000_000.0 / us_per_quarter.max(1) as f64).round() as i32;
+                tempos.insert((to_line(event.tick), event.tick), bpm.clamp(0x20, 16000));
+            }
+        }
+    }
+    let tempos: BTreeMap<i32, i32> = tempos.into_iter().map(|((l, _), b)| (l, b)).collect();
+
+    let (channels, mut dropped_notes) = collect_notes(file, to_line);
+    let lines = channels
+        .values()
+        .flatten()
+        .map(|n| n.end)
+        .chain(tempos.keys().copied())
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let mut summary = ImportSummary::default();
+
+    if let Some(&bpm) = tempos.get(&0) {
+        set_song_speed(slot, bpm, tpl)?;
+        apply_pending_events();
+    }
+    if tempos.keys().any(|&line| line > 0) {
+        let pattern = new_pattern(slot, "Tempo", 0, 0, 1, lines)?;
+        for (&line, &bpm) in tempos.range(1..) {
+            let event = SunvoxNote {
+                ctl: EFFECT_SET_SPEED,
+                ctl_val: bpm as u16,
+                ..Default::default()
+            };
+            set_pattern_event(slot, pattern, 0, line, event)?;
+        }
+        summary.patterns.push(pattern);
+    }
+
+    for (row, (&channel, notes)) in channels.iter().enumerate() {
+        let module = match options.channel_modules[channel as usize] {
+            Some(module) => module,
+            None => {
+                let index = summary.modules.len() as i32;
+                let module = new_module(
+                    slot,
+                    "Generator",
+                    &format!("MIDI ch {}", channel + 1),
+                    (index % 6 - 2) * 128 + 512,
+                    256 + (index / 6) * 128,
+                    0,
+                )?;
+                connect_modules(slot, module, OUTPUT_MODULE)?;
+                summary.modules.push(module);
+                module
+            }
+        };
+
+        // Give every note the first track that is free at its start
+        let mut track_ends: Vec<i32> = Vec::new();
+        let mut placed = Vec::with_capacity(notes.len());
+        for note in notes {
+            match track_ends.iter().position(|&end| end <= note.start) {
+                Some(track) => {
+                    track_ends[track] = note.end;
+                    placed.push((track, *note));
+                }
+                None if track_ends.len() < MAX_PATTERN_TRACKS => {
+                    track_ends.push(note.end);
+                    placed.push((track_ends.len() - 1, *note));
+                }
+                None => dropped_notes += 1,
+            }
+        }
+        if placed.is_empty() {
+            continue;
+        }
+
+        let pattern = new_pattern(
+            slot,
+            &format!("MIDI ch {}", channel + 1),
+            0,
+            (row as i32 + 1) * PATTERN_Y_STEP,
+            track_ends.len() as i32,
+            lines,
+        )?;
+        let starts: HashSet<(usize, i32)> = placed.iter().map(|(t, n)| (*t, n.start)).collect();
+        for (track, note) in &placed {
+            let event = SunvoxNote {
+                note: note.note,
+                vel: sunvox_velocity(note.velocity),
+                module: (module + 1) as u16,
+                ..Default::default()
+            };
+            set_pattern_event(slot, pattern, *track as i32, note.start, event)?;
+
+            // A note starting on the same line replaces the note-off
+            if !starts.contains(&(*track, note.end)) {
+                let off = SunvoxNote {
+                    note: NOTECMD_NOTE_OFF,
+                    ..Default::default()
+                };
+                set_pattern_event(slot, pattern, *track as i32, note.end, off)?;
+            }
+        }
+        summary.notes += placed.len();
+        summary.patterns.push(pattern);
+    }
+
+    summary.dropped_notes = dropped_notes;
+    Ok(summary)
+}
+
+/// Pair note-ons with note-offs and group the quantized notes by channel,
+/// sorted by start line; also returns the number of unrepresentable notes
+fn collect_notes(
+    file: &MidiFile,
+    to_line: impl Fn(u32) -> i32,
+) -> (BTreeMap<u8, Vec<LineNote>>, usize) {
+    let mut channels: BTreeMap<u8, Vec<LineNote>> = BTreeMap::new();
+    let mut dropped = 0;
+
+    for track in &file.tracks {
+        let mut open: HashMap<(u8, u8), VecDeque<(u32, u8)>> = HashMap::new();
+        let mut finish = |channel: u8, note: u8, start: u32, velocity: u8, end: u32| {
+            let Some(note) = sunvox_note(note) else {
+                dropped += 1;
+                return;
+            };
+            let start = to_line(start);
+            // Notes shorter than a line still last one line
+            let end = to_line(end).max(start + 1);
+            channels.entry(channel & 15).or_default().push(LineNote {
+                start,
+                end,
+                note,
+                velocity,
+            });
+        };
+
+        for event in &track.events {
+            match event.kind {
+                MidiEvent::NoteOn {
+                    channel,
+                    note,
+                    velocity,
+                } => open
+                    .entry((channel, note))
+                    .or_default()
+                    .push_back((event.tick, velocity)),
+                MidiEvent::NoteOff { channel, note, .. } => {
+                    if let Some((start, velocity)) =
+                        open.get_mut(&(channel, note)).and_then(|q| q.pop_front())
+                    {
+                        finish(channel, note, start, velocity, event.tick);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Notes still held at the end of the track
+        let last_tick = track.events.last().map_or(0, |e| e.tick);
+        for ((channel, note), starts) in open {
+            for (start, velocity) in starts {
+                finish(channel, note, start, velocity, last_tick);
+            }
+        }
+    }
+
+    for notes in channels.values_mut() {
+        notes.sort_by_key(|n| (n.start, n.end, n.note));
+    }
+    (channels, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi_file::MidiTrack;
+    use crate::module_graph::{clear_modules, module_name};
+    use crate::patterns::{clear_patterns, find_pattern, pattern_event, pattern_info};
+    use crate::project::{load_project, save_project, song_bpm};
+    use crate::test_support::with_engine;
+
+    fn note(note: u8, vel: u8, module: i32) -> SunvoxNote {
+        SunvoxNote {
+            note,
+            vel,
+            module: (module + 1) as u16,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_import_notes_and_tempo() {
+        let mut conductor = MidiTrack::default();
+        conductor.push(0, MidiEvent::Tempo(600_000));
+        conductor.push(384, MidiEvent::Tempo(400_000));
+        let mut notes = MidiTrack::default();
+        for (tick, kind) in [
+            (
+                0,
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 127,
+                },
+            ),
+            (
+                10,
+                MidiEvent::NoteOn {
+                    channel: 9,
+                    note: 36,
+                    velocity: 99,
+                },
+            ),
+            (
+                20,
+                MidiEvent::NoteOff {
+                    channel: 9,
+                    note: 36,
+                    velocity: 64,
+                },
+            ),
+            (
+                48,
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    note: 64,
+                    velocity: 50,
+                },
+            ),
+            (
+                96,
+                MidiEvent::NoteOff {
+                    channel: 0,
+                    note: 60,
+                    velocity: 64,
+                },
+            ),
+            (
+                150,
+                MidiEvent::NoteOff {
+                    channel: 0,
+                    note: 64,
+                    velocity: 64,
+                },
+            ),
+        ] {
+            notes.push(tick, kind);
+        }
+        let file = MidiFile {
+            format: 1,
+            ticks_per_quarter: 96,
+            tracks: vec![conductor, notes],
+        };
+
+        with_engine(|slot| {
+            clear_patterns(slot).unwrap();
+            clear_modules(slot).unwrap();
+            set_song_speed(slot, 125, 6).unwrap();
+            apply_pending_events();
+            let lead = new_module(slot, "Generator", "Lead", 256, 0, 0).unwrap();
+
+            let mut options = ImportOptions::default();
+            options.channel_modules[0] = Some(lead);
+            let summary = import_midi(slot, &file, &options).unwrap();
+            assert_eq!(summary.notes, 3);
+            assert_eq!(summary.dropped_notes, 0);
+            assert_eq!(summary.modules.len(), 1);
+            let drums = summary.modules[0];
+            assert_eq!(module_name(slot, drums).as_deref(), Some("MIDI ch 10"));
+
+            // Saved and reloaded, the project still holds the imported data
+            let path = std::env::temp_dir().join("midi_import_test.sunvox");
+            let path = path.to_str().unwrap();
+            save_project(slot, path).unwrap();
+            load_project(slot, path).unwrap();
+            let _ = std::fs::remove_file(path);
+            assert_eq!(song_bpm(slot), 100);
+
+            let tempo = find_pattern(slot, "Tempo").unwrap();
+            let change = pattern_event(slot, tempo, 0, 16).unwrap();
+            assert_eq!((change.ctl, change.ctl_val), (EFFECT_SET_SPEED, 150));
+
+            // 4 lines per beat at 6 ticks per line; overlapping notes use two tracks
+            let lead_pattern = find_pattern(slot, "MIDI ch 1").unwrap();
+            assert_eq!(pattern_info(slot, lead_pattern).unwrap().tracks, 2);
+            let cell = |track, line| pattern_event(slot, lead_pattern, track, line).unwrap();
+            assert_eq!(cell(0, 0), note(61, 0, lead));
+            assert_eq!(cell(0, 4).note, NOTECMD_NOTE_OFF);
+            assert_eq!(cell(1, 2), note(65, 51, lead));
+            assert_eq!(cell(1, 6).note, NOTECMD_NOTE_OFF);
+
+            let drum_pattern = find_pattern(slot, "MIDI ch 10").unwrap();
+            let cell = |line| pattern_event(slot, drum_pattern, 0, line).unwrap();
+            assert_eq!(cell(0), note(37, 100, drums));
+            assert_eq!(cell(1).note, NOTECMD_NOTE_OFF);
+        });
+    }
+}
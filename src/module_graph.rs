@@ -0,0 +1,346 @@
+// Module graph API
+// Typed wrappers for creating, connecting and inspecting SunVox modules
+
+use crate::error::{c_string, check, owned_string, Result, SunVoxError};
+use crate::slot::SlotLock;
+use crate::sunvox_ffi::*;
+use serde::{Deserialize, Serialize};
+use std::os::raw::c_void;
+
+/// Module number of the Output module, which exists in every project
+pub const OUTPUT_MODULE: i32 = 0;
+
+/// A single module in a project's module graph
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleNode {
+    /// Module number within the slot
+    pub id: i32,
+    /// Module type, e.g. "Generator", "Reverb", "Output"
+    pub module_type: String,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    /// Color in SunVox's 0xBBGGRR format
+    pub color: u32,
+    /// Raw SV_MODULE_FLAG_* bits
+    pub flags: u32,
+}
+
+impl ModuleNode {
+    pub fn is_generator(&self) -> bool {
+        self.flags & SV_MODULE_FLAG_GENERATOR != 0
+    }
+
+    pub fn is_effect(&self) -> bool {
+        self.flags & SV_MODULE_FLAG_EFFECT != 0
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.flags & SV_MODULE_FLAG_MUTE != 0
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.flags & SV_MODULE_FLAG_BYPASS != 0
+    }
+
+    /// Color as (red, green, blue)
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        (
+            (self.color & 0xFF) as u8,
+            ((self.color >> 8) & 0xFF) as u8,
+            ((self.color >> 16) & 0xFF) as u8,
+        )
+    }
+}
+
+/// A connection carrying audio from `source` into `destination`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ModuleEdge {
+    pub source: i32,
+    pub destination: i32,
+}
+
+/// Snapshot of all modules in a slot and the connections between them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleGraph {
+    pub nodes: Vec<ModuleNode>,
+    pub edges: Vec<ModuleEdge>,
+}
+
+impl ModuleGraph {
+    /// Read the module graph of the project loaded in `slot`
+    pub fn read(slot: i32) -> Result<Self> {
+        let _lock = SlotLock::new(slot);
+
+        let count = check("sv_get_number_of_modules", unsafe {
+            sv_get_number_of_modules(slot)
+        })?;
+
+        let mut graph = ModuleGraph::default();
+        for id in 0..count {
+            let flags = module_flags(slot, id);
+            if flags & SV_MODULE_FLAG_EXISTS == 0 {
+                continue;
+            }
+
+            let (x, y) = module_xy(slot, id);
+            graph.nodes.push(ModuleNode {
+                id,
+                module_type: module_type(slot, id).unwrap_or_default(),
+                name: module_name(slot, id).unwrap_or_default(),
+                x,
+                y,
+                color: unsafe { sv_get_module_color(slot, id) } as u32,
+                flags,
+            });
+
+            // Collect edges from the input side only so each link appears once
+            for source in module_inputs(slot, id) {
+                graph.edges.push(ModuleEdge {
+                    source,
+                    destination: id,
+                });
+            }
+        }
+
+        graph.edges.sort();
+        Ok(graph)
+    }
+
+    pub fn node(&self, id: i32) -> Option<&ModuleNode> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&ModuleNode> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+
+    /// Modules feeding audio into `id`
+    pub fn inputs_of(&self, id: i32) -> impl Iterator<Item = i32> + '_ {
+        self.edges
+            .iter()
+            .filter(move |e| e.destination == id)
+            .map(|e| e.source)
+    }
+
+    /// Modules receiving audio from `id`
+    pub fn outputs_of(&self, id: i32) -> impl Iterator<Item = i32> + '_ {
+        self.edges
+            .iter()
+            .filter(move |e| e.source == id)
+            .map(|e| e.destination)
+    }
+}
+
+/// Create a new module and return its number
+pub fn new_module(slot: i32, module_type: &str, name: &str, x: i32, y: i32, z: i32) -> Result<i32> {
+    let module_type_c = c_string(module_type)?;
+    let name_c = c_string(name)?;
+
+    let _lock = SlotLock::new(slot);
+    check("sv_new_module", unsafe {
+        sv_new_module(slot, module_type_c.as_ptr(), name_c.as_ptr(), x, y, z)
+    })
+}
+
+/// Load a `.sunsynth` module (or a sample, into a new Sampler) and return
+/// its number
+///
+/// A file that isn't a module preset always gets a Sampler, which stays empty
+/// if the file isn't a sample either. SunVox locks the slot itself while it
+/// adds the module.
+pub fn load_module(slot: i32, path: &str, x: i32, y: i32, z: i32) -> Result<i32> {
+    let path_c = c_string(path)?;
+    check("sv_load_module", unsafe {
+        sv_load_module(slot, path_c.as_ptr(), x, y, z)
+    })
+}
+
+/// Load a `.sunsynth` module (or a sample) from the contents of a file
+pub fn load_module_bytes(slot: i32, data: &[u8], x: i32, y: i32, z: i32) -> Result<i32> {
+    // SunVox only reads the data, but its signature takes a mutable pointer
+    check("sv_load_module_from_memory", unsafe {
+        sv_load_module_from_memory(
+            slot,
+            data.as_ptr() as *mut c_void,
+            data.len() as u32,
+            x,
+            y,
+            z,
+        )
+    })
+}
+
+pub fn remove_module(slot: i32, id: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    check("sv_remove_module", unsafe { sv_remove_module(slot, id) })?;
+    Ok(())
+}
+
+/// Route the audio output of `source` into `destination`
+pub fn connect_modules(slot: i32, source: i32, destination: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    check("sv_connect_module", unsafe {
+        sv_connect_module(slot, source, destination)
+    })?;
+    Ok(())
+}
+
+pub fn disconnect_modules(slot: i32, source: i32, destination: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    check("sv_disconnect_module", unsafe {
+        sv_disconnect_module(slot, source, destination)
+    })?;
+    Ok(())
+}
+
+/// Remove every module except Output, leaving an empty patch to build on
+pub fn clear_modules(slot: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    let count = check("sv_get_number_of_modules", unsafe {
+        sv_get_number_of_modules(slot)
+    })?;
+
+    for id in (0..count).rev() {
+        if id != OUTPUT_MODULE && module_flags(slot, id) & SV_MODULE_FLAG_EXISTS != 0 {
+            check("sv_remove_module", unsafe { sv_remove_module(slot, id) })?;
+        }
+    }
+    Ok(())
+}
+
+/// Find a module by name
+pub fn find_module(slot: i32, name: &str) -> Result<i32> {
+    let name_c = c_string(name)?;
+    let id = unsafe { sv_find_module(slot, name_c.as_ptr()) };
+    if id < 0 {
+        return Err(SunVoxError::NotFound(format!("module {:?}", name)));
+    }
+    Ok(id)
+}
+
+/// Raw SV_MODULE_FLAG_* bits of a module (0 if the module slot is empty)
+pub fn module_flags(slot: i32, id: i32) -> u32 {
+    unsafe { sv_get_module_flags(slot, id) }
+}
+
+pub fn module_exists(slot: i32, id: i32) -> bool {
+    module_flags(slot, id) & SV_MODULE_FLAG_EXISTS != 0
+}
+
+pub fn module_type(slot: i32, id: i32) -> Option<String> {
+    unsafe { owned_string(sv_get_module_type(slot, id)) }
+}
+
+/// Fail unless module `id` exists and is of type `expected`
+pub fn expect_module_type(slot: i32, id: i32, expected: &'static str) -> Result<()> {
+    if module_exists(slot, id) && module_type(slot, id).as_deref() == Some(expected) {
+        Ok(())
+    } else {
+        Err(SunVoxError::WrongModuleType {
+            module: id,
+            expected,
+        })
+    }
+}
+
+pub fn module_name(slot: i32, id: i32) -> Option<String> {
+    unsafe { owned_string(sv_get_module_name(slot, id)) }
+}
+
+pub fn set_module_name(slot: i32, id: i32, name: &str) -> Result<()> {
+    let name_c = c_string(name)?;
+    check("sv_set_module_name", unsafe {
+        sv_set_module_name(slot, id, name_c.as_ptr())
+    })?;
+    Ok(())
+}
+
+/// Module position, unpacked the same way as the header's SV_GET_MODULE_XY macro
+pub fn module_xy(slot: i32, id: i32) -> (i32, i32) {
+    let xy = unsafe { sv_get_module_xy(slot, id) };
+    (
+        (xy & 0xFFFF) as u16 as i16 as i32,
+        (xy >> 16) as u16 as i16 as i32,
+    )
+}
+
+pub fn set_module_xy(slot: i32, id: i32, x: i32, y: i32) -> Result<()> {
+    check("sv_set_module_xy", unsafe {
+        sv_set_module_xy(slot, id, x, y)
+    })?;
+    Ok(())
+}
+
+/// Set the module color from (red, green, blue)
+pub fn set_module_color(slot: i32, id: i32, (r, g, b): (u8, u8, u8)) -> Result<()> {
+    let color = r as i32 | (g as i32) << 8 | (b as i32) << 16;
+    check("sv_set_module_color", unsafe {
+        sv_set_module_color(slot, id, color)
+    })?;
+    Ok(())
+}
+
+/// Modules connected to the inputs of `id` (empty links are skipped)
+pub fn module_inputs(slot: i32, id: i32) -> Vec<i32> {
+    let count = (module_flags(slot, id) & SV_MODULE_INPUTS_MASK) >> SV_MODULE_INPUTS_OFF;
+    unsafe { read_links(sv_get_module_inputs(slot, id), count as usize) }
+}
+
+/// Modules connected to the outputs of `id` (empty links are skipped)
+pub fn module_outputs(slot: i32, id: i32) -> Vec<i32> {
+    let count = (module_flags(slot, id) & SV_MODULE_OUTPUTS_MASK) >> SV_MODULE_OUTPUTS_OFF;
+    unsafe { read_links(sv_get_module_outputs(slot, id), count as usize) }
+}
+
+unsafe fn read_links(links: *const i32, count: usize) -> Vec<i32> {
+    if links.is_null() || count == 0 {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(links, count)
+        .iter()
+        .copied()
+        .filter(|&l| l >= 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{resource_path, with_engine};
+
+    #[test]
+    fn test_build_patch_from_scratch() {
+        with_engine(|slot| {
+            let generator = new_module(slot, "Generator", "Lead", 256, 512, 0).unwrap();
+            let reverb = new_module(slot, "Reverb", "Room", 384, 512, 0).unwrap();
+            connect_modules(slot, generator, reverb).unwrap();
+            connect_modules(slot, reverb, OUTPUT_MODULE).unwrap();
+
+            let graph = ModuleGraph::read(slot).unwrap();
+            assert_eq!(graph.node(generator).unwrap().module_type, "Generator");
+            assert!(graph.node(generator).unwrap().is_generator());
+            assert_eq!(graph.node(reverb).unwrap().name, "Room");
+            assert_eq!(graph.node(reverb).unwrap().x, 384);
+            assert_eq!(graph.inputs_of(reverb).collect::<Vec<_>>(), vec![generator]);
+            assert_eq!(
+                graph.outputs_of(reverb).collect::<Vec<_>>(),
+                vec![OUTPUT_MODULE]
+            );
+            assert_eq!(find_module(slot, "Room").unwrap(), reverb);
+
+            disconnect_modules(slot, generator, reverb).unwrap();
+            remove_module(slot, reverb).unwrap();
+            let graph = ModuleGraph::read(slot).unwrap();
+            assert!(graph.node(reverb).is_none());
+            assert!(graph.edges.is_empty());
+
+            // A module preset; anything else ends up in a Sampler
+            let organ = load_module(slot, &resource_path("organ.sunsynth"), 0, 0, 0).unwrap();
+            assert!(module_exists(slot, organ));
+            assert!(module_flags(slot, organ) & SV_MODULE_FLAG_GENERATOR != 0);
+            let other = load_module_bytes(slot, b"not a module", 0, 0, 0).unwrap();
+            assert_eq!(module_type(slot, other).as_deref(), Some("Sampler"));
+        });
+    }
+}
@@ -0,0 +1,186 @@
+// MPE (MIDI Polyphonic Expression)
+// Pitch bend ranges, expression targets and the events that bend playing notes
+//
+// An MPE controller plays each note on a member channel of its own (2..16 of
+// the lower zone), so channel pitch bend, pressure and timbre (CC 74) shape a
+// single note; the manager channel (1) bends every note. A note's pitch is
+// fixed when SunVox starts it, so bends are sent as NOTECMD_SET_PITCH with
+// the portamento effect, which glides the playing note to the new pitch
+// without restarting it. Controllers belong to the module rather than to a
+// note, so pressure, timbre, panning and polyphonic modulation set the
+// controller for all notes of the module. Note volume changes the velocity of
+// the playing note, which is per note.
+//
+// CLAP vibrato and expression events are left out on purpose: SunVox has no
+// per-note vibrato depth to follow a host's value, and the generic
+// expression has no agreed meaning to give a controller.
+
+use crate::pitch::set_pitch_event;
+use crate::sunvox_ffi::SunvoxNote;
+
+/// Pattern effect 0x03: slide the playing note to the event's pitch
+pub const EFFECT_PORTAMENTO: u16 = 0x03;
+
+/// Manager channel of the MPE lower zone (MIDI channel 1)
+pub const MPE_MANAGER_CHANNEL: u8 = 0;
+
+/// MIDI CC carrying MPE timbre
+pub const CC_TIMBRE: u8 = 74;
+
+/// Default pitch bend ranges of the MPE specification, in semitones
+pub const MEMBER_BEND_RANGE: f32 = 48.0;
+pub const MANAGER_BEND_RANGE: f32 = 2.0;
+
+/// Per-note expression sent to a module controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expression {
+    /// Channel pressure, polyphonic aftertouch or CLAP pressure
+    Pressure,
+    /// CC 74 or CLAP brightness
+    Timbre,
+    /// CLAP panning
+    Pan,
+    /// CLAP polyphonic modulation of the plugin's modulation parameter
+    Modulation,
+}
+
+/// How incoming MPE data is applied to live notes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MpeConfig {
+    /// Semitones of a full bend on member channels
+    pub bend_range: f32,
+    /// Semitones of a full bend on the manager channel
+    pub manager_bend_range: f32,
+    /// Controller of the playing module set by pressure
+    pub pressure_ctl: Option<i32>,
+    /// Controller of the playing module set by timbre
+    pub timbre_ctl: Option<i32>,
+    /// Controller of the playing module set by note panning
+    pub pan_ctl: Option<i32>,
+    /// Controller of the playing module set by polyphonic modulation
+    pub modulation_ctl: Option<i32>,
+}
+
+impl Default for MpeConfig {
+    fn default() -> Self {
+        Self {
+            bend_range: MEMBER_BEND_RANGE,
+            manager_bend_range: MANAGER_BEND_RANGE,
+            pressure_ctl: None,
+            timbre_ctl: None,
+            pan_ctl: None,
+            modulation_ctl: None,
+        }
+    }
+}
+
+impl MpeConfig {
+    /// Bend in semitones of a pitch bend value on `channel` (0..1, centered
+    /// on 0.5)
+    pub fn bend_semitones(&self, channel: u8, value: f32) -> f32 {
+        let range = if channel == MPE_MANAGER_CHANNEL {
+            self.manager_bend_range
+        } else {
+            self.bend_range
+        };
+        (value.clamp(0.0, 1.0) * 2.0 - 1.0) * range
+    }
+
+    /// Controller an expression is mapped to, if any
+    pub fn controller(&self, expression: Expression) -> Option<i32> {
+        match expression {
+            Expression::Pressure => self.pressure_ctl,
+            Expression::Timbre => self.timbre_ctl,
+            Expression::Pan => self.pan_ctl,
+            Expression::Modulation => self.modulation_ctl,
+        }
+    }
+}
+
+/// Event that glides the note playing on its track to `pitch`
+pub fn glide_event(pitch: i32, module: i32) -> SunvoxNote {
+    SunvoxNote {
+        ctl: EFFECT_PORTAMENTO,
+        ..set_pitch_event(pitch, 0, module)
+    }
+}
+
+/// Event that sets controller `ctl` of `module` to `value` (0..1 of its
+/// range)
+pub fn controller_event(module: i32, ctl: i32, value: f32) -> SunvoxNote {
+    SunvoxNote {
+        module: (module + 1) as u16,
+        ctl: ((ctl + 1) as u16) << 8,
+        ctl_val: (value.clamp(0.0, 1.0) * 0x8000 as f32).round() as u16,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{controller_value, CtlScale};
+    use crate::event_timing::{send_event, set_immediate_events};
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::pitch::{note_pitch, PITCH_PER_SEMITONE};
+    use crate::sunvox_ffi::NOTECMD_NOTE_OFF;
+    use crate::test_support::{render, with_engine};
+
+    /// Frequency and peak of the left channel of interleaved stereo audio
+    fn analyze(audio: &[f32]) -> (f32, f32) {
+        let left: Vec<f32> = audio.iter().step_by(2).copied().collect();
+        let rising = left.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0);
+        let frequency = rising.count() as f32 * 44100.0 / left.len() as f32;
+        (
+            frequency,
+            left.iter().fold(0.0f32, |peak, s| peak.max(s.abs())),
+        )
+    }
+
+    #[test]
+    fn test_glide_and_expression() {
+        let config = MpeConfig::default();
+        assert_eq!(config.bend_semitones(3, 1.0), 48.0);
+        assert_eq!(config.bend_semitones(3, 0.5), 0.0);
+        assert_eq!(config.bend_semitones(MPE_MANAGER_CHANNEL, 0.0), -2.0);
+        assert_eq!(config.controller(Expression::Timbre), None);
+        assert_eq!(controller_event(1, 2, 2.0).ctl_val, 0x8000);
+
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            set_immediate_events(slot).unwrap();
+            let generator = new_module(slot, "Generator", "Gen", 0, 0, 0).unwrap();
+            connect_modules(slot, generator, OUTPUT_MODULE).unwrap();
+            // A slow attack shows whether the glide restarts the note
+            send_event(slot, 0, controller_event(generator, 3, 0.25)).unwrap();
+            render(1024);
+            assert_eq!(
+                controller_value(slot, generator, 3, CtlScale::Scaled),
+                0x2000
+            );
+
+            let a4 = note_pitch(58);
+            send_event(slot, 0, set_pitch_event(a4, 129, generator)).unwrap();
+            let (start, before) = analyze(&render(22050));
+
+            let up = a4 - 12 * PITCH_PER_SEMITONE;
+            send_event(slot, 0, glide_event(up, generator)).unwrap();
+            let (_, gliding) = analyze(&render(4410));
+            assert!(gliding > before * 0.9, "{} {}", before, gliding);
+            let (frequency, _) = analyze(&render(22050));
+            assert!(
+                (frequency / start - 2.0).abs() < 0.05,
+                "{} {}",
+                start,
+                frequency
+            );
+
+            let off = SunvoxNote {
+                note: NOTECMD_NOTE_OFF,
+                ..Default::default()
+            };
+            send_event(slot, 0, off).unwrap();
+            render(1024);
+        });
+    }
+}
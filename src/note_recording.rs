@@ -0,0 +1,359 @@
+// Live note recording
+// Writes notes played during playback into a pattern of the project
+//
+// Notes go to a target pattern and a range of its tracks. In overdub mode
+// they are added to free cells only; in replace mode the track range is
+// erased as the playhead passes, so each pass over the pattern replaces the
+// previous take. Positions come from the caller (the plugin's `Playhead`,
+// at the frame of each note) and are either kept to the 32nd of a line (with
+// the 0x41..0x5F delay effect) or rounded to a grid of lines.
+
+use crate::automation::{LinePosition, EFFECT_DELAY};
+use crate::error::Result;
+use crate::midi_import::{sunvox_note, sunvox_velocity};
+use crate::patterns::{clear_pattern_event, pattern_event, pattern_info, set_pattern_event};
+use crate::slot::SlotLock;
+use crate::sunvox_ffi::*;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantize {
+    /// Keep the position to the 32nd of a line
+    #[default]
+    Off,
+    /// Round to the nearest multiple of this many lines
+    Lines(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordMode {
+    /// Add notes to free cells, keeping what is already there
+    #[default]
+    Overdub,
+    /// Erase the target tracks as the playhead passes them
+    Replace,
+}
+
+/// Where recorded notes go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordTarget {
+    pub pattern: i32,
+    pub tracks: RangeInclusive<i32>,
+}
+
+impl Default for RecordTarget {
+    fn default() -> Self {
+        Self {
+            pattern: 0,
+            tracks: 0..=31,
+        }
+    }
+}
+
+/// A recorded note that has not been released yet
+#[derive(Debug, Clone, Copy)]
+struct HeldNote {
+    channel: u8,
+    note: u8,
+    track: i32,
+    line: i32,
+}
+
+/// Records notes into a pattern while enabled
+#[derive(Debug, Clone, Default)]
+pub struct NoteRecorder {
+    enabled: bool,
+    target: RecordTarget,
+    pub quantize: Quantize,
+    pub mode: RecordMode,
+    held: Vec<HeldNote>,
+    /// Cells written during the current pass, which replace mode keeps
+    written: HashSet<(i32, i32)>,
+    /// Last line erased in replace mode
+    erased: Option<i32>,
+}
+
+impl NoteRecorder {
+    pub fn new(target: RecordTarget) -> Self {
+        Self {
+            target,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start or stop recording; stopping forgets the notes still held
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.restart();
+        }
+    }
+
+    pub fn target(&self) -> &RecordTarget {
+        &self.target
+    }
+
+    pub fn set_target(&mut self, target: RecordTarget) {
+        if self.target != target {
+            self.target = target;
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.held.clear();
+        self.written.clear();
+        self.erased = None;
+    }
+
+    /// Follow the playhead; in replace mode this erases the lines passed
+    /// since the last call
+    pub fn advance(&mut self, slot: i32, position: LinePosition) -> Result<()> {
+        if !self.enabled || self.mode != RecordMode::Replace {
+            return Ok(());
+        }
+        let Some(info) = pattern_info(slot, self.target.pattern) else {
+            return Ok(());
+        };
+        let line = position.line - info.x;
+        if !(0..info.lines).contains(&line) {
+            return Ok(());
+        }
+
+        let from = match self.erased {
+            Some(erased) if erased <= line => erased + 1,
+            // The pattern started over: a new take
+            _ => {
+                self.written.clear();
+                0
+            }
+        };
+        let _lock = SlotLock::new(slot);
+        for l in from..=line {
+            for track in self.tracks(info.tracks) {
+                if !self.written.contains(&(track, l)) {
+                    clear_pattern_event(slot, self.target.pattern, track, l)?;
+                }
+            }
+        }
+        self.erased = Some(line);
+        Ok(())
+    }
+
+    /// Record a MIDI note-on; returns whether it was written
+    ///
+    /// Nothing is written while disabled, outside the target pattern, or when
+    /// no track of the range is free at that line.
+    pub fn note_on(
+        &mut self,
+        slot: i32,
+        position: LinePosition,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        module: i32,
+    ) -> Result<bool> {
+        if !self.enabled {
+            return Ok(false);
+        }
+        let Some(sv_note) = sunvox_note(note) else {
+            return Ok(false);
+        };
+        let Some((line, effect, tracks)) = self.locate(slot, position) else {
+            return Ok(false);
+        };
+        self.held.retain(|h| (h.channel, h.note) != (channel, note));
+
+        let _lock = SlotLock::new(slot);
+        let Some(track) = self
+            .tracks(tracks)
+            .find(|&t| !self.held.iter().any(|h| h.track == t) && self.is_free(slot, t, line))
+        else {
+            return Ok(false);
+        };
+        let event = SunvoxNote {
+            note: sv_note,
+            vel: sunvox_velocity(velocity),
+            module: (module + 1) as u16,
+            ctl: effect,
+            ..Default::default()
+        };
+        set_pattern_event(slot, self.target.pattern, track, line, event)?;
+        self.written.insert((track, line));
+        self.held.push(HeldNote {
+            channel,
+            note,
+            track,
+            line,
+        });
+        Ok(true)
+    }
+
+    /// Record the release of a note recorded by `note_on`; returns whether a
+    /// note-off was written
+    ///
+    /// A note released on the line it started on ends on the next line. When
+    /// the cell for the note-off is taken, the next note on the track ends it.
+    pub fn note_off(
+        &mut self,
+        slot: i32,
+        position: LinePosition,
+        channel: u8,
+        note: u8,
+    ) -> Result<bool> {
+        let Some(index) = self
+            .held
+            .iter()
+            .position(|h| (h.channel, h.note) == (channel, note))
+        else {
+            return Ok(false);
+        };
+        let held = self.held.swap_remove(index);
+        let Some((line, effect, _)) = self.locate(slot, position) else {
+            return Ok(false);
+        };
+        // Released after the pattern started over
+        if line < held.line {
+            return Ok(false);
+        }
+        let (line, effect) = if line == held.line {
+            (line + 1, 0)
+        } else {
+            (line, effect)
+        };
+
+        let _lock = SlotLock::new(slot);
+        let lines = pattern_info(slot, self.target.pattern).map_or(0, |info| info.lines);
+        if line >= lines || !self.is_free(slot, held.track, line) {
+            return Ok(false);
+        }
+        let event = SunvoxNote {
+            note: NOTECMD_NOTE_OFF,
+            ctl: effect,
+            ..Default::default()
+        };
+        set_pattern_event(slot, self.target.pattern, held.track, line, event)?;
+        self.written.insert((held.track, line));
+        Ok(true)
+    }
+
+    /// Pattern line, delay effect and track count for a playback position
+    fn locate(&self, slot: i32, position: LinePosition) -> Option<(i32, u16, i32)> {
+        let info = pattern_info(slot, self.target.pattern)?;
+        let (line, effect) = match self.quantize {
+            Quantize::Off => {
+                let effect = match position.fraction & 31 {
+                    0 => 0,
+                    fraction => EFFECT_DELAY + fraction as u16,
+                };
+                (position.line, effect)
+            }
+            Quantize::Lines(lines) => {
+                let grid = lines.max(1) as i64 * 32;
+                let t = position.line as i64 * 32 + (position.fraction & 31) as i64;
+                let t = (t + grid / 2).div_euclid(grid) * grid;
+                ((t / 32) as i32, 0)
+            }
+        };
+        let line = line - info.x;
+        (0..info.lines)
+            .contains(&line)
+            .then_some((line, effect, info.tracks))
+    }
+
+    /// Target tracks that exist in a pattern with `count` tracks
+    fn tracks(&self, count: i32) -> impl Iterator<Item = i32> {
+        let first = (*self.target.tracks.start()).max(0);
+        let last = (*self.target.tracks.end()).min(count - 1);
+        first..=last
+    }
+
+    /// Whether a cell can take a recorded event
+    fn is_free(&self, slot: i32, track: i32, line: i32) -> bool {
+        if self.written.contains(&(track, line)) {
+            return false;
+        }
+        self.mode == RecordMode::Replace
+            || pattern_event(slot, self.target.pattern, track, line)
+                .is_ok_and(|cell| cell.note == 0 && cell.module == 0 && cell.ctl == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patterns::{clear_patterns, new_pattern};
+    use crate::test_support::with_engine;
+
+    #[test]
+    fn test_record_overdub_and_replace() {
+        with_engine(|slot| {
+            clear_patterns(slot).unwrap();
+            let pattern = new_pattern(slot, "Take", 16, 0, 4, 16).unwrap();
+            let existing = SunvoxNote {
+                note: 40,
+                module: 2,
+                ..Default::default()
+            };
+            for line in 0..16 {
+                set_pattern_event(slot, pattern, 0, line, existing).unwrap();
+            }
+            set_pattern_event(slot, pattern, 1, 2, existing).unwrap();
+            set_pattern_event(slot, pattern, 2, 12, existing).unwrap();
+
+            let at = |line, fraction| LinePosition { line, fraction };
+            let mut recorder = NoteRecorder::new(RecordTarget {
+                pattern,
+                tracks: 1..=2,
+            });
+            assert!(!recorder.note_on(slot, at(18, 0), 0, 60, 100, 1).unwrap());
+            recorder.set_enabled(true);
+
+            // Overdub: track 1 is taken at line 2, so the note goes to track 2
+            // and a second one finds no free track
+            assert!(recorder.note_on(slot, at(18, 12), 0, 60, 100, 1).unwrap());
+            assert!(!recorder.note_on(slot, at(18, 12), 0, 64, 100, 1).unwrap());
+            assert!(!recorder.note_on(slot, at(3, 0), 0, 67, 100, 1).unwrap());
+            let cell = pattern_event(slot, pattern, 2, 2).unwrap();
+            assert_eq!((cell.note, cell.vel, cell.module), (61, 101, 2));
+            assert_eq!(cell.ctl, 0x4C);
+            assert!(recorder.note_off(slot, at(21, 0), 0, 60).unwrap());
+            assert_eq!(
+                pattern_event(slot, pattern, 2, 5).unwrap().note,
+                NOTECMD_NOTE_OFF
+            );
+
+            // Quantized to 4 lines, 6.6 lines into the pattern is line 8;
+            // released on the same grid line, the note lasts one line
+            recorder.quantize = Quantize::Lines(4);
+            assert!(recorder.note_on(slot, at(22, 20), 3, 62, 127, 1).unwrap());
+            assert!(recorder.note_off(slot, at(23, 0), 3, 62).unwrap());
+            assert_eq!(pattern_event(slot, pattern, 1, 8).unwrap().note, 63);
+            assert_eq!(pattern_event(slot, pattern, 1, 8).unwrap().ctl, 0);
+            assert_eq!(
+                pattern_event(slot, pattern, 1, 9).unwrap().note,
+                NOTECMD_NOTE_OFF
+            );
+
+            // Replace: the target tracks are erased up to the playhead, except
+            // for this take's notes; track 0 is left alone
+            recorder.mode = RecordMode::Replace;
+            recorder.quantize = Quantize::Off;
+            recorder.advance(slot, at(16, 0)).unwrap();
+            assert!(recorder.note_on(slot, at(16, 0), 0, 48, 100, 1).unwrap());
+            recorder.advance(slot, at(25, 3)).unwrap();
+            for line in 1..=9 {
+                assert_eq!(pattern_event(slot, pattern, 1, line).unwrap().note, 0);
+                assert_eq!(pattern_event(slot, pattern, 2, line).unwrap().note, 0);
+            }
+            assert_eq!(pattern_event(slot, pattern, 1, 0).unwrap().note, 49);
+            assert_eq!(pattern_event(slot, pattern, 2, 12).unwrap().note, 40);
+            assert_eq!(pattern_event(slot, pattern, 0, 5).unwrap().note, 40);
+        });
+    }
+}
@@ -0,0 +1,189 @@
+// Pattern editing API
+// Typed wrappers for creating, reading and writing SunVox patterns
+
+use crate::error::{c_string, check, owned_string, Result, SunVoxError};
+use crate::slot::SlotLock;
+use crate::sunvox_ffi::*;
+
+/// Position, size and name of a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternInfo {
+    /// Pattern number within the slot
+    pub id: i32,
+    pub name: String,
+    /// Line number of the pattern start on the timeline
+    pub x: i32,
+    /// Vertical position on the timeline
+    pub y: i32,
+    pub tracks: i32,
+    pub lines: i32,
+}
+
+/// Create an empty pattern and return its number
+pub fn new_pattern(slot: i32, name: &str, x: i32, y: i32, tracks: i32, lines: i32) -> Result<i32> {
+    let name_c = c_string(name)?;
+    let _lock = SlotLock::new(slot);
+    check("sv_new_pattern", unsafe {
+        sv_new_pattern(slot, -1, x, y, tracks, lines, x ^ y, name_c.as_ptr())
+    })
+}
+
+pub fn remove_pattern(slot: i32, pattern: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    check("sv_remove_pattern", unsafe {
+        sv_remove_pattern(slot, pattern)
+    })?;
+    Ok(())
+}
+
+/// Remove every pattern, leaving an empty timeline
+pub fn clear_patterns(slot: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    for pattern in (0..pattern_slot_count(slot)).rev() {
+        if pattern_exists(slot, pattern) {
+            check("sv_remove_pattern", unsafe {
+                sv_remove_pattern(slot, pattern)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Number of pattern slots (some may be empty, see `pattern_exists`)
+pub fn pattern_slot_count(slot: i32) -> i32 {
+    unsafe { sv_get_number_of_patterns(slot) }.max(0)
+}
+
+pub fn pattern_exists(slot: i32, pattern: i32) -> bool {
+    unsafe { sv_get_pattern_lines(slot, pattern) > 0 }
+}
+
+/// Numbers of all existing patterns
+pub fn patterns(slot: i32) -> Vec<i32> {
+    (0..pattern_slot_count(slot))
+        .filter(|&p| pattern_exists(slot, p))
+        .collect()
+}
+
+pub fn find_pattern(slot: i32, name: &str) -> Result<i32> {
+    let name_c = c_string(name)?;
+    let id = unsafe { sv_find_pattern(slot, name_c.as_ptr()) };
+    if id < 0 {
+        return Err(SunVoxError::NotFound(format!("pattern {:?}", name)));
+    }
+    Ok(id)
+}
+
+pub fn pattern_info(slot: i32, pattern: i32) -> Option<PatternInfo> {
+    if !pattern_exists(slot, pattern) {
+        return None;
+    }
+    unsafe {
+        Some(PatternInfo {
+            id: pattern,
+            name: owned_string(sv_get_pattern_name(slot, pattern)).unwrap_or_default(),
+            x: sv_get_pattern_x(slot, pattern),
+            y: sv_get_pattern_y(slot, pattern),
+            tracks: sv_get_pattern_tracks(slot, pattern),
+            lines: sv_get_pattern_lines(slot, pattern),
+        })
+    }
+}
+
+pub fn set_pattern_xy(slot: i32, pattern: i32, x: i32, y: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    check("sv_set_pattern_xy", unsafe {
+        sv_set_pattern_xy(slot, pattern, x, y)
+    })?;
+    Ok(())
+}
+
+pub fn set_pattern_size(slot: i32, pattern: i32, tracks: i32, lines: i32) -> Result<()> {
+    let _lock = SlotLock::new(slot);
+    check("sv_set_pattern_size", unsafe {
+        sv_set_pattern_size(slot, pattern, tracks, lines)
+    })?;
+    Ok(())
+}
+
+pub fn set_pattern_name(slot: i32, pattern: i32, name: &str) -> Result<()> {
+    let name_c = c_string(name)?;
+    let _lock = SlotLock::new(slot);
+    check("sv_set_pattern_name", unsafe {
+        sv_set_pattern_name(slot, pattern, name_c.as_ptr())
+    })?;
+    Ok(())
+}
+
+/// Mute or unmute a pattern, returning whether it was muted before
+pub fn set_pattern_mute(slot: i32, pattern: i32, mute: bool) -> Result<bool> {
+    let _lock = SlotLock::new(slot);
+    let previous = check("sv_pattern_mute", unsafe {
+        sv_pattern_mute(slot, pattern, mute as i32)
+    })?;
+    Ok(previous == 1)
+}
+
+pub fn is_pattern_muted(slot: i32, pattern: i32) -> bool {
+    let _lock = SlotLock::new(slot);
+    unsafe { sv_pattern_mute(slot, pattern, -1) == 1 }
+}
+
+/// Copy all events of a pattern, line by line (`line * tracks + track`)
+pub fn read_pattern(slot: i32, pattern: i32) -> Vec<SunvoxNote> {
+    let _lock = SlotLock::new(slot);
+    unsafe {
+        let tracks = sv_get_pattern_tracks(slot, pattern).max(0) as usize;
+        let lines = sv_get_pattern_lines(slot, pattern).max(0) as usize;
+        let data = sv_get_pattern_data(slot, pattern);
+        if data.is_null() {
+            return Vec::new();
+        }
+        std::slice::from_raw_parts(data, tracks * lines).to_vec()
+    }
+}
+
+/// Write a single cell, replacing all of its fields
+pub fn set_pattern_event(
+    slot: i32,
+    pattern: i32,
+    track: i32,
+    line: i32,
+    event: SunvoxNote,
+) -> Result<()> {
+    check("sv_set_pattern_event", unsafe {
+        sv_set_pattern_event(
+            slot,
+            pattern,
+            track,
+            line,
+            event.note as i32,
+            event.vel as i32,
+            event.module as i32,
+            event.ctl as i32,
+            event.ctl_val as i32,
+        )
+    })?;
+    Ok(())
+}
+
+/// Read a single cell
+pub fn pattern_event(slot: i32, pattern: i32, track: i32, line: i32) -> Result<SunvoxNote> {
+    let field = |column| {
+        check("sv_get_pattern_event", unsafe {
+            sv_get_pattern_event(slot, pattern, track, line, column)
+        })
+    };
+    Ok(SunvoxNote {
+        note: field(0)? as u8,
+        vel: field(1)? as u8,
+        module: field(2)? as u16,
+        ctl: field(3)? as u16,
+        ctl_val: field(4)? as u16,
+    })
+}
+
+/// Clear a single cell
+pub fn clear_pattern_event(slot: i32, pattern: i32, track: i32, line: i32) -> Result<()> {
+    set_pattern_event(slot, pattern, track, line, SunvoxNote::default())
+}
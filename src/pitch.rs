@@ -0,0 +1,106 @@
+// Pitch conversion
+// SunVox pitch values, frequencies and notes played at an exact pitch
+//
+// SunVox pitch counts down from C0 (0x7800) in 1/256 of a semitone, so higher
+// values are lower notes. `NOTECMD_SET_PITCH` starts a note at any pitch,
+// which is how notes outside 12-tone equal temperament are played.
+
+use crate::sunvox_ffi::{SunvoxNote, NOTECMD_SET_PITCH};
+
+/// Pitch of SunVox note 1 (C0), the lowest `NOTECMD_SET_PITCH` accepts
+pub const NOTE_C0_PITCH: i32 = 0x7800;
+
+/// Pitch units in a semitone
+pub const PITCH_PER_SEMITONE: i32 = 256;
+
+/// Frequency of C0 in Hz (`SV_PITCH_TO_FREQUENCY(0x7800)`)
+pub const C0_FREQUENCY: f64 = 16.333984375;
+
+/// Frequency in Hz of a pitch (the `SV_PITCH_TO_FREQUENCY` macro)
+pub fn pitch_to_frequency(pitch: f64) -> f64 {
+    2f64.powf((NOTE_C0_PITCH as f64 - pitch) / (12 * PITCH_PER_SEMITONE) as f64) * C0_FREQUENCY
+}
+
+/// Pitch of a frequency in Hz (the `SV_FREQUENCY_TO_PITCH` macro)
+pub fn frequency_to_pitch(frequency: f64) -> f64 {
+    NOTE_C0_PITCH as f64 - (frequency / C0_FREQUENCY).log2() * (12 * PITCH_PER_SEMITONE) as f64
+}
+
+/// Pitch of a SunVox note (1 is C0)
+pub fn note_pitch(note: u8) -> i32 {
+    NOTE_C0_PITCH - (note as i32 - 1) * PITCH_PER_SEMITONE
+}
+
+/// Event that starts a note at `pitch` on `module`, like a note number
+/// would; pitches past either end are clamped
+pub fn set_pitch_event(pitch: i32, vel: u8, module: i32) -> SunvoxNote {
+    SunvoxNote {
+        note: NOTECMD_SET_PITCH,
+        vel,
+        module: (module + 1) as u16,
+        ctl: 0,
+        ctl_val: pitch.clamp(0, NOTE_C0_PITCH) as u16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{set_controller_value, CtlScale};
+    use crate::event_timing::{send_event, set_immediate_events};
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::sunvox_ffi::NOTECMD_NOTE_OFF;
+    use crate::test_support::{render, with_engine};
+
+    /// Generator waveform selector value of the sine
+    const GENERATOR_SINE: i32 = 5;
+
+    /// Frequency of the left channel of interleaved stereo audio, from its
+    /// rising zero crossings
+    fn frequency(audio: &[f32]) -> f32 {
+        let left: Vec<f32> = audio.iter().step_by(2).copied().collect();
+        let rising = left.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0);
+        rising.count() as f32 * 44100.0 / left.len() as f32
+    }
+
+    #[test]
+    fn test_pitch_conversion() {
+        // SunVox note 58 is A4; the header's C0 puts it 2 cents below 440 Hz
+        assert_eq!(note_pitch(1), NOTE_C0_PITCH);
+        assert!((pitch_to_frequency(note_pitch(58) as f64) - 440.0).abs() < 0.5);
+        assert!((frequency_to_pitch(440.0) - note_pitch(58) as f64).abs() < 5.0);
+        assert!((frequency_to_pitch(pitch_to_frequency(1234.5)) - 1234.5).abs() < 1e-6);
+        assert_eq!(set_pitch_event(-5, 0, 1).ctl_val, 0);
+        assert_eq!(set_pitch_event(0x9000, 0, 1).ctl_val, 0x7800);
+
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            set_immediate_events(slot).unwrap();
+            let generator = new_module(slot, "Generator", "Gen", 0, 0, 0).unwrap();
+            connect_modules(slot, generator, OUTPUT_MODULE).unwrap();
+            // A sine crosses zero once per period
+            set_controller_value(slot, generator, 1, GENERATOR_SINE, CtlScale::Real).unwrap();
+
+            // A quarter tone above A4, about 13 Hz from both A4 and A#4
+            let quarter_tone = note_pitch(58) - PITCH_PER_SEMITONE / 2;
+            let expected = pitch_to_frequency(quarter_tone as f64) as f32;
+            assert!((expected - 452.9).abs() < 1.0, "{}", expected);
+            send_event(slot, 0, set_pitch_event(quarter_tone, 129, generator)).unwrap();
+            render(4410);
+            let frequency = frequency(&render(44100));
+            assert!(
+                (frequency - expected).abs() < 2.0,
+                "{} {}",
+                expected,
+                frequency
+            );
+
+            let off = SunvoxNote {
+                note: NOTECMD_NOTE_OFF,
+                ..Default::default()
+            };
+            send_event(slot, 0, off).unwrap();
+            render(1024);
+        });
+    }
+}
@@ -0,0 +1,155 @@
+// Project-level API
+// Loading, saving and song metadata for the project in a slot
+
+use crate::error::{c_string, check, owned_string, Result};
+use crate::sunvox_ffi::*;
+
+/// Pattern effect 0x0F: set speed (XXYY < 0x20: ticks per line, otherwise BPM)
+pub const EFFECT_SET_SPEED: u16 = 0x0F;
+
+pub fn load_project(slot: i32, path: &str) -> Result<()> {
+    let path_c = c_string(path)?;
+    check("sv_load", unsafe { sv_load(slot, path_c.as_ptr()) })?;
+    Ok(())
+}
+
+pub fn save_project(slot: i32, path: &str) -> Result<()> {
+    let path_c = c_string(path)?;
+    check("sv_save", unsafe { sv_save(slot, path_c.as_ptr()) })?;
+    Ok(())
+}
+
+pub fn song_name(slot: i32) -> String {
+    unsafe { owned_string(sv_get_song_name(slot)) }.unwrap_or_default()
+}
+
+pub fn set_song_name(slot: i32, name: &str) -> Result<()> {
+    let name_c = c_string(name)?;
+    check("sv_set_song_name", unsafe {
+        sv_set_song_name(slot, name_c.as_ptr())
+    })?;
+    Ok(())
+}
+
+pub fn song_bpm(slot: i32) -> i32 {
+    unsafe { sv_get_song_bpm(slot) }
+}
+
+/// Ticks per line
+pub fn song_tpl(slot: i32) -> i32 {
+    unsafe { sv_get_song_tpl(slot) }
+}
+
+/// Global project volume (0..256, where 256 = 100%)
+pub fn song_volume(slot: i32) -> i32 {
+    unsafe { sv_volume(slot, -1) }
+}
+
+pub fn set_song_volume(slot: i32, volume: i32) {
+    unsafe {
+        sv_volume(slot, volume.max(0));
+    }
+}
+
+pub fn song_length_lines(slot: i32) -> u32 {
+    unsafe { sv_get_song_length_lines(slot) }
+}
+
+pub fn song_length_frames(slot: i32) -> u32 {
+    unsafe { sv_get_song_length_frames(slot) }
+}
+
+/// Line being played, as heard at the current system time
+pub fn current_line(slot: i32) -> i32 {
+    unsafe { sv_get_current_line(slot) }
+}
+
+/// Start playback from the current position
+pub fn play(slot: i32) -> Result<()> {
+    check("sv_play", unsafe { sv_play(slot) })?;
+    Ok(())
+}
+
+/// Move the playback position to a line of the timeline; a playing song
+/// continues from there
+pub fn rewind(slot: i32, line: i32) -> Result<()> {
+    check("sv_rewind", unsafe { sv_rewind(slot, line.max(0)) })?;
+    Ok(())
+}
+
+/// Whether playback stops at the end of the project instead of looping
+pub fn autostop(slot: i32) -> bool {
+    unsafe { sv_get_autostop(slot) == 1 }
+}
+
+pub fn set_autostop(slot: i32, autostop: bool) -> Result<()> {
+    check("sv_set_autostop", unsafe {
+        sv_set_autostop(slot, autostop as i32)
+    })?;
+    Ok(())
+}
+
+/// Queue a tempo change (BPM and ticks per line)
+///
+/// SunVox has no setter for the project speed, so this sends the 0x0F
+/// effect; it takes effect on the next `sv_audio_callback`, see
+/// `apply_pending_events`.
+pub fn set_song_speed(slot: i32, bpm: i32, tpl: i32) -> Result<()> {
+    let bpm = bpm.clamp(0x20, 16000);
+    let tpl = tpl.clamp(1, 0x1F);
+    for value in [bpm, tpl] {
+        check("sv_send_event", unsafe {
+            sv_send_event(slot, 0, 0, 0, 0, EFFECT_SET_SPEED as i32, value)
+        })?;
+    }
+    Ok(())
+}
+
+/// Render a few frames so that queued events (controller values sent with
+/// `sv_set_module_ctl_value`, speed changes, ...) are applied
+///
+/// Only for offline use: the rendered audio is discarded.
+pub fn apply_pending_events() {
+    let mut buffer = [0.0f32; 64 * 2];
+    unsafe {
+        sv_audio_callback(
+            buffer.as_mut_ptr() as *mut std::os::raw::c_void,
+            64,
+            0,
+            sv_get_ticks(),
+        );
+    }
+}
+
+/// Tempo at the start of each line: (BPM, ticks per line)
+pub fn time_map_speed(slot: i32, start_line: i32, lines: usize) -> Result<Vec<(u16, u16)>> {
+    let mut map = vec![0u32; lines];
+    check("sv_get_time_map", unsafe {
+        sv_get_time_map(
+            slot,
+            start_line,
+            lines as i32,
+            map.as_mut_ptr(),
+            SV_TIME_MAP_SPEED,
+        )
+    })?;
+    Ok(map
+        .into_iter()
+        .map(|v| ((v & 0xFFFF) as u16, (v >> 16) as u16))
+        .collect())
+}
+
+/// Frame counter at the start of each line
+pub fn time_map_frames(slot: i32, start_line: i32, lines: usize) -> Result<Vec<u32>> {
+    let mut map = vec![0u32; lines];
+    check("sv_get_time_map", unsafe {
+        sv_get_time_map(
+            slot,
+            start_line,
+            lines as i32,
+            map.as_mut_ptr(),
+            SV_TIME_MAP_FRAMECNT,
+        )
+    })?;
+    Ok(map)
+}
@@ -0,0 +1,336 @@
+// Human-readable project format
+// Serializes a SunVox project to a stable JSON document and rebuilds it in a slot
+//
+// Only state reachable through the SunVox library API is captured: song
+// metadata, modules with their controller values, connections and pattern
+// events. Module-internal data (sampler samples, MetaModule contents, drawn
+// curves) is not part of the document, so a MetaModule is rebuilt empty: its
+// own five controllers are restored, the user controllers its project mapped
+// are not.
+
+use crate::controllers::{read_controllers, set_controller_real_value};
+use crate::error::Result;
+use crate::module_graph::{
+    clear_modules, connect_modules, new_module, set_module_color, set_module_name, set_module_xy,
+    ModuleEdge, ModuleGraph, OUTPUT_MODULE,
+};
+use crate::patterns::{
+    clear_patterns, new_pattern, pattern_info, patterns, read_pattern, set_pattern_event,
+};
+use crate::project::{
+    apply_pending_events, set_song_name, set_song_speed, set_song_volume, song_bpm, song_name,
+    song_tpl, song_volume,
+};
+use crate::sunvox_ffi::SunvoxNote;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Bumped whenever the document layout changes incompatibly
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectDocument {
+    pub format_version: u32,
+    pub name: String,
+    pub bpm: i32,
+    /// Ticks per line
+    pub tpl: i32,
+    /// Global volume (0..256)
+    pub volume: i32,
+    pub modules: Vec<ModuleDocument>,
+    pub connections: Vec<ModuleEdge>,
+    pub patterns: Vec<PatternDocument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleDocument {
+    /// Module number in the source project
+    pub id: i32,
+    pub module_type: String,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    /// Color in SunVox's 0xBBGGRR format
+    pub color: u32,
+    pub controllers: Vec<ControllerValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControllerValue {
+    pub index: i32,
+    /// Informational only; controllers are restored by index
+    pub name: String,
+    /// Real value
+    pub value: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternDocument {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub tracks: i32,
+    pub lines: i32,
+    /// Non-empty cells only
+    pub events: Vec<PatternEvent>,
+}
+
+/// A non-empty pattern cell; `module` is the module number + 1 as in SunVox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatternEvent {
+    pub line: i32,
+    pub track: i32,
+    pub note: u8,
+    pub vel: u8,
+    pub module: u16,
+    pub ctl: u16,
+    pub ctl_val: u16,
+}
+
+impl PatternEvent {
+    fn to_note(self) -> SunvoxNote {
+        SunvoxNote {
+            note: self.note,
+            vel: self.vel,
+            module: self.module,
+            ctl: self.ctl,
+            ctl_val: self.ctl_val,
+        }
+    }
+}
+
+impl ProjectDocument {
+    /// Capture the project loaded in `slot`
+    pub fn capture(slot: i32) -> Result<Self> {
+        let graph = ModuleGraph::read(slot)?;
+
+        let modules = graph
+            .nodes
+            .iter()
+            .map(|node| ModuleDocument {
+                id: node.id,
+                module_type: node.module_type.clone(),
+                name: node.name.clone(),
+                x: node.x,
+                y: node.y,
+                color: node.color,
+                controllers: read_controllers(slot, node.id)
+                    .into_iter()
+                    .map(|ctl| ControllerValue {
+                        index: ctl.index,
+                        name: ctl.name,
+                        value: ctl.value,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let patterns = patterns(slot)
+            .into_iter()
+            .filter_map(|id| pattern_info(slot, id))
+            .map(|info| {
+                let tracks = info.tracks.max(1);
+                let events = read_pattern(slot, info.id)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, n)| *n != SunvoxNote::default())
+                    .map(|(i, n)| PatternEvent {
+                        line: i as i32 / tracks,
+                        track: i as i32 % tracks,
+                        note: n.note,
+                        vel: n.vel,
+                        module: n.module,
+                        ctl: n.ctl,
+                        ctl_val: n.ctl_val,
+                    })
+                    .collect();
+                PatternDocument {
+                    name: info.name,
+                    x: info.x,
+                    y: info.y,
+                    tracks: info.tracks,
+                    lines: info.lines,
+                    events,
+                }
+            })
+            .collect();
+
+        Ok(ProjectDocument {
+            format_version: FORMAT_VERSION,
+            name: song_name(slot),
+            bpm: song_bpm(slot),
+            tpl: song_tpl(slot),
+            volume: song_volume(slot),
+            modules,
+            connections: graph.edges,
+            patterns,
+        })
+    }
+
+    /// Replace the project in `slot` with the contents of this document
+    ///
+    /// Renders a few frames of audio to apply controller values and the
+    /// song speed, so this must not run on a slot that is being played.
+    pub fn rebuild(&self, slot: i32) -> Result<()> {
+        clear_patterns(slot)?;
+        clear_modules(slot)?;
+        set_song_name(slot, &self.name)?;
+        set_song_speed(slot, self.bpm, self.tpl)?;
+        set_song_volume(slot, self.volume);
+
+        // Module numbers may differ from the source project (gaps left by
+        // removed modules are not recreated), so remember the mapping
+        let mut ids = HashMap::from([(OUTPUT_MODULE, OUTPUT_MODULE)]);
+        for module in &self.modules {
+            let id = if module.id == OUTPUT_MODULE {
+                // The Output module survives clear_modules, but keeps the
+                // name and position of the project it was part of
+                set_module_name(slot, OUTPUT_MODULE, &module.name)?;
+                set_module_xy(slot, OUTPUT_MODULE, module.x, module.y)?;
+                OUTPUT_MODULE
+            } else {
+                new_module(
+                    slot,
+                    &module.module_type,
+                    &module.name,
+                    module.x,
+                    module.y,
+                    0,
+                )?
+            };
+            ids.insert(module.id, id);
+
+            let (r, g, b) = (
+                module.color as u8,
+                (module.color >> 8) as u8,
+                (module.color >> 16) as u8,
+            );
+            set_module_color(slot, id, (r, g, b))?;
+            for ctl in &module.controllers {
+                set_controller_real_value(slot, id, ctl.index, ctl.value)?;
+            }
+        }
+
+        for edge in &self.connections {
+            if let (Some(&source), Some(&destination)) =
+                (ids.get(&edge.source), ids.get(&edge.destination))
+            {
+                connect_modules(slot, source, destination)?;
+            }
+        }
+
+        for pattern in &self.patterns {
+            let id = new_pattern(
+                slot,
+                &pattern.name,
+                pattern.x,
+                pattern.y,
+                pattern.tracks,
+                pattern.lines,
+            )?;
+            for event in &pattern.events {
+                let mut note = event.to_note();
+                if note.module > 0 {
+                    let source = note.module as i32 - 1;
+                    note.module = ids.get(&source).map_or(0, |&m| (m + 1) as u16);
+                }
+                set_pattern_event(slot, id, event.track, event.line, note)?;
+            }
+        }
+
+        apply_pending_events();
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("project document is always serializable")
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metamodule::{user_controllers, METAMODULE, USER_CTLS_OFFSET};
+    use crate::project::{load_project, save_project};
+    use crate::sunvox_ffi::{sv_play_from_beginning, sv_stop};
+    use crate::test_support::{render, resource_path, with_engine};
+
+    /// Load a project and render its first two seconds
+    fn render_song(slot: i32, path: &str) -> Vec<f32> {
+        load_project(slot, path).unwrap();
+        unsafe {
+            sv_play_from_beginning(slot);
+        }
+        let audio = render(88200);
+        unsafe {
+            sv_stop(slot);
+            sv_stop(slot);
+        }
+        audio
+    }
+
+    #[test]
+    fn test_round_trip_bundled_songs() {
+        with_engine(|slot| {
+            for song in ["song01.sunvox", "song02.sunvox"] {
+                let original = render_song(slot, &resource_path(song));
+
+                load_project(slot, &resource_path(song)).unwrap();
+                let document = ProjectDocument::capture(slot).unwrap();
+                let json = document.to_json();
+                let parsed = ProjectDocument::from_json(&json).unwrap();
+                assert_eq!(parsed, document, "{}: JSON round trip", song);
+
+                // The Output module is kept by rebuild, not recreated
+                set_module_name(slot, OUTPUT_MODULE, "Moved").unwrap();
+                set_module_xy(slot, OUTPUT_MODULE, 12, 34).unwrap();
+                parsed.rebuild(slot).unwrap();
+                assert_eq!(ProjectDocument::capture(slot).unwrap(), document);
+
+                let rebuilt_path = std::env::temp_dir().join(format!("round_trip_{}", song));
+                let rebuilt_path = rebuilt_path.to_str().unwrap();
+                save_project(slot, rebuilt_path).unwrap();
+                let rebuilt = render_song(slot, rebuilt_path);
+                let _ = std::fs::remove_file(rebuilt_path);
+
+                let max_diff = original
+                    .iter()
+                    .zip(&rebuilt)
+                    .map(|(a, b)| (a - b).abs())
+                    .fold(0.0f32, f32::max);
+                assert!(max_diff < 1e-4, "{}: renders differ by {}", song, max_diff);
+            }
+
+            // song03/song04 play through MetaModules, which come back empty:
+            // everything else matches, and of their controllers only the
+            // MetaModule's own
+            for song in ["song03.sunvox", "song04.sunvox"] {
+                load_project(slot, &resource_path(song)).unwrap();
+                let document = ProjectDocument::capture(slot).unwrap();
+                document.rebuild(slot).unwrap();
+                let rebuilt = ProjectDocument::capture(slot).unwrap();
+                assert_eq!(rebuilt.connections, document.connections, "{}", song);
+                assert_eq!(rebuilt.patterns, document.patterns, "{}", song);
+                assert_eq!(rebuilt.modules.len(), document.modules.len(), "{}", song);
+
+                let mut metamodules = 0;
+                for (before, after) in document.modules.iter().zip(&rebuilt.modules) {
+                    if before.module_type != METAMODULE {
+                        assert_eq!(after, before, "{}", song);
+                        continue;
+                    }
+                    metamodules += 1;
+                    let own = USER_CTLS_OFFSET as usize;
+                    assert!(before.controllers.len() > own, "{}", song);
+                    assert_eq!(after.controllers[..own], before.controllers[..own]);
+                    assert!(user_controllers(slot, after.id).is_empty());
+                }
+                assert_eq!(metamodules, 1, "{}", song);
+            }
+        });
+    }
+}
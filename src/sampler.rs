@@ -0,0 +1,274 @@
+// Sampler API
+// Loads samples and instruments into Sampler modules and edits their samples
+//
+// A Sampler takes WAV, AIFF, OGG, MP3, FLAC and XI files, from a path or from
+// memory. Loading into `Instrument` replaces the whole instrument (an XI
+// brings its samples, note map and envelopes); loading into `Sample(n)`
+// replaces one sample and keeps the rest. Loading locks the module itself,
+// so no slot lock is needed.
+
+use crate::error::{c_string, check, Result, SunVoxError};
+use crate::module_graph::{expect_module_type, new_module, remove_module};
+use crate::sunvox_ffi::*;
+use std::os::raw::c_void;
+
+/// Module type of the Sampler
+pub const SAMPLER: &str = "Sampler";
+
+/// MIDI note a sample with relative note 0 plays at its recorded pitch (C5 in
+/// SunVox)
+pub const SAMPLER_BASE_NOTE: i32 = 60;
+
+/// What a load replaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplerTarget {
+    /// The whole instrument
+    Instrument,
+    /// One sample slot
+    Sample(i32),
+}
+
+impl SamplerTarget {
+    fn sample_slot(self) -> i32 {
+        match self {
+            SamplerTarget::Instrument => -1,
+            SamplerTarget::Sample(n) => n.max(0),
+        }
+    }
+}
+
+/// Load a sample or instrument file into a Sampler
+pub fn load_sampler_file(slot: i32, module: i32, path: &str, target: SamplerTarget) -> Result<()> {
+    expect_module_type(slot, module, SAMPLER)?;
+    let path_c = c_string(path)?;
+    check("sv_sampler_load", unsafe {
+        sv_sampler_load(slot, module, path_c.as_ptr(), target.sample_slot())
+    })?;
+    Ok(())
+}
+
+/// Load a sample or instrument from the contents of a file
+pub fn load_sampler_bytes(
+    slot: i32,
+    module: i32,
+    data: &[u8],
+    target: SamplerTarget,
+) -> Result<()> {
+    expect_module_type(slot, module, SAMPLER)?;
+    // SunVox only reads the data, but its signature takes a mutable pointer
+    check("sv_sampler_load_from_memory", unsafe {
+        sv_sampler_load_from_memory(
+            slot,
+            module,
+            data.as_ptr() as *mut c_void,
+            data.len() as u32,
+            target.sample_slot(),
+        )
+    })?;
+    Ok(())
+}
+
+/// Create a Sampler playing a sample or instrument file; returns its number
+///
+/// The module is removed again if the file can't be loaded.
+pub fn new_sampler_from_file(slot: i32, name: &str, path: &str, x: i32, y: i32) -> Result<i32> {
+    let module = new_module(slot, SAMPLER, name, x, y, 0)?;
+    load_sampler_file(slot, module, path, SamplerTarget::Instrument)
+        .inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+        .map(|_| module)
+}
+
+/// Create a Sampler playing a sample or instrument from memory; returns its
+/// number
+pub fn new_sampler_from_bytes(slot: i32, name: &str, data: &[u8], x: i32, y: i32) -> Result<i32> {
+    let module = new_module(slot, SAMPLER, name, x, y, 0)?;
+    load_sampler_bytes(slot, module, data, SamplerTarget::Instrument)
+        .inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+        .map(|_| module)
+}
+
+/// Parameters of a sample, numbered as `sv_sampler_par` expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleParam {
+    /// First frame of the loop
+    LoopBegin = 0,
+    /// Loop length in frames
+    LoopLength = 1,
+    /// `LoopType` as a number
+    LoopType = 2,
+    /// 1: leave the loop when the note is released
+    LoopRelease = 3,
+    /// 0..64
+    Volume = 4,
+    /// 0 (left) .. 128 (center) .. 255 (right)
+    Panning = 5,
+    /// -128..127, in 1/128 of a semitone
+    Finetune = 6,
+    /// -128..127 semitones
+    RelativeNote = 7,
+    /// Frame playback starts at
+    StartPosition = 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopType {
+    #[default]
+    Off = 0,
+    Forward = 1,
+    PingPong = 2,
+}
+
+impl LoopType {
+    pub fn from_raw(value: i32) -> Self {
+        match value & 3 {
+            1 => LoopType::Forward,
+            2 => LoopType::PingPong,
+            _ => LoopType::Off,
+        }
+    }
+}
+
+/// Read a sample parameter (0 if the sample doesn't exist)
+pub fn sample_param(slot: i32, module: i32, sample: i32, param: SampleParam) -> i32 {
+    unsafe { sv_sampler_par(slot, module, sample, param as i32, 0, 0) }
+}
+
+/// Set a sample parameter
+///
+/// SunVox ignores values out of range (loop points past the end of the
+/// sample, for instance); those, and a missing sample, are reported as
+/// `InvalidValue`.
+pub fn set_sample_param(
+    slot: i32,
+    module: i32,
+    sample: i32,
+    param: SampleParam,
+    value: i32,
+) -> Result<()> {
+    expect_module_type(slot, module, SAMPLER)?;
+    unsafe {
+        sv_sampler_par(slot, module, sample, param as i32, value, 1);
+    }
+    if sample_param(slot, module, sample, param) != value {
+        return Err(SunVoxError::InvalidValue(format!(
+            "{:?} = {} for sample {} of module {}",
+            param, value, sample, module
+        )));
+    }
+    Ok(())
+}
+
+/// Loop `length` frames from `begin`; a length of 0 turns the loop off
+pub fn set_sample_loop(
+    slot: i32,
+    module: i32,
+    sample: i32,
+    begin: i32,
+    length: i32,
+    loop_type: LoopType,
+) -> Result<()> {
+    // The loop has to fit the sample at every step, so shrink it first
+    set_sample_param(slot, module, sample, SampleParam::LoopLength, 0)?;
+    set_sample_param(slot, module, sample, SampleParam::LoopBegin, begin)?;
+    set_sample_param(slot, module, sample, SampleParam::LoopLength, length)?;
+    let loop_type = if length > 0 { loop_type } else { LoopType::Off };
+    set_sample_param(
+        slot,
+        module,
+        sample,
+        SampleParam::LoopType,
+        loop_type as i32,
+    )
+}
+
+/// Make a sample recorded at MIDI note `note` play at its own pitch on that
+/// note
+pub fn set_sample_base_note(slot: i32, module: i32, sample: i32, note: u8) -> Result<()> {
+    set_sample_param(
+        slot,
+        module,
+        sample,
+        SampleParam::RelativeNote,
+        SAMPLER_BASE_NOTE - note as i32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_timing::{send_event, set_immediate_events};
+    use crate::module_graph::{clear_modules, connect_modules, module_exists, OUTPUT_MODULE};
+    use crate::scope::ScopeCapture;
+    use crate::test_support::{render, resource_path, with_engine};
+
+    #[test]
+    fn test_load_and_edit_samples() {
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            set_immediate_events(slot).unwrap();
+            let flute =
+                new_sampler_from_file(slot, "Flute", &resource_path("flute.xi"), 0, 0).unwrap();
+            let drums_ogg = std::fs::read(resource_path("drums.ogg")).unwrap();
+            let drums = new_sampler_from_bytes(slot, "Drums", &drums_ogg, 0, 0).unwrap();
+            connect_modules(slot, flute, OUTPUT_MODULE).unwrap();
+            connect_modules(slot, drums, OUTPUT_MODULE).unwrap();
+
+            // Not a sampler, not a sample file
+            let generator = new_module(slot, "Generator", "Gen", 0, 0, 0).unwrap();
+            assert!(matches!(
+                load_sampler_bytes(slot, generator, &drums_ogg, SamplerTarget::Sample(0)),
+                Err(SunVoxError::WrongModuleType { .. })
+            ));
+            assert!(new_sampler_from_bytes(slot, "Junk", b"not a sample", 0, 0).is_err());
+            assert!(!module_exists(slot, generator + 1));
+
+            // Sample parameters
+            set_sample_base_note(slot, drums, 0, 48).unwrap();
+            assert_eq!(sample_param(slot, drums, 0, SampleParam::RelativeNote), 12);
+            set_sample_param(slot, drums, 0, SampleParam::Volume, 32).unwrap();
+            assert!(set_sample_param(slot, drums, 0, SampleParam::Volume, 65).is_err());
+            assert_eq!(sample_param(slot, drums, 0, SampleParam::Volume), 32);
+            set_sample_loop(slot, drums, 0, 100, 1000, LoopType::PingPong).unwrap();
+            assert_eq!(sample_param(slot, drums, 0, SampleParam::LoopBegin), 100);
+            assert_eq!(sample_param(slot, drums, 0, SampleParam::LoopLength), 1000);
+            assert_eq!(
+                LoopType::from_raw(sample_param(slot, drums, 0, SampleParam::LoopType)),
+                LoopType::PingPong
+            );
+            assert!(set_sample_loop(slot, drums, 0, 0, 1 << 30, LoopType::Forward).is_err());
+            assert!(set_sample_param(slot, drums, 5, SampleParam::Volume, 10).is_err());
+
+            // Both play
+            let mut capture = ScopeCapture::new(4096);
+            let rings = [capture.add(flute, 0), capture.add(drums, 0)];
+            for (track, module) in [flute, drums].into_iter().enumerate() {
+                let note = SunvoxNote {
+                    note: 61,
+                    vel: 129,
+                    module: module as u16 + 1,
+                    ..Default::default()
+                };
+                send_event(slot, track as i32, note).unwrap();
+            }
+            for _ in 0..8 {
+                render(1024);
+                capture.capture(slot, 1024);
+            }
+            for ring in rings {
+                assert!(ring.peak(4096) > 0.01);
+            }
+            for track in 0..2 {
+                let off = SunvoxNote {
+                    note: NOTECMD_NOTE_OFF,
+                    ..Default::default()
+                };
+                send_event(slot, track, off).unwrap();
+            }
+            render(1024);
+        });
+    }
+}
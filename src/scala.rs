@@ -0,0 +1,410 @@
+// Scala tunings
+// Reads Scala scale (.scl) and keyboard mapping (.kbm) files into pitch tables
+//
+// A scale lists the degrees of one period (usually an octave) in cents or as
+// ratios; a keyboard mapping says which MIDI note plays which degree and
+// which note sounds at the reference frequency. `PitchTable` combines the two
+// into a SunVox pitch for every MIDI note, to be played with
+// `NOTECMD_SET_PITCH`.
+//
+// SunVox plays MIDI note n as its note n + 1, an octave above the MIDI
+// frequency. Pitch tables keep that convention, so a 12-tone equal
+// temperament scale gives exactly the usual note pitches.
+
+use crate::pitch::{note_pitch, NOTE_C0_PITCH, PITCH_PER_SEMITONE};
+use crate::tuning::STANDARD_A4;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Errors found while reading Scala files
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScalaError {
+    /// The file ends before a required line
+    MissingLine(&'static str),
+    /// A line that doesn't hold the value it should (1-based line number)
+    InvalidLine { line: usize, text: String },
+    /// The scale has no degrees
+    EmptyScale,
+    /// The keyboard mapping leaves its reference note unmapped
+    UnmappedReference(u8),
+}
+
+impl fmt::Display for ScalaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScalaError::MissingLine(what) => write!(f, "missing {}", what),
+            ScalaError::InvalidLine { line, text } => {
+                write!(f, "line {}: invalid {:?}", line, text)
+            }
+            ScalaError::EmptyScale => write!(f, "the scale has no degrees"),
+            ScalaError::UnmappedReference(note) => {
+                write!(f, "reference note {} is not mapped", note)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScalaError {}
+
+/// Lines of a Scala file that aren't comments, with their line numbers
+fn data_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim_end_matches('\r')))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+/// First word of a line, parsed
+fn parse_value<T: std::str::FromStr>((line, text): (usize, &str)) -> Result<T, ScalaError> {
+    text.split_whitespace()
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| ScalaError::InvalidLine {
+            line,
+            text: text.to_string(),
+        })
+}
+
+/// A scale: the degrees of one period in cents above the first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    /// Degrees 1..=n; the last one is the period
+    pub degrees: Vec<f64>,
+}
+
+impl Scale {
+    /// Parse the contents of a `.scl` file
+    pub fn parse(text: &str) -> Result<Self, ScalaError> {
+        let mut lines = data_lines(text);
+        let description = lines
+            .next()
+            .ok_or(ScalaError::MissingLine("description"))?
+            .1
+            .trim()
+            .to_string();
+        let count: usize = parse_value(lines.next().ok_or(ScalaError::MissingLine("note count"))?)?;
+        if count == 0 {
+            return Err(ScalaError::EmptyScale);
+        }
+        let degrees = lines
+            .take(count)
+            .map(parse_degree)
+            .collect::<Result<Vec<_>, _>>()?;
+        if degrees.len() < count {
+            return Err(ScalaError::MissingLine("scale degree"));
+        }
+        Ok(Self {
+            description,
+            degrees,
+        })
+    }
+
+    /// `notes` equal steps per octave
+    pub fn equal_temperament(notes: usize) -> Self {
+        let notes = notes.max(1);
+        Self {
+            description: format!("{}-tone equal temperament", notes),
+            degrees: (1..=notes)
+                .map(|i| 1200.0 * i as f64 / notes as f64)
+                .collect(),
+        }
+    }
+
+    pub fn period(&self) -> f64 {
+        self.degrees.last().copied().unwrap_or(1200.0)
+    }
+
+    /// Cents of a degree counted from degree 0, in any period
+    pub fn cents(&self, degree: i32) -> f64 {
+        let size = self.degrees.len() as i32;
+        let period = degree.div_euclid(size);
+        let step = degree.rem_euclid(size);
+        let within = if step == 0 {
+            0.0
+        } else {
+            self.degrees[step as usize - 1]
+        };
+        period as f64 * self.period() + within
+    }
+}
+
+/// A degree line: cents if it has a period, otherwise a ratio like `3/2`
+fn parse_degree((line, text): (usize, &str)) -> Result<f64, ScalaError> {
+    let invalid = || ScalaError::InvalidLine {
+        line,
+        text: text.to_string(),
+    };
+    let word = text.split_whitespace().next().ok_or_else(invalid)?;
+    if word.contains('.') {
+        return word.parse().map_err(|_| invalid());
+    }
+    let (numerator, denominator) = word.split_once('/').unwrap_or((word, "1"));
+    let numerator: f64 = numerator.parse().map_err(|_| invalid())?;
+    let denominator: f64 = denominator.parse().map_err(|_| invalid())?;
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(1200.0 * (numerator / denominator).log2())
+}
+
+/// Which MIDI notes play which scale degrees
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMap {
+    /// First and last MIDI notes that play; the others are silent
+    pub first_note: u8,
+    pub last_note: u8,
+    /// Note that plays degree 0
+    pub middle_note: u8,
+    /// Note that sounds at `reference_frequency`
+    pub reference_note: u8,
+    pub reference_frequency: f64,
+    /// Degree a repetition of the map moves up by (0 for the scale size)
+    pub octave_degree: i32,
+    /// Degree of each key in one repetition, `None` for unmapped keys;
+    /// empty maps consecutive notes to consecutive degrees
+    pub keys: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMap {
+    /// Every note, degree 0 on middle C and A4 at 440 Hz
+    fn default() -> Self {
+        Self {
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 69,
+            reference_frequency: 440.0,
+            octave_degree: 0,
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMap {
+    /// Parse the contents of a `.kbm` file
+    pub fn parse(text: &str) -> Result<Self, ScalaError> {
+        let mut lines = data_lines(text).filter(|(_, line)| !line.trim().is_empty());
+        let mut next = |what| lines.next().ok_or(ScalaError::MissingLine(what));
+        let size: usize = parse_value(next("map size")?)?;
+        let first_note = parse_value(next("first note")?)?;
+        let last_note = parse_value(next("last note")?)?;
+        let middle_note = parse_value(next("middle note")?)?;
+        let reference_note = parse_value(next("reference note")?)?;
+        let reference_frequency = parse_value(next("reference frequency")?)?;
+        let octave_degree = parse_value(next("octave degree")?)?;
+        let mut keys = Vec::with_capacity(size);
+        // Keys missing at the end of the file are unmapped
+        for _ in 0..size {
+            let key = match lines.next() {
+                Some((_, text)) if text.trim().starts_with('x') => None,
+                Some(line) => Some(parse_value(line)?),
+                None => None,
+            };
+            keys.push(key);
+        }
+        Ok(Self {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_frequency,
+            octave_degree,
+            keys,
+        })
+    }
+
+    /// Scale degree a MIDI note plays, if any
+    pub fn degree(&self, note: u8, scale_size: usize) -> Option<i32> {
+        if note < self.first_note || note > self.last_note {
+            return None;
+        }
+        let offset = note as i32 - self.middle_note as i32;
+        if self.keys.is_empty() {
+            return Some(offset);
+        }
+        let size = self.keys.len() as i32;
+        let octave_degree = match self.octave_degree {
+            0 => scale_size as i32,
+            degree => degree,
+        };
+        let key = self.keys[offset.rem_euclid(size) as usize]?;
+        Some(offset.div_euclid(size) * octave_degree + key)
+    }
+}
+
+/// SunVox pitch of every MIDI note under a scale and keyboard mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PitchTable {
+    pitches: [Option<u16>; 128],
+}
+
+impl PitchTable {
+    pub fn new(scale: &Scale, map: &KeyboardMap) -> Result<Self, ScalaError> {
+        let size = scale.degrees.len();
+        if size == 0 {
+            return Err(ScalaError::EmptyScale);
+        }
+        let reference = map
+            .degree(map.reference_note, size)
+            .ok_or(ScalaError::UnmappedReference(map.reference_note))?;
+        let reference_cents = scale.cents(reference);
+        let mut pitches = [None; 128];
+        for (note, pitch) in pitches.iter_mut().enumerate() {
+            let Some(degree) = map.degree(note as u8, size) else {
+                continue;
+            };
+            // Offset from the equal tempered note, so 12-TET stays exact
+            let cents = scale.cents(degree) - reference_cents
+                + 1200.0 * (map.reference_frequency / STANDARD_A4 as f64).log2()
+                - 100.0 * (note as f64 - 69.0);
+            let offset = cents * PITCH_PER_SEMITONE as f64 / 100.0;
+            let sunvox_pitch = (note_pitch(note as u8 + 1) as f64 - offset).round();
+            *pitch = Some(sunvox_pitch.clamp(0.0, NOTE_C0_PITCH as f64) as u16);
+        }
+        Ok(Self { pitches })
+    }
+
+    /// Pitch to play for a MIDI note, `None` for unmapped notes
+    pub fn pitch(&self, note: u8) -> Option<u16> {
+        self.pitches.get(note as usize).copied().flatten()
+    }
+}
+
+/// The text of a `.scl` file and optionally a `.kbm` file, kept so a tuning
+/// can be stored in plugin state and rebuilt without the files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScalaTuning {
+    pub scale: String,
+    pub keyboard_map: Option<String>,
+}
+
+impl ScalaTuning {
+    /// Read a scale and optionally a keyboard mapping, checking that they
+    /// make a pitch table
+    pub fn from_files(
+        scale: impl AsRef<Path>,
+        keyboard_map: Option<impl AsRef<Path>>,
+    ) -> std::io::Result<Self> {
+        let tuning = Self {
+            scale: std::fs::read_to_string(scale)?,
+            keyboard_map: keyboard_map.map(std::fs::read_to_string).transpose()?,
+        };
+        tuning
+            .pitch_table()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(tuning)
+    }
+
+    pub fn pitch_table(&self) -> Result<PitchTable, ScalaError> {
+        let scale = Scale::parse(&self.scale)?;
+        let map = match &self.keyboard_map {
+            Some(text) => KeyboardMap::parse(text)?,
+            None => KeyboardMap::default(),
+        };
+        PitchTable::new(&scale, &map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEANTONE: &str = "! meanquar.scl
+!
+1/4-comma meantone scale. Pietro Aaron's temperament (1523)
+ 12
+!
+ 76.04900
+ 193.15686
+ 310.26471
+ 5/4
+ 503.42157
+ 579.47057
+ 696.57843
+ 25/16
+ 889.73529
+ 1006.84314
+ 1082.89214
+ 2/1
+";
+
+    // White keys only, D4 at 293.66 Hz
+    const WHITE_KEYS: &str = "! white.kbm
+12
+0
+127
+60
+62
+293.66
+7
+! map
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    #[test]
+    fn test_scala_files() {
+        let scale = Scale::parse(MEANTONE).unwrap();
+        assert_eq!(scale.degrees.len(), 12);
+        assert!((scale.degrees[3] - 386.3137).abs() < 1e-3);
+        assert_eq!(scale.period(), 1200.0);
+        assert!((scale.cents(-1) + 117.1079).abs() < 1e-3);
+        assert_eq!(
+            Scale::parse("desc\n2\n100.0\nabc\n"),
+            Err(ScalaError::InvalidLine {
+                line: 4,
+                text: "abc".into()
+            })
+        );
+        assert_eq!(
+            Scale::parse("desc\n3\n100.0\n"),
+            Err(ScalaError::MissingLine("scale degree"))
+        );
+
+        // 12-TET with the default map gives the usual note pitches
+        let table =
+            PitchTable::new(&Scale::equal_temperament(12), &KeyboardMap::default()).unwrap();
+        for note in 0..=120 {
+            assert_eq!(table.pitch(note), Some(note_pitch(note + 1) as u16));
+        }
+
+        let map = KeyboardMap::parse(WHITE_KEYS).unwrap();
+        assert_eq!(map.keys[1], None);
+        assert_eq!(map.degree(72, 7), Some(7));
+        let diatonic = Scale::parse("C major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1\n").unwrap();
+        let table = PitchTable::new(&diatonic, &map).unwrap();
+        assert_eq!(table.pitch(61), None);
+        assert_eq!(table.pitch(62), Some(note_pitch(63) as u16));
+        // A 5/4 major third above C, 17.6 cents below the equal tempered one
+        let third = table.pitch(64).unwrap() as i32;
+        assert_eq!(third - note_pitch(65), 45);
+        assert_eq!(
+            PitchTable::new(
+                &diatonic,
+                &KeyboardMap {
+                    reference_note: 61,
+                    ..map
+                }
+            ),
+            Err(ScalaError::UnmappedReference(61))
+        );
+
+        let tuning = ScalaTuning {
+            scale: MEANTONE.into(),
+            keyboard_map: None,
+        };
+        assert!(tuning.pitch_table().unwrap().pitch(69).is_some());
+    }
+}
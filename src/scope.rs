@@ -0,0 +1,265 @@
+// Module oscilloscopes
+// Captures the output of module channels into ring buffers
+//
+// `sv_get_module_scope2` returns the latest samples a module channel produced
+// (for the Output module, what it received). `ScopeCapture` reads the taps it
+// was given after every rendered block and appends the samples to a
+// `ScopeRing` per tap. The audio thread is the only writer; any number of
+// readers (a scope view, a test) copy the latest samples without locking.
+//
+// SunVox positions the scope data by the wall clock, so when blocks are
+// rendered faster than realtime consecutive captures may overlap or skip a
+// little. That is fine for display and for telling whether a module sounds.
+
+use crate::sunvox_ffi::*;
+use std::sync::atomic::{fence, AtomicI16, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Samples SunVox keeps per module channel
+pub const ENGINE_SCOPE_SIZE: usize = 16384;
+
+/// Read the latest samples of a module channel into `dest`, oldest first
+///
+/// Returns the number of samples received: 0 if the module or the channel
+/// doesn't exist.
+pub fn read_scope(slot: i32, module: i32, channel: i32, dest: &mut [i16]) -> usize {
+    let len = dest.len().min(ENGINE_SCOPE_SIZE);
+    if module < 0 || channel < 0 || len == 0 {
+        return 0;
+    }
+    let received =
+        unsafe { sv_get_module_scope2(slot, module, channel, dest.as_mut_ptr(), len as u32) };
+    (received as usize).min(len)
+}
+
+/// Sample value to -1.0..1.0
+pub fn sample_to_f32(sample: i16) -> f32 {
+    sample as f32 / 32768.0
+}
+
+/// Ring buffer with one writer and lock-free readers
+#[derive(Debug)]
+pub struct ScopeRing {
+    samples: Box<[AtomicI16]>,
+    /// Samples pushed so far
+    written: AtomicU64,
+    /// Where the push in progress ends; readers drop what it may overwrite
+    writing: AtomicU64,
+}
+
+impl ScopeRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicI16::new(0)).collect(),
+            written: AtomicU64::new(0),
+            writing: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Samples pushed since the ring was created
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Append samples; only one thread may push
+    pub fn push(&self, samples: &[i16]) {
+        let capacity = self.capacity() as u64;
+        let start = self.written.load(Ordering::Relaxed);
+        let end = start + samples.len() as u64;
+        self.writing.store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+        // Only the last `capacity` samples survive anyway
+        let skip = end.saturating_sub(capacity).saturating_sub(start);
+        for (i, &sample) in samples.iter().enumerate().skip(skip as usize) {
+            let index = ((start + i as u64) % capacity) as usize;
+            self.samples[index].store(sample, Ordering::Relaxed);
+        }
+        self.written.store(end, Ordering::Release);
+    }
+
+    /// Copy the latest samples into `dest`, oldest first
+    ///
+    /// Returns how many were copied: at most `dest.len()`, fewer when less
+    /// was pushed or when a concurrent push overwrote the oldest ones.
+    pub fn read_latest(&self, dest: &mut [i16]) -> usize {
+        let capacity = self.capacity() as u64;
+        let end = self.written.load(Ordering::Acquire);
+        let count = (dest.len() as u64).min(capacity).min(end);
+        let start = end - count;
+        for (i, slot) in dest.iter_mut().take(count as usize).enumerate() {
+            let index = ((start + i as u64) % capacity) as usize;
+            *slot = self.samples[index].load(Ordering::Relaxed);
+        }
+
+        fence(Ordering::Acquire);
+        let overwritten = self
+            .writing
+            .load(Ordering::Relaxed)
+            .saturating_sub(capacity)
+            .saturating_sub(start)
+            .min(count);
+        dest.copy_within(overwritten as usize..count as usize, 0);
+        (count - overwritten) as usize
+    }
+
+    /// The latest `samples` samples, oldest first
+    pub fn snapshot(&self, samples: usize) -> Vec<i16> {
+        let mut dest = vec![0; samples.min(self.capacity())];
+        let count = self.read_latest(&mut dest);
+        dest.truncate(count);
+        dest
+    }
+
+    /// Peak of the latest `samples` samples (0.0..1.0)
+    pub fn peak(&self, samples: usize) -> f32 {
+        self.snapshot(samples)
+            .into_iter()
+            .map(|s| sample_to_f32(s).abs())
+            .fold(0.0, f32::max)
+    }
+}
+
+/// A module channel being captured
+#[derive(Debug, Clone)]
+struct ScopeTap {
+    module: i32,
+    channel: i32,
+    ring: Arc<ScopeRing>,
+}
+
+/// Captures module channels block by block on the audio thread
+#[derive(Debug)]
+pub struct ScopeCapture {
+    /// Ring size of new taps
+    capacity: usize,
+    taps: Vec<ScopeTap>,
+    /// Allocated once, so capturing doesn't allocate
+    scratch: Vec<i16>,
+}
+
+impl Default for ScopeCapture {
+    fn default() -> Self {
+        Self::new(ENGINE_SCOPE_SIZE)
+    }
+}
+
+impl ScopeCapture {
+    /// Taps keep the last `capacity` samples
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            taps: Vec::new(),
+            scratch: vec![0; ENGINE_SCOPE_SIZE],
+        }
+    }
+
+    /// Start capturing a channel of a module; returns its ring, which readers
+    /// keep
+    ///
+    /// Adding a channel that is already captured returns the existing ring.
+    pub fn add(&mut self, module: i32, channel: i32) -> Arc<ScopeRing> {
+        if let Some(ring) = self.ring(module, channel) {
+            return ring;
+        }
+        let ring = Arc::new(ScopeRing::new(self.capacity));
+        self.taps.push(ScopeTap {
+            module,
+            channel,
+            ring: ring.clone(),
+        });
+        ring
+    }
+
+    pub fn remove(&mut self, module: i32, channel: i32) {
+        self.taps
+            .retain(|t| (t.module, t.channel) != (module, channel));
+    }
+
+    pub fn ring(&self, module: i32, channel: i32) -> Option<Arc<ScopeRing>> {
+        self.taps
+            .iter()
+            .find(|t| (t.module, t.channel) == (module, channel))
+            .map(|t| t.ring.clone())
+    }
+
+    /// Append the last `frames` samples of every tap; call after rendering
+    /// each block
+    pub fn capture(&mut self, slot: i32, frames: usize) {
+        let frames = frames.min(self.scratch.len());
+        for tap in &self.taps {
+            let received = read_scope(slot, tap.module, tap.channel, &mut self.scratch[..frames]);
+            tap.ring.push(&self.scratch[..received]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_timing::{send_event, set_immediate_events};
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::test_support::{render, with_engine};
+
+    #[test]
+    fn test_capture_module_output() {
+        let ring = ScopeRing::new(8);
+        ring.push(&[1, 2, 3]);
+        assert_eq!(ring.snapshot(16), [1, 2, 3]);
+        ring.push(&(4..=13).collect::<Vec<_>>());
+        assert_eq!(ring.written(), 13);
+        assert_eq!(ring.snapshot(16), [6, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(ring.snapshot(2), [12, 13]);
+
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            set_immediate_events(slot).unwrap();
+            let playing = new_module(slot, "Generator", "Playing", 0, 0, 0).unwrap();
+            let quiet = new_module(slot, "Generator", "Quiet", 0, 0, 0).unwrap();
+            connect_modules(slot, playing, OUTPUT_MODULE).unwrap();
+            connect_modules(slot, quiet, OUTPUT_MODULE).unwrap();
+
+            let mut capture = ScopeCapture::new(4096);
+            let playing_ring = capture.add(playing, 0);
+            let quiet_ring = capture.add(quiet, 0);
+            let output_ring = capture.add(OUTPUT_MODULE, 1);
+            assert!(Arc::ptr_eq(&capture.add(playing, 0), &playing_ring));
+            // Generators are mono
+            capture.add(playing, 1);
+
+            let note = SunvoxNote {
+                note: 61,
+                vel: 129,
+                module: playing as u16 + 1,
+                ..Default::default()
+            };
+            send_event(slot, 0, note).unwrap();
+            for _ in 0..8 {
+                render(1024);
+                capture.capture(slot, 1024);
+            }
+
+            assert_eq!(playing_ring.written(), 8 * 1024);
+            assert!(playing_ring.peak(4096) > 0.1);
+            assert!(output_ring.peak(4096) > 0.1);
+            assert_eq!(quiet_ring.peak(4096), 0.0);
+            assert_eq!(capture.ring(playing, 1).unwrap().written(), 0);
+
+            capture.remove(playing, 0);
+            assert!(capture.ring(playing, 0).is_none());
+            let mut buffer = [0i16; 16];
+            assert_eq!(read_scope(slot, 200, 0, &mut buffer), 0);
+
+            let off = SunvoxNote {
+                note: NOTECMD_NOTE_OFF,
+                module: playing as u16 + 1,
+                ..Default::default()
+            };
+            send_event(slot, 0, off).unwrap();
+            render(1024);
+        });
+    }
+}
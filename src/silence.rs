@@ -0,0 +1,113 @@
+// Silence detection
+// Tracks when the output has gone quiet, so the host can suspend processing
+//
+// `sv_audio_callback` returns 0 when it wrote nothing but zeros, which settles
+// it. Otherwise `sv_get_current_signal_level`, a coarse 0..255 meter (one
+// step is about -48 dBFS), shows clearly audible output without touching the
+// samples; only when it reads 0 is the block scanned for its peak, so quiet
+// release tails are not cut short.
+
+use crate::metering::signal_level;
+
+/// Peak below which a block counts as silent (-100 dBFS)
+pub const SILENCE_THRESHOLD: f32 = 1.0e-5;
+
+/// Whether a rendered block is audible
+///
+/// `produced_audio` is the result of `sv_audio_callback` (or `render_block`).
+pub fn is_audible(slot: i32, buffer: &[f32], produced_audio: bool) -> bool {
+    if !produced_audio {
+        return false;
+    }
+    if signal_level(slot, 0) > 0 || signal_level(slot, 1) > 0 {
+        return true;
+    }
+    buffer.iter().any(|s| s.abs() > SILENCE_THRESHOLD)
+}
+
+/// What the plugin is doing, as far as suspending it is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    /// Playing the song or live notes
+    Running,
+    /// Stopped, with at most this many frames of tail left
+    Tail(u32),
+    /// Stopped and silent
+    Silent,
+}
+
+/// Follows the output block by block to tell when a stopped song has faded out
+#[derive(Debug, Clone)]
+pub struct SilenceDetector {
+    /// Quiet frames needed before the output counts as silent
+    hold_frames: u32,
+    /// Longest tail after stopping
+    max_tail: u32,
+    quiet_frames: u32,
+    stopped_frames: u32,
+}
+
+impl SilenceDetector {
+    /// Silence has to last 100 ms; the tail is cut after `max_tail_seconds`
+    pub fn new(sample_rate: f32, max_tail_seconds: f32) -> Self {
+        Self {
+            hold_frames: (sample_rate * 0.1) as u32,
+            max_tail: (sample_rate * max_tail_seconds) as u32,
+            quiet_frames: 0,
+            stopped_frames: 0,
+        }
+    }
+
+    /// Account for a rendered block of `frames` frames
+    pub fn update(&mut self, frames: u32, running: bool, audible: bool) -> Activity {
+        self.quiet_frames = if audible {
+            0
+        } else {
+            self.quiet_frames.saturating_add(frames)
+        };
+        if running {
+            self.stopped_frames = 0;
+            return Activity::Running;
+        }
+
+        self.stopped_frames = self.stopped_frames.saturating_add(frames);
+        if self.quiet_frames >= self.hold_frames || self.stopped_frames >= self.max_tail {
+            Activity::Silent
+        } else {
+            Activity::Tail(self.max_tail - self.stopped_frames)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::with_engine;
+
+    #[test]
+    fn test_tail_ends_on_silence() {
+        let mut detector = SilenceDetector::new(1000.0, 1.0);
+        assert_eq!(detector.update(50, true, false), Activity::Running);
+        // After the stop, the tail lasts until 100 frames in a row were quiet
+        assert_eq!(detector.update(20, false, true), Activity::Tail(980));
+        assert_eq!(detector.update(50, false, false), Activity::Tail(930));
+        assert_eq!(detector.update(50, false, false), Activity::Silent);
+        assert_eq!(detector.update(50, true, true), Activity::Running);
+
+        // A tail that never fades out is cut after the maximum length
+        let mut detector = SilenceDetector::new(1000.0, 0.5);
+        let mut activity = Activity::Running;
+        for _ in 0..10 {
+            activity = detector.update(50, false, true);
+        }
+        assert_eq!(activity, Activity::Silent);
+
+        with_engine(|slot| {
+            let mut buffer = vec![0.0f32; 64];
+            assert!(!is_audible(slot, &buffer, false));
+            assert!(!is_audible(slot, &buffer, true));
+            buffer[17] = -1.0e-4;
+            assert!(is_audible(slot, &buffer, true));
+        });
+    }
+}
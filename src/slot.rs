@@ -0,0 +1,29 @@
+// Slot locking helpers
+
+use crate::sunvox_ffi::{sv_lock_slot, sv_unlock_slot};
+
+/// RAII guard around `sv_lock_slot` / `sv_unlock_slot`
+///
+/// SunVox functions marked "USE LOCK/UNLOCK" in `sunvox.h` (module and
+/// pattern creation, connections, ...) must only be called while the slot
+/// is locked. The lock is released when the guard is dropped.
+pub struct SlotLock {
+    slot: i32,
+}
+
+impl SlotLock {
+    pub fn new(slot: i32) -> Self {
+        unsafe {
+            sv_lock_slot(slot);
+        }
+        Self { slot }
+    }
+}
+
+impl Drop for SlotLock {
+    fn drop(&mut self) {
+        unsafe {
+            sv_unlock_slot(self.slot);
+        }
+    }
+}
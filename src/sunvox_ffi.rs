@@ -15,12 +15,47 @@ pub const SV_INIT_FLAG_AUDIO_INT16: u32 = 1 << 2;
 pub const SV_INIT_FLAG_AUDIO_FLOAT32: u32 = 1 << 3;
 pub const SV_INIT_FLAG_ONE_THREAD: u32 = 1 << 4;
 
+// Flags for sv_get_time_map
+pub const SV_TIME_MAP_SPEED: c_int = 0;
+pub const SV_TIME_MAP_FRAMECNT: c_int = 1;
+
 // Note commands
 pub const NOTECMD_NOTE_OFF: u8 = 128;
 pub const NOTECMD_ALL_NOTES_OFF: u8 = 129;
 pub const NOTECMD_CLEAN_SYNTHS: u8 = 130;
 pub const NOTECMD_STOP: u8 = 131;
 pub const NOTECMD_PLAY: u8 = 132;
+/// Play the pitch in XXYY: 0x0000 is the highest, 0x7800 (note C0) the lowest,
+/// 0x100 is a semitone
+pub const NOTECMD_SET_PITCH: u8 = 133;
+
+// Module flags (returned by sv_get_module_flags)
+pub const SV_MODULE_FLAG_EXISTS: u32 = 1 << 0;
+pub const SV_MODULE_FLAG_GENERATOR: u32 = 1 << 1; // Note input + Sound output
+pub const SV_MODULE_FLAG_EFFECT: u32 = 1 << 2; // Sound input + Sound output
+pub const SV_MODULE_FLAG_MUTE: u32 = 1 << 3;
+pub const SV_MODULE_FLAG_SOLO: u32 = 1 << 4;
+pub const SV_MODULE_FLAG_BYPASS: u32 = 1 << 5;
+pub const SV_MODULE_INPUTS_OFF: u32 = 16;
+pub const SV_MODULE_INPUTS_MASK: u32 = 255 << SV_MODULE_INPUTS_OFF;
+pub const SV_MODULE_OUTPUTS_OFF: u32 = 16 + 8;
+pub const SV_MODULE_OUTPUTS_MASK: u32 = 255 << SV_MODULE_OUTPUTS_OFF;
+
+/// A single pattern cell (event), matching `sunvox_note` in sunvox.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SunvoxNote {
+    /// NN: 0 = nothing, 1..127 = note number, 128 = note off, 129.. = NOTECMD_*
+    pub note: u8,
+    /// VV: Velocity 1..129, 0 = default
+    pub vel: u8,
+    /// MM: 0 = nothing, 1..65535 = module number + 1
+    pub module: u16,
+    /// 0xCCEE: CC = controller number + 1, EE = effect
+    pub ctl: u16,
+    /// 0xXXYY: Controller value or effect parameter
+    pub ctl_val: u16,
+}
 
 // External C functions from SunVox library
 #[link(name = "sunvox")]
@@ -146,6 +181,31 @@ extern "C" {
     /// 0 on success, negative on error
     pub fn sv_stop(slot: c_int) -> c_int;
 
+    /// Jump to a line of the project timeline
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `line_num`: Line number on the timeline
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_rewind(slot: c_int, line_num: c_int) -> c_int;
+
+    /// Enable or disable autostop
+    ///
+    /// When autostop is off, the project plays endlessly in a loop.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `autostop`: 0 = disable, 1 = enable
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_autostop(slot: c_int, autostop: c_int) -> c_int;
+
+    /// Get the autostop state (0 = disabled, 1 = enabled)
+    pub fn sv_get_autostop(slot: c_int) -> c_int;
+
     /// Set volume for a slot
     ///
     /// # Parameters
@@ -156,6 +216,17 @@ extern "C" {
     /// Previous volume value
     pub fn sv_volume(slot: c_int, vol: c_int) -> c_int;
 
+    /// Set the timestamp of events sent by `sv_send_event`
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `set`: 1 = use `t`, 0 = reset to automatic timing (the default)
+    /// - `t`: Timestamp in system ticks (0 = process as quickly as possible)
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_event_t(slot: c_int, set: c_int, t: c_int) -> c_int;
+
     /// Send a note or event to SunVox
     ///
     /// # Parameters
@@ -202,6 +273,25 @@ extern "C" {
     /// Current line number
     pub fn sv_get_current_line(slot: c_int) -> c_int;
 
+    /// Get current playback line number with sub-line precision
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Current line in fixed point 27.5 format (line * 32 + 32nds of a line)
+    pub fn sv_get_current_line2(slot: c_int) -> c_int;
+
+    /// Get the current output level of a channel
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `channel`: 0 = left, 1 = right
+    ///
+    /// # Returns
+    /// Level from 0 to 255
+    pub fn sv_get_current_signal_level(slot: c_int, channel: c_int) -> c_int;
+
     /// Check if song has ended
     ///
     /// # Parameters
@@ -210,6 +300,859 @@ extern "C" {
     /// # Returns
     /// 0 = playing, 1 = stopped
     pub fn sv_end_of_song(slot: c_int) -> c_int;
+
+    /// Create a new module (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `module_type`: Module type name, e.g. "Generator" or "Reverb"
+    /// - `name`: Module name shown in the SunVox UI
+    /// - `x`, `y`: Position in the module view (normal working area: 0..1024)
+    /// - `z`: Layer number
+    ///
+    /// # Returns
+    /// New module number, negative on error
+    pub fn sv_new_module(
+        slot: c_int,
+        module_type: *const c_char,
+        name: *const c_char,
+        x: c_int,
+        y: c_int,
+        z: c_int,
+    ) -> c_int;
+
+    /// Remove a module (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_remove_module(slot: c_int, mod_num: c_int) -> c_int;
+
+    /// Connect the source module to the destination module (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `source`: Source module number
+    /// - `destination`: Destination module number
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_connect_module(slot: c_int, source: c_int, destination: c_int) -> c_int;
+
+    /// Disconnect the source module from the destination module (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `source`: Source module number
+    /// - `destination`: Destination module number
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_disconnect_module(slot: c_int, source: c_int, destination: c_int) -> c_int;
+
+    /// Load a module from a file
+    ///
+    /// Takes a `.sunsynth` module preset, or a sample (XI, WAV, AIFF, OGG,
+    /// MP3, FLAC), which gets a new Sampler module.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `file_name`: Path of the file
+    /// - `x`, `y`: Position in the module view
+    /// - `z`: Layer number
+    ///
+    /// # Returns
+    /// New module number, negative on error
+    pub fn sv_load_module(
+        slot: c_int,
+        file_name: *const c_char,
+        x: c_int,
+        y: c_int,
+        z: c_int,
+    ) -> c_int;
+
+    /// Load a module (or a sample into a new Sampler) from memory
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `data`: File contents
+    /// - `data_size`: Size of `data` in bytes
+    /// - `x`, `y`: Position in the module view
+    /// - `z`: Layer number
+    ///
+    /// # Returns
+    /// New module number, negative on error
+    pub fn sv_load_module_from_memory(
+        slot: c_int,
+        data: *mut c_void,
+        data_size: u32,
+        x: c_int,
+        y: c_int,
+        z: c_int,
+    ) -> c_int;
+
+    /// Load a sample or instrument file (WAV, AIFF, XI, OGG, ...) into a
+    /// Sampler module
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Sampler module number
+    /// - `file_name`: Path of the file
+    /// - `sample_slot`: Sample number to load into; -1 replaces the whole
+    ///   instrument
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_sampler_load(
+        slot: c_int,
+        mod_num: c_int,
+        file_name: *const c_char,
+        sample_slot: c_int,
+    ) -> c_int;
+
+    /// Load a sample or instrument from memory into a Sampler module
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Sampler module number
+    /// - `data`: File contents
+    /// - `data_size`: Size of `data` in bytes
+    /// - `sample_slot`: Sample number to load into; -1 replaces the whole
+    ///   instrument
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_sampler_load_from_memory(
+        slot: c_int,
+        mod_num: c_int,
+        data: *mut c_void,
+        data_size: u32,
+        sample_slot: c_int,
+    ) -> c_int;
+
+    /// Get or set a parameter of a sample in a Sampler module
+    ///
+    /// Parameters: 0 loop begin, 1 loop length, 2 loop type (0 none, 1
+    /// forward, 2 ping-pong), 3 loop release flag, 4 volume (0..64), 5 panning
+    /// (0..255, 128 = center), 6 finetune (-128..127), 7 relative note
+    /// (-128..127), 8 start position. Out-of-range values are ignored.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Sampler module number
+    /// - `sample_slot`: Sample number
+    /// - `par`: Parameter number
+    /// - `par_val`: New value (when setting)
+    /// - `set`: 1 to set, 0 to get
+    ///
+    /// # Returns
+    /// Value of the parameter before the call; 0 if the sample doesn't exist
+    pub fn sv_sampler_par(
+        slot: c_int,
+        mod_num: c_int,
+        sample_slot: c_int,
+        par: c_int,
+        par_val: c_int,
+        set: c_int,
+    ) -> c_int;
+
+    /// Load a project (SunVox, MOD, XM or MIDI file) into a MetaModule
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: MetaModule number
+    /// - `file_name`: Path of the file
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_metamodule_load(slot: c_int, mod_num: c_int, file_name: *const c_char) -> c_int;
+
+    /// Load a project from memory into a MetaModule
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: MetaModule number
+    /// - `data`: File contents
+    /// - `data_size`: Size of `data` in bytes
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_metamodule_load_from_memory(
+        slot: c_int,
+        mod_num: c_int,
+        data: *mut c_void,
+        data_size: u32,
+    ) -> c_int;
+
+    /// Load an OGG Vorbis file into a Vorbis Player module
+    ///
+    /// The whole file is copied into the module.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Vorbis Player number
+    /// - `file_name`: Path of the file
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_vplayer_load(slot: c_int, mod_num: c_int, file_name: *const c_char) -> c_int;
+
+    /// Load an OGG Vorbis file from memory into a Vorbis Player module
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Vorbis Player number
+    /// - `data`: File contents
+    /// - `data_size`: Size of `data` in bytes
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_vplayer_load_from_memory(
+        slot: c_int,
+        mod_num: c_int,
+        data: *mut c_void,
+        data_size: u32,
+    ) -> c_int;
+
+    /// Get the number of module slots (not the actual number of modules)
+    ///
+    /// A module slot may be empty; check SV_MODULE_FLAG_EXISTS in the flags.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Number of module slots, negative on error
+    pub fn sv_get_number_of_modules(slot: c_int) -> c_int;
+
+    /// Find a module by name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `name`: Module name
+    ///
+    /// # Returns
+    /// Module number, or -1 if not found
+    pub fn sv_find_module(slot: c_int, name: *const c_char) -> c_int;
+
+    /// Get module flags
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Combination of SV_MODULE_FLAG_* constants, plus the number of
+    /// input/output links packed at SV_MODULE_INPUTS_OFF/SV_MODULE_OUTPUTS_OFF
+    pub fn sv_get_module_flags(slot: c_int, mod_num: c_int) -> u32;
+
+    /// Get the input links of a module
+    ///
+    /// The array length is encoded in the module flags (SV_MODULE_INPUTS_MASK).
+    /// Some links may be empty (value = -1).
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Pointer to an array of module numbers, or NULL
+    pub fn sv_get_module_inputs(slot: c_int, mod_num: c_int) -> *mut c_int;
+
+    /// Get the output links of a module
+    ///
+    /// The array length is encoded in the module flags (SV_MODULE_OUTPUTS_MASK).
+    /// Some links may be empty (value = -1).
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Pointer to an array of module numbers, or NULL
+    pub fn sv_get_module_outputs(slot: c_int, mod_num: c_int) -> *mut c_int;
+
+    /// Get the module type name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Null-terminated type name owned by SunVox, or NULL
+    pub fn sv_get_module_type(slot: c_int, mod_num: c_int) -> *const c_char;
+
+    /// Get the module name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Null-terminated name owned by SunVox, or NULL
+    pub fn sv_get_module_name(slot: c_int, mod_num: c_int) -> *const c_char;
+
+    /// Set the module name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `name`: New module name
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_module_name(slot: c_int, mod_num: c_int, name: *const c_char) -> c_int;
+
+    /// Get module XY coordinates
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Packed coordinates: (x & 0xFFFF) | ((y & 0xFFFF) << 16), both signed 16-bit
+    pub fn sv_get_module_xy(slot: c_int, mod_num: c_int) -> u32;
+
+    /// Set module XY coordinates
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `x`, `y`: New position (normal working area: 0..1024)
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_module_xy(slot: c_int, mod_num: c_int, x: c_int, y: c_int) -> c_int;
+
+    /// Get module color
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Color in 0xBBGGRR format
+    pub fn sv_get_module_color(slot: c_int, mod_num: c_int) -> c_int;
+
+    /// Set module color
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `color`: Color in 0xBBGGRR format
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_module_color(slot: c_int, mod_num: c_int, color: c_int) -> c_int;
+
+    /// Get the relative note and finetune of a module
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// `(finetune & 0xFFFF) | ((relative_note & 0xFFFF) << 16)`, both signed
+    /// 16-bit (the `SV_GET_MODULE_FINETUNE` macro); 0 if the module doesn't
+    /// exist
+    pub fn sv_get_module_finetune(slot: c_int, mod_num: c_int) -> u32;
+
+    /// Change the finetune of a module immediately
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `finetune`: -256..256 (256 is one semitone up)
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_module_finetune(slot: c_int, mod_num: c_int, finetune: c_int) -> c_int;
+
+    /// Change the relative note of a module immediately
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `relative_note`: Transposition in semitones
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_module_relnote(slot: c_int, mod_num: c_int, relative_note: c_int) -> c_int;
+
+    /// Read the latest output of a module channel (its oscilloscope)
+    ///
+    /// The samples end at the current system time, so the data follows the
+    /// wall clock rather than the last rendered buffer.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `channel`: Output channel (input channel for the Output module)
+    /// - `dest_buf`: Destination for samples (-32768..32767)
+    /// - `samples_to_read`: Number of samples to read
+    ///
+    /// # Returns
+    /// Number of samples received (0 if the module or channel doesn't exist)
+    pub fn sv_get_module_scope2(
+        slot: c_int,
+        mod_num: c_int,
+        channel: c_int,
+        dest_buf: *mut i16,
+        samples_to_read: u32,
+    ) -> u32;
+
+    /// Read or write a curve of a module
+    ///
+    /// Curves (Y = CURVE[X]):
+    /// - MultiSynth 0: note (0..127) to velocity (0..1), 128 items
+    /// - MultiSynth 1: velocity (0..256) to velocity (0..1), 257 items
+    /// - MultiSynth 2: note (0..127) to pitch (0..1), 128 items
+    /// - WaveShaper 0: input (0..255) to output (0..1), 256 items
+    /// - MultiCtl 0: input (0..256) to output (0..1), 257 items
+    /// - Analog Generator, Generator 0: drawn waveform (0..31), -1..1, 32 items
+    /// - FMX 0: custom waveform (0..255), -1..1, 256 items
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `curve_num`: Curve number
+    /// - `data`: Destination or source buffer
+    /// - `len`: Number of items to read or write (0 for the whole curve)
+    /// - `w`: 0 to read, 1 to write
+    ///
+    /// # Returns
+    /// Number of items processed
+    pub fn sv_module_curve(
+        slot: c_int,
+        mod_num: c_int,
+        curve_num: c_int,
+        data: *mut f32,
+        len: c_int,
+        w: c_int,
+    ) -> c_int;
+
+    /// Get the number of controllers of a module
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    ///
+    /// # Returns
+    /// Number of controllers
+    pub fn sv_get_number_of_module_ctls(slot: c_int, mod_num: c_int) -> c_int;
+
+    /// Get the name of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    ///
+    /// # Returns
+    /// Null-terminated name owned by SunVox, or NULL
+    pub fn sv_get_module_ctl_name(slot: c_int, mod_num: c_int, ctl_num: c_int) -> *const c_char;
+
+    /// Get the value of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    /// - `scaled`: 0 = real value as stored in the controller;
+    ///   1 = scaled 0x0000..0x8000 (pattern XXYY column format) for normal
+    ///   controllers, real value for selectors; 2 = value displayed in the UI
+    ///
+    /// # Returns
+    /// Controller value
+    pub fn sv_get_module_ctl_value(
+        slot: c_int,
+        mod_num: c_int,
+        ctl_num: c_int,
+        scaled: c_int,
+    ) -> c_int;
+
+    /// Send a value to a module controller (uses sv_send_event internally)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    /// - `val`: New value
+    /// - `scaled`: Value format, same as sv_get_module_ctl_value
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_module_ctl_value(
+        slot: c_int,
+        mod_num: c_int,
+        ctl_num: c_int,
+        val: c_int,
+        scaled: c_int,
+    ) -> c_int;
+
+    /// Get the minimum value of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    /// - `scaled`: Value format, same as sv_get_module_ctl_value
+    ///
+    /// # Returns
+    /// Minimum value
+    pub fn sv_get_module_ctl_min(
+        slot: c_int,
+        mod_num: c_int,
+        ctl_num: c_int,
+        scaled: c_int,
+    ) -> c_int;
+
+    /// Get the maximum value of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    /// - `scaled`: Value format, same as sv_get_module_ctl_value
+    ///
+    /// # Returns
+    /// Maximum value
+    pub fn sv_get_module_ctl_max(
+        slot: c_int,
+        mod_num: c_int,
+        ctl_num: c_int,
+        scaled: c_int,
+    ) -> c_int;
+
+    /// Get the display value offset of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    ///
+    /// # Returns
+    /// Offset added to the real value for display
+    pub fn sv_get_module_ctl_offset(slot: c_int, mod_num: c_int, ctl_num: c_int) -> c_int;
+
+    /// Get the type of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    ///
+    /// # Returns
+    /// 0 = normal (scaled), 1 = selector (enum)
+    pub fn sv_get_module_ctl_type(slot: c_int, mod_num: c_int, ctl_num: c_int) -> c_int;
+
+    /// Get the UI group of a module controller
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `mod_num`: Module number
+    /// - `ctl_num`: Controller number (from 0)
+    ///
+    /// # Returns
+    /// Group number
+    pub fn sv_get_module_ctl_group(slot: c_int, mod_num: c_int, ctl_num: c_int) -> c_int;
+
+    /// Save the project to a file
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `name`: Path to the .sunvox file
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_save(slot: c_int, name: *const c_char) -> c_int;
+
+    /// Get the project name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Null-terminated name owned by SunVox, or NULL
+    pub fn sv_get_song_name(slot: c_int) -> *const c_char;
+
+    /// Set the project name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `name`: New project name
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_song_name(slot: c_int, name: *const c_char) -> c_int;
+
+    /// Get the project tempo
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Beats per minute
+    pub fn sv_get_song_bpm(slot: c_int) -> c_int;
+
+    /// Get the project speed
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Ticks per line
+    pub fn sv_get_song_tpl(slot: c_int) -> c_int;
+
+    /// Get the project length in frames
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Length in frames at the current sample rate
+    pub fn sv_get_song_length_frames(slot: c_int) -> u32;
+
+    /// Get the project length in lines
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Length in lines
+    pub fn sv_get_song_length_lines(slot: c_int) -> u32;
+
+    /// Get the tempo or frame position at the start of each line
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `start_line`: First line to read (usually 0)
+    /// - `len`: Number of lines to read
+    /// - `dest`: Buffer of `len` values
+    /// - `flags`: SV_TIME_MAP_SPEED: dest[X] = BPM | (TPL << 16);
+    ///   SV_TIME_MAP_FRAMECNT: dest[X] = frame counter at line X
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_get_time_map(
+        slot: c_int,
+        start_line: c_int,
+        len: c_int,
+        dest: *mut u32,
+        flags: c_int,
+    ) -> c_int;
+
+    /// Create a new pattern (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `clone`: Number of the pattern to clone, or -1 for a new pattern
+    /// - `x`: Line number (horizontal position on the timeline)
+    /// - `y`: Vertical position on the timeline
+    /// - `tracks`: Number of tracks
+    /// - `lines`: Number of lines
+    /// - `icon_seed`: Seed for the generated pattern icon
+    /// - `name`: Pattern name
+    ///
+    /// # Returns
+    /// New pattern number, negative on error
+    pub fn sv_new_pattern(
+        slot: c_int,
+        clone: c_int,
+        x: c_int,
+        y: c_int,
+        tracks: c_int,
+        lines: c_int,
+        icon_seed: c_int,
+        name: *const c_char,
+    ) -> c_int;
+
+    /// Remove a pattern (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_remove_pattern(slot: c_int, pat_num: c_int) -> c_int;
+
+    /// Get the number of pattern slots (not the actual number of patterns)
+    ///
+    /// A pattern slot is empty if sv_get_pattern_lines returns 0 for it.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    ///
+    /// # Returns
+    /// Number of pattern slots
+    pub fn sv_get_number_of_patterns(slot: c_int) -> c_int;
+
+    /// Find a pattern by name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `name`: Pattern name
+    ///
+    /// # Returns
+    /// Pattern number, or -1 if not found
+    pub fn sv_find_pattern(slot: c_int, name: *const c_char) -> c_int;
+
+    /// Get the pattern position on the timeline (line number)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// Line number of the pattern start
+    pub fn sv_get_pattern_x(slot: c_int, pat_num: c_int) -> c_int;
+
+    /// Get the vertical pattern position on the timeline
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// Vertical position
+    pub fn sv_get_pattern_y(slot: c_int, pat_num: c_int) -> c_int;
+
+    /// Set the pattern position on the timeline (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    /// - `x`: Line number
+    /// - `y`: Vertical position
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_pattern_xy(slot: c_int, pat_num: c_int, x: c_int, y: c_int) -> c_int;
+
+    /// Get the number of pattern tracks
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// Number of tracks
+    pub fn sv_get_pattern_tracks(slot: c_int, pat_num: c_int) -> c_int;
+
+    /// Get the number of pattern lines
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// Number of lines (0 if the pattern slot is empty)
+    pub fn sv_get_pattern_lines(slot: c_int, pat_num: c_int) -> c_int;
+
+    /// Resize a pattern (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    /// - `tracks`: New number of tracks
+    /// - `lines`: New number of lines
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_pattern_size(slot: c_int, pat_num: c_int, tracks: c_int, lines: c_int) -> c_int;
+
+    /// Get the pattern name
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// Null-terminated name owned by SunVox, or NULL
+    pub fn sv_get_pattern_name(slot: c_int, pat_num: c_int) -> *const c_char;
+
+    /// Set the pattern name (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    /// - `name`: New pattern name
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_pattern_name(slot: c_int, pat_num: c_int, name: *const c_char) -> c_int;
+
+    /// Get the pattern event buffer (for reading and writing)
+    ///
+    /// Events are stored line by line: the cell for (line, track) is at
+    /// index `line * tracks + track`.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    ///
+    /// # Returns
+    /// Pointer to `tracks * lines` events, or NULL
+    pub fn sv_get_pattern_data(slot: c_int, pat_num: c_int) -> *mut SunvoxNote;
+
+    /// Write a pattern event to the cell at the given line and track
+    ///
+    /// Only non-negative field values are written.
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    /// - `track`: Track number
+    /// - `line`: Line number
+    /// - `nn`, `vv`, `mm`, `ccee`, `xxyy`: Same as the SunvoxNote fields
+    ///
+    /// # Returns
+    /// 0 on success, negative on error
+    pub fn sv_set_pattern_event(
+        slot: c_int,
+        pat_num: c_int,
+        track: c_int,
+        line: c_int,
+        nn: c_int,
+        vv: c_int,
+        mm: c_int,
+        ccee: c_int,
+        xxyy: c_int,
+    ) -> c_int;
+
+    /// Read one field of a pattern event
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    /// - `track`: Track number
+    /// - `line`: Line number
+    /// - `column`: 0 = NN, 1 = VV, 2 = MM, 3 = CCEE, 4 = XXYY
+    ///
+    /// # Returns
+    /// Field value, negative on error
+    pub fn sv_get_pattern_event(
+        slot: c_int,
+        pat_num: c_int,
+        track: c_int,
+        line: c_int,
+        column: c_int,
+    ) -> c_int;
+
+    /// Mute or unmute a pattern (USE LOCK/UNLOCK)
+    ///
+    /// # Parameters
+    /// - `slot`: Slot number
+    /// - `pat_num`: Pattern number
+    /// - `mute`: 1 = mute, 0 = unmute, negative = query only
+    ///
+    /// # Returns
+    /// Previous state (1 = muted, 0 = unmuted), or -1 on error
+    pub fn sv_pattern_mute(slot: c_int, pat_num: c_int, mute: c_int) -> c_int;
 }
 
 #[cfg(test)]
@@ -220,6 +1163,9 @@ mod tests {
     fn test_sunvox_ffi_bindings() {
         // Comprehensive test of SunVox FFI bindings
         // All tests run in sequence to avoid parallel initialization conflicts
+        let _serial = crate::test_support::ENGINE_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
         unsafe {
             println!("\n=== Testing SunVox FFI Bindings ===\n");
 
@@ -235,13 +1181,15 @@ mod tests {
                     | SV_INIT_FLAG_ONE_THREAD,
             );
 
-            if result != 0 {
+            // sv_init returns the library version on success, negative on error
+            if result < 0 {
                 // In some environments (containers, CI), SunVox init may fail
                 // but FFI bindings are still valid if we got a return value
                 println!("  ⚠ sv_init returned error code: {} (0x{:x})", result, result);
                 println!("  ⚠ This may be expected in containerized environments");
                 println!("  ✓ FFI bindings are working (successfully called C function)");
                 println!("\n=== FFI bindings verified (initialization skipped) ===\n");
+                sv_deinit();
                 return;
             }
 
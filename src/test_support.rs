@@ -0,0 +1,79 @@
+// Shared helpers for tests that need a running SunVox engine
+
+use crate::sunvox_ffi::*;
+use std::sync::Mutex;
+
+/// SunVox is a process-wide singleton, so engine tests must not overlap
+pub static ENGINE_LOCK: Mutex<()> = Mutex::new(());
+
+const TEST_SLOT: i32 = 0;
+
+/// Closes the slot and shuts SunVox down even if the test body panics
+struct EngineGuard;
+
+impl Drop for EngineGuard {
+    fn drop(&mut self) {
+        unsafe {
+            sv_close_slot(TEST_SLOT);
+            sv_deinit();
+        }
+    }
+}
+
+/// Run `f` with SunVox initialized offline at 44.1 kHz and an empty slot open
+///
+/// The library is linked into the tests, so failing to start it is a bug (most
+/// likely an earlier test that left it initialized) and panics rather than
+/// skipping the test.
+pub fn with_engine<F: FnOnce(i32)>(f: F) {
+    let _serial = ENGINE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    unsafe {
+        // sv_init returns the library version on success, negative on error
+        let result = sv_init(
+            std::ptr::null(),
+            44100,
+            2,
+            SV_INIT_FLAG_NO_DEBUG_OUTPUT
+                | SV_INIT_FLAG_OFFLINE
+                | SV_INIT_FLAG_AUDIO_FLOAT32
+                | SV_INIT_FLAG_ONE_THREAD,
+        );
+        if result < 0 {
+            sv_deinit();
+            panic!("sv_init returned error code {}", result);
+        }
+
+        let result = sv_open_slot(TEST_SLOT);
+        if result != 0 {
+            sv_deinit();
+            panic!("sv_open_slot returned error code {}", result);
+        }
+    }
+
+    let _engine = EngineGuard;
+    f(TEST_SLOT);
+}
+
+/// Path of a file in the bundled SunVox resources directory
+pub fn resource_path(name: &str) -> String {
+    format!(
+        "{}/sunvox_lib/sunvox_lib/resources/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    )
+}
+
+/// Render `frames` stereo frames of interleaved float audio
+pub fn render(frames: usize) -> Vec<f32> {
+    let mut buffer = vec![0.0f32; frames * 2];
+    unsafe {
+        sv_audio_callback(
+            buffer.as_mut_ptr() as *mut std::os::raw::c_void,
+            frames as i32,
+            0,
+            sv_get_ticks(),
+        );
+    }
+    buffer
+}
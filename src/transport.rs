@@ -0,0 +1,326 @@
+// Song position and loop region
+// Start line, loop region and jumps for the project in a slot
+//
+// The loop wraps with the 0x31 effect ("jump to line XXYY") sent to the
+// virtual pattern while the last line of the region plays. The engine applies
+// it at the line boundary, as seamlessly as its own song loop, and before its
+// end-of-project check, so the region also wraps where autostop would stop
+// the song. Other jumps use `sv_rewind`. The end of the region is found on
+// the plugin's `Playhead`, which follows the engine whatever moves the song,
+// and the block is split half a line before it to send the jump.
+//
+// What happens at the end of the project is set with an `EndOfSong` policy,
+// which also configures autostop.
+
+use crate::automation::{is_playing, Playhead};
+use crate::error::Result;
+use crate::event_timing::send_event;
+use crate::project::{play, rewind, set_autostop, song_length_lines};
+use crate::sunvox_ffi::*;
+
+/// Pattern effect 0x31: jump to line XXYY when the current line ends
+pub const EFFECT_JUMP: u16 = 0x31;
+
+/// Pattern effect 0x32: how 0x31 counts lines (0 = absolute)
+pub const EFFECT_JUMP_MODE: u16 = 0x32;
+
+/// Jump to a line of the timeline when the line being played ends
+///
+/// The jump mode is reset to absolute line numbers first, as the project may
+/// have changed it.
+pub fn jump_after_line(slot: i32, line: i32) -> Result<()> {
+    for (ctl, ctl_val) in [(EFFECT_JUMP_MODE, 0), (EFFECT_JUMP, line.clamp(0, 0xFFFF))] {
+        let event = SunvoxNote {
+            ctl,
+            ctl_val: ctl_val as u16,
+            ..Default::default()
+        };
+        send_event(slot, 0, event)?;
+    }
+    Ok(())
+}
+
+/// Lines `start..end` of the timeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopRegion {
+    pub start: i32,
+    /// First line after the region
+    pub end: i32,
+}
+
+impl LoopRegion {
+    /// A region of at least one line, starting at line 0 or later
+    pub fn new(start: i32, end: i32) -> Option<Self> {
+        (start >= 0 && end > start).then_some(Self { start, end })
+    }
+
+    pub fn lines(&self) -> i32 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, line: i32) -> bool {
+        (self.start..self.end).contains(&line)
+    }
+}
+
+/// What playback does at the end of the project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EndOfSong {
+    /// Stop; the plugin goes quiet once the modules have faded out
+    Stop,
+    /// Continue from the project's restart position (autostop off)
+    #[default]
+    LoopToStart,
+    /// Continue from a line of the timeline
+    LoopToMarker(i32),
+    /// Stop, but keep the plugin running for live input
+    HoldSilence,
+}
+
+impl EndOfSong {
+    /// Whether SunVox has to stop at the end of the project
+    pub fn autostop(&self) -> bool {
+        *self != EndOfSong::LoopToStart
+    }
+}
+
+/// Start line, loop region and end-of-song policy of the song
+#[derive(Debug, Clone, Default)]
+pub struct SongPosition {
+    /// Line `play_from_start` begins at
+    pub start_line: i32,
+    pub loop_region: Option<LoopRegion>,
+    end_of_song: EndOfSong,
+    /// Line seen by the last update
+    last_line: Option<i32>,
+}
+
+impl SongPosition {
+    pub fn new(start_line: i32, loop_region: Option<LoopRegion>) -> Self {
+        Self {
+            start_line,
+            loop_region,
+            end_of_song: EndOfSong::default(),
+            last_line: None,
+        }
+    }
+
+    pub fn end_of_song(&self) -> EndOfSong {
+        self.end_of_song
+    }
+
+    /// Set the end-of-song policy and the autostop it needs
+    pub fn set_end_of_song(&mut self, slot: i32, policy: EndOfSong) -> Result<()> {
+        set_autostop(slot, policy.autostop())?;
+        self.end_of_song = policy;
+        Ok(())
+    }
+
+    /// Start playback at the start line
+    pub fn play_from_start(&mut self, slot: i32) -> Result<()> {
+        self.jump_to(slot, self.start_line)?;
+        if !is_playing(slot) {
+            play(slot)?;
+        }
+        Ok(())
+    }
+
+    /// Move the playhead to `line` right away
+    pub fn jump_to(&mut self, slot: i32, line: i32) -> Result<()> {
+        rewind(slot, line)?;
+        self.last_line = Some(line.max(0));
+        Ok(())
+    }
+
+    /// Find the frame of the block at which `cue` has to be called, if the
+    /// end of the loop region (or of the project, looping to the marker)
+    /// comes up in it; call before rendering each block of `frames`
+    ///
+    /// An end the song is already past is made up for at once with a rewind.
+    pub fn update(
+        &mut self,
+        slot: i32,
+        playhead: &mut Playhead,
+        frames: u32,
+    ) -> Result<Option<u32>> {
+        let line = playhead.position_at(0).map(|p| p.line);
+        let last = std::mem::replace(&mut self.last_line, line);
+        let Some(region) = self.region(slot) else {
+            return Ok(None);
+        };
+        let was_inside = last.is_some_and(|l| region.contains(l));
+
+        let Some(line) = line else {
+            // Autostop ended the song at the end of the region (or of the
+            // project, looping to the marker)
+            if was_inside && last >= Some(region.end - 1) {
+                self.jump_to(slot, region.start)?;
+                play(slot)?;
+                playhead.jump(region.start);
+            }
+            return Ok(None);
+        };
+        if playhead.is_jump_pending() {
+            return Ok(None);
+        }
+
+        if line >= region.end {
+            if was_inside {
+                // The last line ended before an update saw it
+                let target = region.start + (line - region.end) % region.lines();
+                self.jump_to(slot, target)?;
+                playhead.jump(target);
+            }
+            return Ok(None);
+        }
+        // Half a line early, as the playhead may be a little off
+        let cue = playhead.frames_until(region.end as f64 - 0.5);
+        Ok(cue.filter(|&cue| cue < frames))
+    }
+
+    /// Wrap at the end of the line playing `offset` frames into the block,
+    /// as found by `update`
+    ///
+    /// Returns the line the song jumps to.
+    pub fn cue(&mut self, slot: i32, playhead: &mut Playhead, offset: u32) -> Result<Option<i32>> {
+        let Some(region) = self.region(slot) else {
+            return Ok(None);
+        };
+        jump_after_line(slot, region.start)?;
+        playhead.jump_after_line(offset, region.start);
+        Ok(Some(region.start))
+    }
+
+    /// The loop region, or the marker loop at the end of the project
+    fn region(&self, slot: i32) -> Option<LoopRegion> {
+        let marker_loop = match self.end_of_song {
+            EndOfSong::LoopToMarker(marker) => {
+                LoopRegion::new(marker, song_length_lines(slot) as i32)
+            }
+            _ => None,
+        };
+        self.loop_region.or(marker_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{controller_value, CtlScale};
+    use crate::event_timing::{render_block_split, ticks, BlockTime};
+    use crate::module_graph::{clear_modules, new_module};
+    use crate::patterns::{clear_patterns, new_pattern, set_pattern_event};
+    use crate::project::{apply_pending_events, set_autostop, set_song_speed};
+    use crate::test_support::{render, with_engine};
+
+    /// Frames per line at 125 BPM and 6 ticks per line
+    const LINE: usize = 5292;
+
+    #[test]
+    fn test_loop_region_wraps() {
+        assert!(LoopRegion::new(4, 4).is_none());
+        assert_eq!(LoopRegion::new(2, 6).map(|r| r.lines()), Some(4));
+
+        with_engine(|slot| {
+            // Every line sets the generator volume to twice its number, so
+            // the volume tells which line played last
+            clear_patterns(slot).unwrap();
+            clear_modules(slot).unwrap();
+            set_song_speed(slot, 125, 6).unwrap();
+            apply_pending_events();
+            let marker = new_module(slot, "Generator", "Marker", 0, 0, 0).unwrap();
+            let pattern = new_pattern(slot, "Lines", 0, 0, 1, 16).unwrap();
+            for line in 0..16 {
+                let event = SunvoxNote {
+                    module: marker as u16 + 1,
+                    ctl: 0x0100,
+                    ctl_val: (line * 0x100) as u16,
+                    ..Default::default()
+                };
+                set_pattern_event(slot, pattern, 0, line, event).unwrap();
+            }
+            let played = || controller_value(slot, marker, 0, CtlScale::Real) / 2;
+
+            // Plays `lines` lines from the start line in blocks of `block`
+            // frames through `update` and `cue`, checking after every block
+            // that the last line played is the one `region` wraps to at that
+            // frame
+            let play_lines = |position: &mut SongPosition, region: LoopRegion, lines: usize| {
+                for block in [441, 2048, 4096] {
+                    let mut playhead = Playhead::new(44100);
+                    let mut buffer = vec![0.0f32; block * 2];
+                    position.play_from_start(slot).unwrap();
+                    playhead.jump(position.start_line);
+                    for frame in (block..lines * LINE).step_by(block) {
+                        playhead.start_block(slot);
+                        let cue = position.update(slot, &mut playhead, block as u32).unwrap();
+                        let time = BlockTime::new(ticks(), 44100);
+                        render_block_split(slot, &mut buffer, 2, time, &[], cue, || {
+                            let offset = cue.unwrap_or(0);
+                            position.cue(slot, &mut playhead, offset).map(|_| ())
+                        })
+                        .unwrap();
+                        playhead.advance(block as u32);
+
+                        // Lines start on a tick, which may round by a frame
+                        if (frame % LINE).min(LINE - frame % LINE) < 32 {
+                            continue;
+                        }
+                        let mut line = position.start_line + (frame / LINE) as i32;
+                        if line >= region.end {
+                            line = region.start + (line - region.end) % region.lines();
+                        }
+                        assert_eq!(played(), line, "block {} frame {}", block, frame);
+                    }
+                    unsafe {
+                        sv_stop(slot);
+                        sv_stop(slot);
+                    }
+                }
+            };
+
+            let region = LoopRegion::new(2, 6).unwrap();
+            let mut position = SongPosition::new(3, Some(region));
+            play_lines(&mut position, region, 10);
+
+            // At the end of the project the region wraps instead of stopping
+            set_autostop(slot, true).unwrap();
+            let region = LoopRegion::new(12, 16).unwrap();
+            let mut position = SongPosition::new(12, Some(region));
+            play_lines(&mut position, region, 7);
+
+            // Looping to a marker at the end of the project
+            let mut position = SongPosition::new(13, None);
+            position
+                .set_end_of_song(slot, EndOfSong::LoopToMarker(10))
+                .unwrap();
+            play_lines(&mut position, LoopRegion::new(10, 16).unwrap(), 9);
+
+            // Stopping
+            position.set_end_of_song(slot, EndOfSong::Stop).unwrap();
+            position.play_from_start(slot).unwrap();
+            render(4 * LINE);
+            assert!(!is_playing(slot));
+
+            // A wrap the updates missed is made up for with a rewind
+            let mut position = SongPosition::new(4, LoopRegion::new(0, 6));
+            let mut playhead = Playhead::new(44100);
+            position.play_from_start(slot).unwrap();
+            playhead.jump(4);
+            for update in [true, false, false, false, true] {
+                playhead.start_block(slot);
+                if update {
+                    assert_eq!(position.update(slot, &mut playhead, 100).unwrap(), None);
+                }
+                render(if update { 100 } else { LINE });
+                playhead.advance(if update { 100 } else { LINE as u32 });
+            }
+            assert_eq!(played(), 1);
+            assert_eq!(playhead.position_at(0).unwrap().line, 1);
+            unsafe {
+                sv_stop(slot);
+                sv_stop(slot);
+            }
+        });
+    }
+}
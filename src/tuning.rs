@@ -0,0 +1,256 @@
+// Module tuning API
+// Finetune and relative note of modules, and a global transpose on top of them
+//
+// Every module shifts the notes it receives by its relative note (semitones)
+// and finetune (1/256 of a semitone). The values take effect on the next
+// note; notes already playing keep their pitch. `GlobalTuning` remembers the
+// project's own tuning of the modules that take notes and offsets them all,
+// which is how the plugin's transpose, fine-tune and master tune work.
+
+use crate::error::{check, Result, SunVoxError};
+use crate::module_graph::{module_exists, ModuleGraph};
+use crate::sunvox_ffi::*;
+use std::ops::RangeInclusive;
+
+/// Finetune units in a semitone
+pub const FINETUNE_PER_SEMITONE: i32 = 256;
+
+pub const FINETUNE_RANGE: RangeInclusive<i32> = -256..=256;
+
+pub const RELATIVE_NOTE_RANGE: RangeInclusive<i32> = -128..=128;
+
+/// Concert pitch SunVox is tuned to, in Hz
+pub const STANDARD_A4: f32 = 440.0;
+
+/// Relative note and finetune of a module
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ModuleTuning {
+    /// 1/256 of a semitone, -256..256
+    pub finetune: i32,
+    /// Semitones
+    pub relative_note: i32,
+}
+
+impl ModuleTuning {
+    /// Unpack the value of `sv_get_module_finetune` (the
+    /// `SV_GET_MODULE_FINETUNE` macro)
+    pub fn from_packed(packed: u32) -> Self {
+        Self {
+            finetune: (packed & 0xFFFF) as u16 as i16 as i32,
+            relative_note: (packed >> 16) as u16 as i16 as i32,
+        }
+    }
+
+    /// The nearest tuning to a shift in cents, with the finetune kept
+    /// within half a semitone
+    pub fn from_cents(cents: f32) -> Self {
+        let units = (cents * FINETUNE_PER_SEMITONE as f32 / 100.0).round() as i32;
+        Self::from_finetune_units(units)
+    }
+
+    fn from_finetune_units(units: i32) -> Self {
+        let relative_note = (units as f32 / FINETUNE_PER_SEMITONE as f32).round() as i32;
+        Self {
+            finetune: units - relative_note * FINETUNE_PER_SEMITONE,
+            relative_note,
+        }
+    }
+
+    fn finetune_units(self) -> i32 {
+        self.relative_note * FINETUNE_PER_SEMITONE + self.finetune
+    }
+
+    pub fn cents(self) -> f32 {
+        self.finetune_units() as f32 * 100.0 / FINETUNE_PER_SEMITONE as f32
+    }
+
+    /// This tuning shifted by `offset`; the relative note is clamped to
+    /// `RELATIVE_NOTE_RANGE`
+    pub fn offset_by(self, offset: ModuleTuning) -> Self {
+        let mut tuning = Self::from_finetune_units(self.finetune_units() + offset.finetune_units());
+        tuning.relative_note = tuning
+            .relative_note
+            .clamp(*RELATIVE_NOTE_RANGE.start(), *RELATIVE_NOTE_RANGE.end());
+        tuning
+    }
+}
+
+/// Shift in cents that tunes A4 to `a4_hz` (-31.8 for A = 432 Hz)
+pub fn master_tune_cents(a4_hz: f32) -> f32 {
+    1200.0 * (a4_hz / STANDARD_A4).log2()
+}
+
+/// Relative note and finetune of a module (zero if it doesn't exist)
+pub fn module_tuning(slot: i32, module: i32) -> ModuleTuning {
+    ModuleTuning::from_packed(unsafe { sv_get_module_finetune(slot, module) })
+}
+
+pub fn set_module_finetune(slot: i32, module: i32, finetune: i32) -> Result<()> {
+    if !FINETUNE_RANGE.contains(&finetune) {
+        return Err(SunVoxError::InvalidValue(format!(
+            "finetune {} for module {}",
+            finetune, module
+        )));
+    }
+    check("sv_set_module_finetune", unsafe {
+        sv_set_module_finetune(slot, module, finetune)
+    })?;
+    Ok(())
+}
+
+pub fn set_module_relnote(slot: i32, module: i32, relative_note: i32) -> Result<()> {
+    if !RELATIVE_NOTE_RANGE.contains(&relative_note) {
+        return Err(SunVoxError::InvalidValue(format!(
+            "relative note {} for module {}",
+            relative_note, module
+        )));
+    }
+    check("sv_set_module_relnote", unsafe {
+        sv_set_module_relnote(slot, module, relative_note)
+    })?;
+    Ok(())
+}
+
+pub fn set_module_tuning(slot: i32, module: i32, tuning: ModuleTuning) -> Result<()> {
+    set_module_finetune(slot, module, tuning.finetune)?;
+    set_module_relnote(slot, module, tuning.relative_note)
+}
+
+/// An offset applied to the tuning of every module that takes notes
+///
+/// Notes pass through a chain like MultiSynth -> Generator with each module
+/// shifting them, so only generators that get no notes from another
+/// generator are offset.
+#[derive(Debug, Clone, Default)]
+pub struct GlobalTuning {
+    /// Modules and their tuning in the project
+    base: Vec<(i32, ModuleTuning)>,
+    applied: ModuleTuning,
+}
+
+impl GlobalTuning {
+    /// Remember the tuning of the project in `slot`, with no offset applied
+    pub fn capture(slot: i32) -> Result<Self> {
+        let graph = ModuleGraph::read(slot)?;
+        let base = graph
+            .nodes
+            .iter()
+            .filter(|node| node.is_generator())
+            .filter(|node| {
+                !graph
+                    .inputs_of(node.id)
+                    .any(|input| graph.node(input).is_some_and(|n| n.is_generator()))
+            })
+            .map(|node| (node.id, module_tuning(slot, node.id)))
+            .collect();
+        Ok(Self {
+            base,
+            applied: ModuleTuning::default(),
+        })
+    }
+
+    /// Modules the offset applies to
+    pub fn modules(&self) -> impl Iterator<Item = i32> + '_ {
+        self.base.iter().map(|&(module, _)| module)
+    }
+
+    pub fn offset(&self) -> ModuleTuning {
+        self.applied
+    }
+
+    /// Tune every module to its project tuning plus `offset`; does nothing
+    /// if that offset is already applied
+    pub fn apply(&mut self, slot: i32, offset: ModuleTuning) -> Result<()> {
+        if offset == self.applied {
+            return Ok(());
+        }
+        for &(module, base) in &self.base {
+            if module_exists(slot, module) {
+                set_module_tuning(slot, module, base.offset_by(offset))?;
+            }
+        }
+        self.applied = offset;
+        Ok(())
+    }
+
+    /// Put back the project's own tuning
+    pub fn restore(&mut self, slot: i32) -> Result<()> {
+        self.apply(slot, ModuleTuning::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module_graph::{clear_modules, connect_modules, new_module, OUTPUT_MODULE};
+    use crate::test_support::with_engine;
+
+    #[test]
+    fn test_module_and_global_tuning() {
+        let tuning = ModuleTuning {
+            finetune: -3,
+            relative_note: -12,
+        };
+        let packed =
+            (tuning.finetune as u32 & 0xFFFF) | ((tuning.relative_note as u32 & 0xFFFF) << 16);
+        assert_eq!(ModuleTuning::from_packed(packed), tuning);
+        assert!((master_tune_cents(432.0) + 31.77).abs() < 0.01);
+        let a432 = ModuleTuning::from_cents(master_tune_cents(432.0));
+        assert_eq!((a432.relative_note, a432.finetune), (0, -81));
+        let up = ModuleTuning::from_cents(1230.0);
+        assert_eq!((up.relative_note, up.finetune), (12, 77));
+        assert_eq!(
+            tuning
+                .offset_by(ModuleTuning::from_cents(-20000.0))
+                .relative_note,
+            -128
+        );
+
+        with_engine(|slot| {
+            clear_modules(slot).unwrap();
+            let multisynth = new_module(slot, "MultiSynth", "Notes", 0, 0, 0).unwrap();
+            let generator = new_module(slot, "Generator", "Gen", 0, 0, 0).unwrap();
+            let sampler = new_module(slot, "Sampler", "Sampler", 0, 0, 0).unwrap();
+            let reverb = new_module(slot, "Reverb", "Reverb", 0, 0, 0).unwrap();
+            connect_modules(slot, multisynth, generator).unwrap();
+            connect_modules(slot, generator, reverb).unwrap();
+            connect_modules(slot, reverb, OUTPUT_MODULE).unwrap();
+            connect_modules(slot, sampler, OUTPUT_MODULE).unwrap();
+
+            set_module_tuning(slot, sampler, tuning).unwrap();
+            assert_eq!(module_tuning(slot, sampler), tuning);
+            assert!(set_module_finetune(slot, sampler, 300).is_err());
+            assert!(set_module_relnote(slot, sampler, -129).is_err());
+            assert_eq!(module_tuning(slot, sampler), tuning);
+
+            let mut global = GlobalTuning::capture(slot).unwrap();
+            assert_eq!(global.modules().collect::<Vec<_>>(), [multisynth, sampler]);
+            let offset = ModuleTuning {
+                finetune: 10,
+                relative_note: 2,
+            };
+            global.apply(slot, offset).unwrap();
+            global.apply(slot, offset).unwrap();
+            assert_eq!(global.offset(), offset);
+            assert_eq!(
+                module_tuning(slot, multisynth),
+                ModuleTuning {
+                    finetune: 10,
+                    relative_note: 2
+                }
+            );
+            assert_eq!(
+                module_tuning(slot, sampler),
+                ModuleTuning {
+                    finetune: 7,
+                    relative_note: -10
+                }
+            );
+            assert_eq!(module_tuning(slot, generator), ModuleTuning::default());
+
+            global.restore(slot).unwrap();
+            assert_eq!(module_tuning(slot, multisynth), ModuleTuning::default());
+            assert_eq!(module_tuning(slot, sampler), tuning);
+        });
+    }
+}
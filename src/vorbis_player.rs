@@ -0,0 +1,241 @@
+// Vorbis Player API
+// Loads OGG Vorbis files into Vorbis Player modules and attaches backing tracks
+//
+// A Vorbis Player keeps the whole compressed file in the module (and in the
+// saved project) and plays it from the start on every note. A backing track
+// is a Vorbis Player connected to the Output and started by a one-note
+// pattern at line 0, so it plays along with the song when the song is played
+// from the beginning.
+
+use crate::controllers::set_controller_real_value;
+use crate::error::{c_string, check, Result, SunVoxError};
+use crate::module_graph::{
+    connect_modules, expect_module_type, new_module, remove_module, OUTPUT_MODULE,
+};
+use crate::patterns::{new_pattern, pattern_info, patterns, remove_pattern, set_pattern_event};
+use crate::slot::SlotLock;
+use crate::sunvox_ffi::*;
+use std::os::raw::c_void;
+
+/// Module type of the Vorbis Player
+pub const VORBIS_PLAYER: &str = "Vorbis player";
+
+/// First bytes of an OGG file
+pub const OGG_SIGNATURE: &[u8; 4] = b"OggS";
+
+/// Vorbis Player controllers (real values)
+pub const VPLAYER_CTL_VOLUME: i32 = 0;
+/// 1: play at the recorded speed whatever the note
+pub const VPLAYER_CTL_ORIGINAL_SPEED: i32 = 1;
+pub const VPLAYER_CTL_FINETUNE: i32 = 2;
+pub const VPLAYER_CTL_TRANSPOSE: i32 = 3;
+pub const VPLAYER_CTL_INTERPOLATION: i32 = 4;
+pub const VPLAYER_CTL_POLYPHONY: i32 = 5;
+pub const VPLAYER_CTL_REPEAT: i32 = 6;
+/// 1: keep playing to the end of the file after a note off
+pub const VPLAYER_CTL_IGNORE_NOTE_OFF: i32 = 7;
+
+/// Note that starts a backing track (C5, the player's base note)
+const BACKING_TRACK_NOTE: u8 = 61;
+
+/// Load an OGG Vorbis file into a Vorbis Player
+pub fn load_vplayer_file(slot: i32, module: i32, path: &str) -> Result<()> {
+    expect_module_type(slot, module, VORBIS_PLAYER)?;
+    let path_c = c_string(path)?;
+    check("sv_vplayer_load", unsafe {
+        sv_vplayer_load(slot, module, path_c.as_ptr())
+    })?;
+    Ok(())
+}
+
+/// Load an OGG Vorbis file into a Vorbis Player from memory
+///
+/// SunVox stores whatever it is given, so data without the OGG signature is
+/// rejected here as `InvalidValue`.
+pub fn load_vplayer_bytes(slot: i32, module: i32, data: &[u8]) -> Result<()> {
+    expect_module_type(slot, module, VORBIS_PLAYER)?;
+    if !data.starts_with(OGG_SIGNATURE) {
+        return Err(SunVoxError::InvalidValue("not an OGG file".into()));
+    }
+    // SunVox only reads the data, but its signature takes a mutable pointer
+    check("sv_vplayer_load_from_memory", unsafe {
+        sv_vplayer_load_from_memory(
+            slot,
+            module,
+            data.as_ptr() as *mut c_void,
+            data.len() as u32,
+        )
+    })?;
+    Ok(())
+}
+
+/// Create a Vorbis Player playing an OGG file; returns its number
+///
+/// The module is removed again if the file can't be loaded.
+pub fn new_vplayer_from_file(slot: i32, name: &str, path: &str, x: i32, y: i32) -> Result<i32> {
+    let module = new_module(slot, VORBIS_PLAYER, name, x, y, 0)?;
+    load_vplayer_file(slot, module, path)
+        .inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+        .map(|_| module)
+}
+
+/// Create a Vorbis Player playing an OGG file from memory; returns its number
+pub fn new_vplayer_from_bytes(slot: i32, name: &str, data: &[u8], x: i32, y: i32) -> Result<i32> {
+    let module = new_module(slot, VORBIS_PLAYER, name, x, y, 0)?;
+    load_vplayer_bytes(slot, module, data)
+        .inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+        .map(|_| module)
+}
+
+/// A Vorbis Player and the pattern that starts it with the song
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackingTrack {
+    pub module: i32,
+    pub pattern: i32,
+}
+
+impl BackingTrack {
+    /// Add an OGG file to the project as a backing track
+    ///
+    /// The player is connected to the Output, plays at the file's own speed
+    /// and ignores note offs; the pattern goes below every existing one.
+    pub fn attach(slot: i32, name: &str, data: &[u8]) -> Result<Self> {
+        let module = new_vplayer_from_bytes(slot, name, data, 256, 768)?;
+        Self::start_with_song(slot, name, module)
+    }
+
+    /// Add an OGG file on disk to the project as a backing track
+    pub fn attach_file(slot: i32, name: &str, path: &str) -> Result<Self> {
+        let module = new_vplayer_from_file(slot, name, path, 256, 768)?;
+        Self::start_with_song(slot, name, module)
+    }
+
+    /// Wire up a new player; it is removed again on failure
+    fn start_with_song(slot: i32, name: &str, module: i32) -> Result<Self> {
+        Self::add_start_pattern(slot, name, module).inspect_err(|_| {
+            let _ = remove_module(slot, module);
+        })
+    }
+
+    fn add_start_pattern(slot: i32, name: &str, module: i32) -> Result<Self> {
+        connect_modules(slot, module, OUTPUT_MODULE)?;
+        set_controller_real_value(slot, module, VPLAYER_CTL_ORIGINAL_SPEED, 1)?;
+        set_controller_real_value(slot, module, VPLAYER_CTL_IGNORE_NOTE_OFF, 1)?;
+
+        let y = {
+            let _lock = SlotLock::new(slot);
+            patterns(slot)
+                .into_iter()
+                .filter_map(|p| pattern_info(slot, p))
+                .map(|info| info.y + 32)
+                .max()
+                .unwrap_or(0)
+        };
+        let pattern = new_pattern(slot, name, 0, y, 1, 16)?;
+        let note = SunvoxNote {
+            note: BACKING_TRACK_NOTE,
+            vel: 129,
+            module: module as u16 + 1,
+            ..Default::default()
+        };
+        if let Err(e) = set_pattern_event(slot, pattern, 0, 0, note) {
+            let _ = remove_pattern(slot, pattern);
+            return Err(e);
+        }
+        Ok(Self { module, pattern })
+    }
+
+    /// Remove the player and its pattern from the project
+    pub fn detach(self, slot: i32) -> Result<()> {
+        remove_pattern(slot, self.pattern)?;
+        remove_module(slot, self.module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::{controller_value, CtlScale};
+    use crate::module_graph::{module_exists, module_inputs, module_type};
+    use crate::patterns::{pattern_event, pattern_exists};
+    use crate::project::{load_project, set_autostop};
+    use crate::scope::ScopeCapture;
+    use crate::test_support::{render, resource_path, with_engine};
+
+    #[test]
+    fn test_attach_backing_track() {
+        with_engine(|slot| {
+            load_project(slot, &resource_path("song01.sunvox")).unwrap();
+            let song_patterns = patterns(slot);
+            let lowest = song_patterns
+                .iter()
+                .filter_map(|&p| pattern_info(slot, p))
+                .map(|info| info.y)
+                .max()
+                .unwrap();
+
+            // Not an OGG file, not a Vorbis Player
+            let sampler = new_module(slot, "Sampler", "Sampler", 0, 0, 0).unwrap();
+            let ogg = std::fs::read(resource_path("drums.ogg")).unwrap();
+            assert!(matches!(
+                load_vplayer_bytes(slot, sampler, &ogg),
+                Err(SunVoxError::WrongModuleType { .. })
+            ));
+            assert!(matches!(
+                BackingTrack::attach(slot, "Junk", b"not an ogg file"),
+                Err(SunVoxError::InvalidValue(_))
+            ));
+            assert!(!module_exists(slot, sampler + 1));
+            remove_module(slot, sampler).unwrap();
+
+            let track =
+                BackingTrack::attach_file(slot, "Guide", &resource_path("drums.ogg")).unwrap();
+            assert_eq!(
+                module_type(slot, track.module).as_deref(),
+                Some(VORBIS_PLAYER)
+            );
+            assert!(module_inputs(slot, OUTPUT_MODULE).contains(&track.module));
+            let info = pattern_info(slot, track.pattern).unwrap();
+            assert_eq!((info.x, info.name.as_str()), (0, "Guide"));
+            assert!(info.y > lowest);
+            let note = pattern_event(slot, track.pattern, 0, 0).unwrap();
+            assert_eq!(note.module as i32, track.module + 1);
+
+            // The player starts with the song
+            render(64);
+            assert_eq!(
+                controller_value(
+                    slot,
+                    track.module,
+                    VPLAYER_CTL_IGNORE_NOTE_OFF,
+                    CtlScale::Real
+                ),
+                1
+            );
+            set_autostop(slot, true).unwrap();
+            let mut capture = ScopeCapture::new(4096);
+            let player = capture.add(track.module, 0);
+            unsafe {
+                sv_play_from_beginning(slot);
+            }
+            for _ in 0..4 {
+                render(1024);
+                capture.capture(slot, 1024);
+            }
+            assert!(player.peak(4096) > 0.01);
+            unsafe {
+                sv_stop(slot);
+            }
+            render(1024);
+
+            track.detach(slot).unwrap();
+            assert!(!pattern_exists(slot, track.pattern));
+            assert!(!module_exists(slot, track.module));
+            assert_eq!(patterns(slot), song_patterns);
+        });
+    }
+}